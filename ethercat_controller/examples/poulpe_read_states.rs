@@ -32,43 +32,18 @@ fn main() {
 
     std::thread::sleep(Duration::from_secs(1));
     // send switch on command
-    ec.set_pdo_register(id, &"controlword".into(), 0, vec![0b0111, 0]);
+    ec.set::<u16>(id, "controlword", 0, 0b0111).unwrap();
 
     std::thread::sleep(Duration::from_secs(1));
     // ec.set_pdo_register(id, &"controlword".into(), 0, vec![0b1111, 0]);
 
     loop {
-        let positions = ec
-            .get_pdo_registers(id, &"actual_position".into())
-            .unwrap()
-            .iter()
-            .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-            .collect::<Vec<f32>>();
-        let velocities = ec
-            .get_pdo_registers(id, &"actual_velocity".into())
-            .unwrap()
-            .iter()
-            .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-            .collect::<Vec<f32>>();
-        let axis = ec
-            .get_pdo_registers(id, &"actual_axis_position".into())
-            .unwrap()
-            .iter()
-            .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-            .collect::<Vec<f32>>();
-        let board_temperature = ec
-            .get_pdo_registers(id, &"board_temperatures".into())
-            .unwrap()
-            .iter()
-            .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-            .collect::<Vec<f32>>();
+        let positions = ec.get_all::<f32>(id, "actual_position").unwrap();
+        let velocities = ec.get_all::<f32>(id, "actual_velocity").unwrap();
+        let axis = ec.get_all::<f32>(id, "actual_axis_position").unwrap();
+        let board_temperature = ec.get_all::<f32>(id, "board_temperatures").unwrap();
 
-        let motor_temperature = ec
-            .get_pdo_registers(id, &"motor_temperatures".into())
-            .unwrap()
-            .iter()
-            .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-            .collect::<Vec<f32>>();
+        let motor_temperature = ec.get_all::<f32>(id, "motor_temperatures").unwrap();
         log::info!(
             "Pos: {:?}, \t Vel: {:?},\t Axis: {:?}, \t Board Temp: {:?}, \t Motor Temp: {:?}",
             positions,
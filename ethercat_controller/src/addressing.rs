@@ -0,0 +1,139 @@
+// Station alias addressing
+//
+// By default the slaves are configured by their position on the bus (SlaveAddr::ByPos) and the
+// position is used as the slave id, so re-routing a cable changes the ids.
+// With the `slaves` list of the configuration, the listed slaves are configured by their station
+// alias (SlaveAddr::ByAlias) and the id of each slave is its index in the list. The slaves found
+// on the bus but not listed are configured by their position and get the ids after the listed ones.
+
+use std::io;
+
+use ethercat::{Master, SlaveAddr, SlavePos};
+
+use crate::config::SlaveAddressConfig;
+
+// address of the slave with a given id
+#[derive(Debug, Clone, Copy)]
+pub struct SlaveAddress {
+    // position of the slave on the bus (used for the requests to the master)
+    pub position: u16,
+    // address used to configure the slave
+    pub addr: SlaveAddr,
+}
+
+// find the address of each slave, the returned list is indexed by slave id
+pub fn resolve_slave_addresses(
+    master: &Master,
+    slave_num: u32,
+    slaves: &[SlaveAddressConfig],
+) -> Result<Vec<SlaveAddress>, io::Error> {
+    if slaves.is_empty() {
+        return Ok((0..slave_num as u16)
+            .map(|position| SlaveAddress {
+                position,
+                addr: SlaveAddr::ByPos(position),
+            })
+            .collect());
+    }
+
+    // name and alias of the slaves by position
+    let mut found = vec![];
+    for position in 0..slave_num as u16 {
+        let info = master.get_slave_info(SlavePos::from(position))?;
+        found.push((info.name, info.alias));
+    }
+
+    let mut addresses: Vec<SlaveAddress> = vec![];
+    for (id, slave) in slaves.iter().enumerate() {
+        if slave.alias == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Slave {:?} | station alias 0 is not a valid alias",
+                    slave.name
+                ),
+            ));
+        }
+        if slaves[..id].iter().any(|s| s.alias == slave.alias) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Station alias {} configured more than once", slave.alias),
+            ));
+        }
+
+        let positions = (0..slave_num as u16)
+            .filter(|&p| found[p as usize].1 == slave.alias)
+            .collect::<Vec<_>>();
+        let position = match positions[..] {
+            [position] => position,
+            [] => {
+                log::error!(
+                    "Slave {:?} with station alias {} not found on the bus!",
+                    slave.name,
+                    slave.alias
+                );
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Slave {:?} not found", slave.name),
+                ));
+            }
+            _ => {
+                log::error!(
+                    "Slaves at positions {:?} have the same station alias {}!",
+                    positions,
+                    slave.alias
+                );
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Station alias {} is not unique on the bus", slave.alias),
+                ));
+            }
+        };
+
+        // make sure that the alias is set on the expected slave
+        let name = &found[position as usize].0;
+        if *name != slave.name {
+            log::error!(
+                "Slave with station alias {} is {:?}, expected {:?}!",
+                slave.alias,
+                name,
+                slave.name
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected slave {:?} with alias {}", name, slave.alias),
+            ));
+        }
+
+        log::info!(
+            "Slave {:?} with station alias {} at position {} | id {}",
+            slave.name,
+            slave.alias,
+            position,
+            id
+        );
+        addresses.push(SlaveAddress {
+            position,
+            addr: SlaveAddr::ByAlias(slave.alias, 0),
+        });
+    }
+
+    // the slaves that are not listed keep the position addressing
+    for position in 0..slave_num as u16 {
+        if addresses.iter().any(|a| a.position == position) {
+            continue;
+        }
+        log::warn!(
+            "Slave {:?} at position {} is not in the slaves list, configured by position | id {}",
+            found[position as usize].0,
+            position,
+            addresses.len()
+        );
+        addresses.push(SlaveAddress {
+            position,
+            addr: SlaveAddr::ByPos(position),
+        });
+    }
+
+    Ok(addresses)
+}
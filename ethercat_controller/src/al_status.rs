@@ -0,0 +1,284 @@
+// AL status code of the slaves
+//
+// When a slave refuses a state transition it sets the error indicator of its AL status register (0x0130)
+// and reports the reason in the AL status code register (0x0134) (see ETG.1000.6 table 11).
+
+use std::fmt;
+
+use ethercat::SlavePos;
+
+use crate::ethercat_patch::slave_reg_read;
+
+// AL status register (state + error indicator)
+const AL_STATUS_REGISTER: u16 = 0x0130;
+// AL status code register (0x0134), read together with the AL status register
+const AL_STATUS_CODE_OFFSET: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlStatusCode {
+    NoError,
+    UnspecifiedError,
+    NoMemory,
+    InvalidRequestedStateChange,
+    UnknownRequestedState,
+    BootstrapNotSupported,
+    NoValidFirmware,
+    InvalidMailboxConfigurationBootstrap,
+    InvalidMailboxConfigurationPreop,
+    InvalidSyncManagerConfiguration,
+    NoValidInputsAvailable,
+    NoValidOutputs,
+    SynchronizationError,
+    SyncManagerWatchdog,
+    InvalidSyncManagerTypes,
+    InvalidOutputConfiguration,
+    InvalidInputConfiguration,
+    InvalidWatchdogConfiguration,
+    SlaveNeedsColdStart,
+    SlaveNeedsInit,
+    SlaveNeedsPreop,
+    SlaveNeedsSafeop,
+    InvalidInputMapping,
+    InvalidOutputMapping,
+    InconsistentSettings,
+    FreerunNotSupported,
+    SynchronizationNotSupported,
+    FreerunNeeds3BufferMode,
+    BackgroundWatchdog,
+    NoValidInputsAndOutputs,
+    FatalSyncError,
+    NoSyncError,
+    InvalidDcSyncConfiguration,
+    InvalidDcLatchConfiguration,
+    PllError,
+    DcSyncIoError,
+    DcSyncTimeoutError,
+    DcInvalidSyncCycleTime,
+    DcSync0CycleTime,
+    DcSync1CycleTime,
+    MailboxAoe,
+    MailboxEoe,
+    MailboxCoe,
+    MailboxFoe,
+    MailboxSoe,
+    MailboxVoe,
+    EepromNoAccess,
+    EepromError,
+    SlaveRestartedLocally,
+    DeviceIdentificationValueUpdated,
+    ApplicationControllerAvailable,
+    // 0x8000 - 0xFFFF
+    VendorSpecific(u16),
+    Unknown(u16),
+}
+
+impl From<u16> for AlStatusCode {
+    fn from(code: u16) -> Self {
+        use AlStatusCode::*;
+        match code {
+            0x0000 => NoError,
+            0x0001 => UnspecifiedError,
+            0x0002 => NoMemory,
+            0x0011 => InvalidRequestedStateChange,
+            0x0012 => UnknownRequestedState,
+            0x0013 => BootstrapNotSupported,
+            0x0014 => NoValidFirmware,
+            0x0015 => InvalidMailboxConfigurationBootstrap,
+            0x0016 => InvalidMailboxConfigurationPreop,
+            0x0017 => InvalidSyncManagerConfiguration,
+            0x0018 => NoValidInputsAvailable,
+            0x0019 => NoValidOutputs,
+            0x001A => SynchronizationError,
+            0x001B => SyncManagerWatchdog,
+            0x001C => InvalidSyncManagerTypes,
+            0x001D => InvalidOutputConfiguration,
+            0x001E => InvalidInputConfiguration,
+            0x001F => InvalidWatchdogConfiguration,
+            0x0020 => SlaveNeedsColdStart,
+            0x0021 => SlaveNeedsInit,
+            0x0022 => SlaveNeedsPreop,
+            0x0023 => SlaveNeedsSafeop,
+            0x0024 => InvalidInputMapping,
+            0x0025 => InvalidOutputMapping,
+            0x0026 => InconsistentSettings,
+            0x0027 => FreerunNotSupported,
+            0x0028 => SynchronizationNotSupported,
+            0x0029 => FreerunNeeds3BufferMode,
+            0x002A => BackgroundWatchdog,
+            0x002B => NoValidInputsAndOutputs,
+            0x002C => FatalSyncError,
+            0x002D => NoSyncError,
+            0x0030 => InvalidDcSyncConfiguration,
+            0x0031 => InvalidDcLatchConfiguration,
+            0x0032 => PllError,
+            0x0033 => DcSyncIoError,
+            0x0034 => DcSyncTimeoutError,
+            0x0035 => DcInvalidSyncCycleTime,
+            0x0036 => DcSync0CycleTime,
+            0x0037 => DcSync1CycleTime,
+            0x0041 => MailboxAoe,
+            0x0042 => MailboxEoe,
+            0x0043 => MailboxCoe,
+            0x0044 => MailboxFoe,
+            0x0045 => MailboxSoe,
+            0x004F => MailboxVoe,
+            0x0050 => EepromNoAccess,
+            0x0051 => EepromError,
+            0x0060 => SlaveRestartedLocally,
+            0x0061 => DeviceIdentificationValueUpdated,
+            0x00F0 => ApplicationControllerAvailable,
+            0x8000..=0xFFFF => VendorSpecific(code),
+            _ => Unknown(code),
+        }
+    }
+}
+
+impl AlStatusCode {
+    pub fn code(&self) -> u16 {
+        use AlStatusCode::*;
+        match self {
+            NoError => 0x0000,
+            UnspecifiedError => 0x0001,
+            NoMemory => 0x0002,
+            InvalidRequestedStateChange => 0x0011,
+            UnknownRequestedState => 0x0012,
+            BootstrapNotSupported => 0x0013,
+            NoValidFirmware => 0x0014,
+            InvalidMailboxConfigurationBootstrap => 0x0015,
+            InvalidMailboxConfigurationPreop => 0x0016,
+            InvalidSyncManagerConfiguration => 0x0017,
+            NoValidInputsAvailable => 0x0018,
+            NoValidOutputs => 0x0019,
+            SynchronizationError => 0x001A,
+            SyncManagerWatchdog => 0x001B,
+            InvalidSyncManagerTypes => 0x001C,
+            InvalidOutputConfiguration => 0x001D,
+            InvalidInputConfiguration => 0x001E,
+            InvalidWatchdogConfiguration => 0x001F,
+            SlaveNeedsColdStart => 0x0020,
+            SlaveNeedsInit => 0x0021,
+            SlaveNeedsPreop => 0x0022,
+            SlaveNeedsSafeop => 0x0023,
+            InvalidInputMapping => 0x0024,
+            InvalidOutputMapping => 0x0025,
+            InconsistentSettings => 0x0026,
+            FreerunNotSupported => 0x0027,
+            SynchronizationNotSupported => 0x0028,
+            FreerunNeeds3BufferMode => 0x0029,
+            BackgroundWatchdog => 0x002A,
+            NoValidInputsAndOutputs => 0x002B,
+            FatalSyncError => 0x002C,
+            NoSyncError => 0x002D,
+            InvalidDcSyncConfiguration => 0x0030,
+            InvalidDcLatchConfiguration => 0x0031,
+            PllError => 0x0032,
+            DcSyncIoError => 0x0033,
+            DcSyncTimeoutError => 0x0034,
+            DcInvalidSyncCycleTime => 0x0035,
+            DcSync0CycleTime => 0x0036,
+            DcSync1CycleTime => 0x0037,
+            MailboxAoe => 0x0041,
+            MailboxEoe => 0x0042,
+            MailboxCoe => 0x0043,
+            MailboxFoe => 0x0044,
+            MailboxSoe => 0x0045,
+            MailboxVoe => 0x004F,
+            EepromNoAccess => 0x0050,
+            EepromError => 0x0051,
+            SlaveRestartedLocally => 0x0060,
+            DeviceIdentificationValueUpdated => 0x0061,
+            ApplicationControllerAvailable => 0x00F0,
+            VendorSpecific(code) | Unknown(code) => *code,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        use AlStatusCode::*;
+        match self {
+            NoError => "No error",
+            UnspecifiedError => "Unspecified error",
+            NoMemory => "No memory",
+            InvalidRequestedStateChange => "Invalid requested state change",
+            UnknownRequestedState => "Unknown requested state",
+            BootstrapNotSupported => "Bootstrap not supported",
+            NoValidFirmware => "No valid firmware",
+            InvalidMailboxConfigurationBootstrap => "Invalid mailbox configuration (bootstrap)",
+            InvalidMailboxConfigurationPreop => "Invalid mailbox configuration (preop)",
+            InvalidSyncManagerConfiguration => "Invalid sync manager configuration",
+            NoValidInputsAvailable => "No valid inputs available",
+            NoValidOutputs => "No valid outputs",
+            SynchronizationError => "Synchronization error",
+            SyncManagerWatchdog => "Sync manager watchdog",
+            InvalidSyncManagerTypes => "Invalid sync manager types",
+            InvalidOutputConfiguration => "Invalid output configuration",
+            InvalidInputConfiguration => "Invalid input configuration",
+            InvalidWatchdogConfiguration => "Invalid watchdog configuration",
+            SlaveNeedsColdStart => "Slave needs cold start",
+            SlaveNeedsInit => "Slave needs INIT",
+            SlaveNeedsPreop => "Slave needs PREOP",
+            SlaveNeedsSafeop => "Slave needs SAFEOP",
+            InvalidInputMapping => "Invalid input mapping",
+            InvalidOutputMapping => "Invalid output mapping",
+            InconsistentSettings => "Inconsistent settings",
+            FreerunNotSupported => "Freerun not supported",
+            SynchronizationNotSupported => "Synchronization not supported",
+            FreerunNeeds3BufferMode => "Freerun needs 3 buffer mode",
+            BackgroundWatchdog => "Background watchdog",
+            NoValidInputsAndOutputs => "No valid inputs and outputs",
+            FatalSyncError => "Fatal sync error",
+            NoSyncError => "No sync error",
+            InvalidDcSyncConfiguration => "Invalid DC SYNC configuration",
+            InvalidDcLatchConfiguration => "Invalid DC latch configuration",
+            PllError => "PLL error",
+            DcSyncIoError => "DC sync IO error",
+            DcSyncTimeoutError => "DC sync timeout error",
+            DcInvalidSyncCycleTime => "DC invalid sync cycle time",
+            DcSync0CycleTime => "DC SYNC0 cycle time",
+            DcSync1CycleTime => "DC SYNC1 cycle time",
+            MailboxAoe => "Mailbox AoE error",
+            MailboxEoe => "Mailbox EoE error",
+            MailboxCoe => "Mailbox CoE error",
+            MailboxFoe => "Mailbox FoE error",
+            MailboxSoe => "Mailbox SoE error",
+            MailboxVoe => "Mailbox VoE error",
+            EepromNoAccess => "EEPROM no access",
+            EepromError => "EEPROM error",
+            SlaveRestartedLocally => "Slave restarted locally",
+            DeviceIdentificationValueUpdated => "Device identification value updated",
+            ApplicationControllerAvailable => "Application controller available",
+            VendorSpecific(_) => "Vendor specific error",
+            Unknown(_) => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Display for AlStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04X} ({})", self.code(), self.description())
+    }
+}
+
+// AL status of a slave as read from its registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlStatus {
+    // current AL state (1 init, 2 preop, 3 boot, 4 safeop, 8 op)
+    pub state: u8,
+    // the slave refused the last requested state change
+    pub error: bool,
+    pub code: AlStatusCode,
+}
+
+// read the AL status and AL status code registers of the slave
+// must not be called from the EtherCAT loop thread (see slave_reg_read)
+pub fn read_al_status(master_id: u32, slave_pos: SlavePos) -> Result<AlStatus, ethercat::Error> {
+    let mut data = [0u8; AL_STATUS_CODE_OFFSET + 2];
+    slave_reg_read(master_id, slave_pos, AL_STATUS_REGISTER, &mut data)?;
+    Ok(AlStatus {
+        state: data[0] & 0x0F,
+        error: data[0] & 0x10 != 0,
+        code: AlStatusCode::from(u16::from_le_bytes([
+            data[AL_STATUS_CODE_OFFSET],
+            data[AL_STATUS_CODE_OFFSET + 1],
+        ])),
+    })
+}
@@ -0,0 +1,417 @@
+// Live process data inspector
+//
+// Attaches read only to the EtherCAT master of the running application (or opens the replay) from
+// the configuration file and shows the PDO entries of each slave decoded by their data type,
+// refreshing in place. The entries that changed since the previous refresh are highlighted. With
+// --json the process data is dumped once as JSON.
+//
+// The master is neither reserved nor activated: the process data is read from the domain of the
+// application (ex. the GRPC server), nothing is written to the bus. The inspector waits for the
+// application to configure the master (or the replay to be ready) at most --timeout ms.
+//
+// usage: pdo_inspector <config.yaml> [--slave <key>]... [--entry <name>]... [--period <ms>]
+//        [--timeout <ms>] [--json]
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use ethercat_controller::{
+    identity::find_slave,
+    pdo_value::{data_type_name, format_value},
+    process_image::ProcessImage,
+    Config, EtherCatController, SlaveKey,
+};
+
+const USAGE: &str = "usage: pdo_inspector <config.yaml> [--slave <key>]... [--entry <name>]... \
+[--period <ms>] [--timeout <ms>] [--json]";
+
+// domain of the application
+const DOMAIN_IDX: u32 = 0;
+
+// ANSI escape sequences
+const CLEAR_SCREEN: &str = "\x1b[2J";
+const CURSOR_HOME: &str = "\x1b[H";
+const CLEAR_TO_END: &str = "\x1b[J";
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+struct Options {
+    config: String,
+    // slaves to show (all if empty)
+    slaves: Vec<SlaveKey>,
+    // entries to show (substring of the entry name, all if empty)
+    entries: Vec<String>,
+    period: Duration,
+    // max time to wait for the master (or the replay) to be ready
+    timeout: Duration,
+    json: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut config = None;
+    let mut options = Options {
+        config: String::new(),
+        slaves: vec![],
+        entries: vec![],
+        period: Duration::from_millis(100),
+        timeout: Duration::from_secs(5),
+        json: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--slave" | "-s" => {
+                let key = args.next().ok_or("missing slave key")?;
+                options
+                    .slaves
+                    .push(key.parse().map_err(|e| format!("{}", e))?);
+            }
+            "--entry" | "-e" => options
+                .entries
+                .push(args.next().ok_or("missing entry name")?),
+            "--period" | "-p" => {
+                let period = args.next().ok_or("missing period")?;
+                let period: u64 = period
+                    .parse()
+                    .map_err(|_| format!("invalid period {:?}", period))?;
+                options.period = Duration::from_millis(period);
+            }
+            "--timeout" | "-t" => {
+                let timeout = args.next().ok_or("missing timeout")?;
+                let timeout: u64 = timeout
+                    .parse()
+                    .map_err(|_| format!("invalid timeout {:?}", timeout))?;
+                options.timeout = Duration::from_millis(timeout);
+            }
+            "--json" => options.json = true,
+            _ if config.is_none() && !arg.starts_with('-') => config = Some(arg),
+            _ => return Err(format!("unknown argument {:?}", arg)),
+        }
+    }
+    options.config = config.ok_or("no configuration file provided")?;
+    Ok(options)
+}
+
+// process data of the running master (read only) or of the replay
+enum Source {
+    Master(ProcessImage),
+    Replay(EtherCatController),
+}
+
+impl Source {
+    fn open(config: &Config, timeout: Duration) -> Result<Self, io::Error> {
+        let ethercat = &config.ethercat;
+        if let Some(replay) = &ethercat.replay {
+            let controller = EtherCatController::open_replay(
+                &replay.path,
+                replay.speed,
+                ethercat.command_drop_time_us,
+            )?;
+            return Ok(Source::Replay(controller.wait_for_ready_timeout(timeout)?));
+        }
+        // retry until the application has configured the master
+        let start = Instant::now();
+        loop {
+            match ProcessImage::attach(ethercat.master_id, DOMAIN_IDX, &ethercat.pdo_mapping) {
+                Ok(image) => return Ok(Source::Master(image)),
+                Err(e) if start.elapsed() < timeout => {
+                    log::debug!("Master not ready: {}", e);
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("Master not ready after {:?}: {}", timeout, e),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn slave_ids(&self) -> Vec<u16> {
+        match self {
+            Source::Master(image) => image.slaves().iter().map(|s| s.id()).collect(),
+            Source::Replay(controller) => controller.get_slave_ids(),
+        }
+    }
+
+    fn find_slave(&self, key: &SlaveKey) -> Result<u16, io::Error> {
+        match self {
+            Source::Master(image) => find_slave(&image.identities(), key),
+            Source::Replay(controller) => controller.find_slave(key),
+        }
+    }
+
+    // name and position on the bus of the slave
+    fn slave_info(&self, id: u16) -> (String, u16) {
+        match self {
+            Source::Master(image) => image.slave(id).map_or((String::new(), id), |s| {
+                (s.identity.name.clone(), s.identity.position)
+            }),
+            Source::Replay(controller) => (
+                controller.get_slave_name(id).unwrap_or_default(),
+                controller.get_slave_position(id).unwrap_or(id),
+            ),
+        }
+    }
+
+    // decoded values of the selected entries of the slaves
+    fn read(&self, slave_ids: &[u16], filter: &[String]) -> Result<SlaveValues, io::Error> {
+        let selected =
+            |name: &str| filter.is_empty() || filter.iter().any(|f| name.contains(f.as_str()));
+        let mut values = vec![];
+        match self {
+            Source::Master(image) => {
+                let data = image.read()?;
+                for &id in slave_ids {
+                    let Some(slave) = image.slave(id) else {
+                        continue;
+                    };
+                    let entries = slave
+                        .entry_names()
+                        .into_iter()
+                        .filter(|name| selected(name))
+                        .map(|name| {
+                            let entries: Vec<_> = slave.entries(&name).collect();
+                            EntryValues {
+                                input: entries.first().is_some_and(|e| e.input),
+                                data_type: entries
+                                    .first()
+                                    .and_then(|e| e.data_type)
+                                    .and_then(data_type_name),
+                                values: entries
+                                    .iter()
+                                    .zip(slave.values(&data, &name))
+                                    .map(|(e, bytes)| format_value(e.data_type, &bytes))
+                                    .collect(),
+                                name,
+                            }
+                        })
+                        .collect();
+                    values.push((id, entries));
+                }
+            }
+            Source::Replay(controller) => {
+                for &id in slave_ids {
+                    values.push((id, read_entries(controller, id, &selected)));
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    // wait for fresh process data
+    fn wait_for_next_cycle(&self, period: Duration) {
+        match self {
+            Source::Master(_) => thread::sleep(period),
+            Source::Replay(controller) => controller.wait_for_next_cycle(),
+        }
+    }
+}
+
+// decoded values of the selected entries by slave id
+type SlaveValues = Vec<(u16, Vec<EntryValues>)>;
+
+// decoded value of an entry
+struct EntryValues {
+    name: String,
+    input: bool,
+    data_type: Option<&'static str>,
+    values: Vec<String>,
+}
+
+// decoded values of the selected entries of a slave
+fn read_entries(
+    controller: &EtherCatController,
+    slave_id: u16,
+    selected: &dyn Fn(&str) -> bool,
+) -> Vec<EntryValues> {
+    controller
+        .get_entry_names(slave_id)
+        .into_iter()
+        .filter(|name| selected(name))
+        .map(|name| {
+            let values = controller
+                .get_pdo_registers(slave_id, &name)
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+                .map(|(i, bytes)| {
+                    format_value(controller.get_entry_data_type(slave_id, &name, i), bytes)
+                })
+                .collect();
+            EntryValues {
+                input: controller.is_input_entry(slave_id, &name),
+                data_type: controller
+                    .get_entry_data_type(slave_id, &name, 0)
+                    .and_then(data_type_name),
+                values,
+                name,
+            }
+        })
+        .collect()
+}
+
+// escape a JSON string
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// numbers and booleans as is, anything else (hexadecimal, NaN...) as a string
+fn json_value(value: &str) -> String {
+    match value {
+        "true" | "false" => value.to_string(),
+        _ if value.parse::<f64>().is_ok_and(|v| v.is_finite()) => value.to_string(),
+        _ => format!("\"{}\"", escape(value)),
+    }
+}
+
+fn dump_json(source: &Source, values: &SlaveValues) -> String {
+    let slaves = values
+        .iter()
+        .map(|(id, entries)| {
+            let (name, position) = source.slave_info(*id);
+            let entries = entries
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{{\"name\":\"{}\",\"input\":{},\"data_type\":{},\"values\":[{}]}}",
+                        escape(&e.name),
+                        e.input,
+                        e.data_type
+                            .map_or("null".to_string(), |t| format!("\"{}\"", t)),
+                        e.values
+                            .iter()
+                            .map(|v| json_value(v))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!(
+                "{{\"id\":{},\"name\":\"{}\",\"position\":{},\"entries\":[{}]}}",
+                id,
+                escape(&name),
+                position,
+                entries.join(",")
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("{{\"slaves\":[{}]}}", slaves.join(","))
+}
+
+// render the table of the entries, highlighting the values changed since the previous refresh
+fn render(
+    source: &Source,
+    values: SlaveValues,
+    previous: &mut HashMap<(u16, String), Vec<String>>,
+) -> String {
+    let mut out = String::new();
+    for (id, entries) in values {
+        let (name, position) = source.slave_info(id);
+        let _ = writeln!(out, "Slave {} {:?} (pos: {})", id, name, position);
+        for entry in entries {
+            let values = entry.values.join(", ");
+            let key = (id, entry.name.clone());
+            let changed = previous.get(&key).is_some_and(|v| *v != entry.values);
+            let _ = writeln!(
+                out,
+                "  {:<32} {:<3} {:<6} {}{}{}",
+                entry.name,
+                if entry.input { "in" } else { "out" },
+                entry.data_type.unwrap_or("?"),
+                if changed { HIGHLIGHT } else { "" },
+                values,
+                if changed { RESET } else { "" },
+            );
+            previous.insert(key, entry.values);
+        }
+    }
+    out
+}
+
+fn main() {
+    env_logger::init();
+
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("{}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let config = match Config::from_yaml(&options.config) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Failed reading the configuration {}: {}", options.config, e);
+            return;
+        }
+    };
+    let source = match Source::open(&config, options.timeout) {
+        Ok(source) => source,
+        Err(e) => {
+            log::error!("Failed attaching to the EtherCAT master: {}", e);
+            return;
+        }
+    };
+
+    // selected slaves
+    let slave_ids = match options.slaves.is_empty() {
+        true => source.slave_ids(),
+        false => {
+            let mut ids = vec![];
+            for key in &options.slaves {
+                match source.find_slave(key) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        return;
+                    }
+                }
+            }
+            ids
+        }
+    };
+
+    if options.json {
+        source.wait_for_next_cycle(options.period);
+        match source.read(&slave_ids, &options.entries) {
+            Ok(values) => println!("{}", dump_json(&source, &values)),
+            Err(e) => log::error!("Failed reading the process data: {}", e),
+        }
+        return;
+    }
+
+    print!("{}", CLEAR_SCREEN);
+    let mut previous = HashMap::new();
+    loop {
+        let values = match source.read(&slave_ids, &options.entries) {
+            Ok(values) => values,
+            Err(e) => {
+                log::error!("Failed reading the process data: {}", e);
+                return;
+            }
+        };
+        let table = render(&source, values, &mut previous);
+        print!("{}{}{}", CURSOR_HOME, table, CLEAR_TO_END);
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        thread::sleep(options.period);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{error::Error, fs};
+use std::{collections::HashMap, error::Error, fs};
 
 use serde::{Deserialize, Serialize};
 
@@ -7,13 +7,163 @@ pub struct Config {
     pub ethercat: EthercatConfig,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct EthercatConfig {
     pub master_id: u32,
     pub cycle_time_us: u32,
     pub command_drop_time_us: u32,
     pub watchdog_timeout_ms: u32,
     pub mailbox_wait_time_ms: u32,
+    // MAC address of the backup network device used for the ring redundancy (optional)
+    // the device is set in the EtherCAT master configuration, it is only verified at startup
+    pub backup_device: Option<String>,
+    // record the process image of each cycle to this file (optional)
+    pub process_image_log: Option<String>,
+    // replay a recorded process image log instead of using the EtherCAT master (optional)
+    pub replay: Option<ReplayConfig>,
+    // watchdog encoding per slave type or firmware version (optional)
+    // the first matching entry is used, the poulpe encoding is used if none match
+    #[serde(default)]
+    pub watchdog: Vec<WatchdogConfig>,
+    // mailbox PDOs liveness verification per slave type or firmware version (optional)
+    // the first matching entry is used, the non zero data heuristic is used if none match
+    #[serde(default)]
+    pub mailbox: Vec<MailboxConfig>,
+    // PDOs and entries to map per slave type (optional)
+    // the first matching entry is used, all the PDOs are mapped if none match
+    #[serde(default)]
+    pub pdo_mapping: Vec<PdoMappingConfig>,
+    // station alias addressing (optional)
+    // the listed slaves are configured by their station alias instead of their position on the bus
+    // and the id of each slave is its index in the list, so the ids do not change with the cable order
+    #[serde(default)]
+    pub slaves: Vec<SlaveAddressConfig>,
+    // safety policies of the master (optional)
+    #[serde(default)]
+    pub safety: EthercatSafetyConfig,
+}
+
+// safety policies of the EtherCAT master
+// the defaults are set by the cargo features of the same name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EthercatSafetyConfig {
+    // send the watchdog counter to the slaves and verify that they echo it back
+    pub enable_watchdog: bool,
+    // verify that the slaves keep updating their mailbox PDOs
+    pub verify_mailbox_pdos: bool,
+    // exit the process if the master cannot go to the operational state
+    pub stop_operation_on_error: bool,
+}
+
+impl Default for EthercatSafetyConfig {
+    fn default() -> Self {
+        EthercatSafetyConfig {
+            enable_watchdog: cfg!(feature = "enable_watchdog"),
+            verify_mailbox_pdos: cfg!(feature = "verify_mailbox_pdos"),
+            stop_operation_on_error: cfg!(feature = "stop_opeation_on_error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    // slaves to which the entry applies (substring of the slave name/firmware version)
+    // if not set the entry applies to all slaves
+    pub slave_name: Option<String>,
+    pub firmware_version: Option<String>,
+    // set to false for slaves that do not implement the watchdog
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    // entry and bits (LSB first) to which the counter is written
+    #[serde(default = "default_watchdog_control_entry")]
+    pub control_entry: String,
+    #[serde(default = "default_watchdog_control_bits")]
+    pub control_bits: Vec<u8>,
+    // entry and bits (LSB first) from which the echoed counter is read
+    #[serde(default = "default_watchdog_status_entry")]
+    pub status_entry: String,
+    #[serde(default = "default_watchdog_status_bits")]
+    pub status_bits: Vec<u8>,
+    // increment the counter once every N cycles
+    #[serde(default = "default_watchdog_cycles_per_increment")]
+    pub cycles_per_increment: u32,
+    // overrides the watchdog_timeout_ms
+    pub timeout_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailboxConfig {
+    // slaves to which the entry applies (substring of the slave name/firmware version)
+    // if not set the entry applies to all slaves
+    pub slave_name: Option<String>,
+    pub firmware_version: Option<String>,
+    // entry changed by the slave each time it writes its mailbox PDOs (counter or timestamp)
+    // the slave is alive as long as it changes, the non zero data heuristic is used if not set
+    pub counter_entry: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_watchdog_control_entry() -> String {
+    crate::watchdog::DEFAULT_CONTROL_ENTRY.to_string()
+}
+
+fn default_watchdog_control_bits() -> Vec<u8> {
+    crate::watchdog::DEFAULT_CONTROL_BITS.to_vec()
+}
+
+fn default_watchdog_status_entry() -> String {
+    crate::watchdog::DEFAULT_STATUS_ENTRY.to_string()
+}
+
+fn default_watchdog_status_bits() -> Vec<u8> {
+    crate::watchdog::DEFAULT_STATUS_BITS.to_vec()
+}
+
+fn default_watchdog_cycles_per_increment() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PdoMappingConfig {
+    // slaves to which the entry applies (substring of the slave name and exact product code)
+    // if not set the entry applies to all slaves
+    pub slave_name: Option<String>,
+    pub product_code: Option<u32>,
+    // indexes of the PDOs to map (ex. 0x1600), all the PDOs of the slave if not set
+    pub pdos: Option<Vec<u16>>,
+    // names of the entries to map, all the entries of the mapped PDOs if not set
+    pub entries: Option<Vec<String>>,
+    // rename the entries (name reported by the slave -> name used by the application)
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    // data types of the entries (name reported by the slave -> ESI type name, ex. REAL)
+    // overrides the data types read from the CoE dictionary of the slave
+    #[serde(default)]
+    pub data_types: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaveAddressConfig {
+    // expected name of the slave (verified at initialisation)
+    pub name: String,
+    // station alias of the slave (set in its SII)
+    pub alias: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    pub path: String,
+    // 1.0 is real-time, 0.0 is as fast as possible
+    #[serde(default = "default_replay_speed")]
+    pub speed: f32,
+}
+
+fn default_replay_speed() -> f32 {
+    1.0
 }
 
 #[derive(Debug, Serialize, Deserialize)]
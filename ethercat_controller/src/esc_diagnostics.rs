@@ -0,0 +1,161 @@
+// ESC error counters and port status of the slaves
+//
+// Each EtherCAT slave controller (ESC) counts the errors seen on each of its ports (ETG.1000.4):
+// - 0x0110 DL status: link, loop and communication state of each port
+// - 0x0300 + 2*port: invalid frames (CRC errors) received on the port
+// - 0x0301 + 2*port: physical layer errors (RX errors) on the port
+// - 0x0308 + port: forwarded RX errors (CRC errors detected by a previous slave)
+// - 0x030C: processing unit errors, 0x030D: PDI errors
+// - 0x0310 + port: lost links on the port
+//
+// The counters are 8 bits and saturate at 255 (they are cleared by writing to them, which is not done here),
+// so the errors are followed as deltas between two reads.
+//
+// A frame enters a slave by its port 0 and goes to the next slave by its port 1 (line topology), so
+// the errors counted on the port 0 of a slave point to the cable (or connector) between the previous
+// slave and this one, and the lost links on its port 1 to the cable to the next slave.
+// The forwarded errors were detected upstream, the slave that counts the error without forwarding
+// it is the one right after the faulty cable.
+
+use ethercat::SlavePos;
+
+use crate::ethercat_patch::slave_reg_read;
+
+// DL status register
+const DL_STATUS_REGISTER: u16 = 0x0110;
+// error counters registers (0x0300 - 0x0313)
+const ERROR_COUNTERS_REGISTER: u16 = 0x0300;
+const ERROR_COUNTERS_SIZE: usize = 0x14;
+
+pub const PORT_NUMBER: usize = 4;
+
+// error counters of one port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortCounters {
+    // frames received with a CRC error
+    pub invalid_frames: u64,
+    // physical layer errors
+    pub rx_errors: u64,
+    // frames received with a CRC error already detected by a previous slave
+    pub forwarded_errors: u64,
+    pub lost_links: u64,
+}
+
+impl PortCounters {
+    pub fn is_zero(&self) -> bool {
+        *self == PortCounters::default()
+    }
+
+    // errors counted since the previous read
+    // a counter lower than the previous value has been reset (the slave restarted)
+    fn delta(&self, previous: &PortCounters) -> PortCounters {
+        let delta = |current: u64, previous: u64| match current >= previous {
+            true => current - previous,
+            false => current,
+        };
+        PortCounters {
+            invalid_frames: delta(self.invalid_frames, previous.invalid_frames),
+            rx_errors: delta(self.rx_errors, previous.rx_errors),
+            forwarded_errors: delta(self.forwarded_errors, previous.forwarded_errors),
+            lost_links: delta(self.lost_links, previous.lost_links),
+        }
+    }
+
+    fn add(&mut self, other: &PortCounters) {
+        self.invalid_frames += other.invalid_frames;
+        self.rx_errors += other.rx_errors;
+        self.forwarded_errors += other.forwarded_errors;
+        self.lost_links += other.lost_links;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortDiagnostics {
+    // physical link detected on the port
+    pub link: bool,
+    // the port is closed (frames are looped back instead of forwarded)
+    pub loop_closed: bool,
+    // stable communication on the port
+    pub communication: bool,
+    // value of the error counters registers (saturating at 255)
+    pub counters: PortCounters,
+    // errors counted since the previous read
+    pub delta: PortCounters,
+    // errors counted since the start of the master
+    pub total: PortCounters,
+}
+
+// diagnostics of the ESC of a slave
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EscDiagnostics {
+    pub ports: [PortDiagnostics; PORT_NUMBER],
+    pub processing_unit_errors: u8,
+    pub pdi_errors: u8,
+}
+
+impl EscDiagnostics {
+    // the errors counted on the ports since the previous read
+    pub fn has_new_errors(&self) -> bool {
+        self.ports.iter().any(|p| !p.delta.is_zero())
+    }
+}
+
+// read the DL status and the error counters registers of the slave
+// must not be called from the EtherCAT loop thread (see slave_reg_read)
+pub fn read_esc_diagnostics(
+    master_id: u32,
+    slave_pos: SlavePos,
+) -> Result<EscDiagnostics, ethercat::Error> {
+    let mut dl_status = [0u8; 2];
+    slave_reg_read(master_id, slave_pos, DL_STATUS_REGISTER, &mut dl_status)?;
+    let dl_status = u16::from_le_bytes(dl_status);
+    let mut counters = [0u8; ERROR_COUNTERS_SIZE];
+    slave_reg_read(master_id, slave_pos, ERROR_COUNTERS_REGISTER, &mut counters)?;
+
+    let mut diagnostics = EscDiagnostics {
+        processing_unit_errors: counters[0x0C],
+        pdi_errors: counters[0x0D],
+        ..Default::default()
+    };
+    for (i, port) in diagnostics.ports.iter_mut().enumerate() {
+        port.link = dl_status & (1 << (4 + i)) != 0;
+        port.loop_closed = dl_status & (1 << (8 + 2 * i)) != 0;
+        port.communication = dl_status & (1 << (9 + 2 * i)) != 0;
+        port.counters = PortCounters {
+            invalid_frames: counters[2 * i] as u64,
+            rx_errors: counters[2 * i + 1] as u64,
+            forwarded_errors: counters[0x08 + i] as u64,
+            lost_links: counters[0x10 + i] as u64,
+        };
+    }
+    Ok(diagnostics)
+}
+
+// compute the deltas and the totals from the previous diagnostics of the slave
+pub fn update_esc_diagnostics(
+    mut diagnostics: EscDiagnostics,
+    previous: Option<&EscDiagnostics>,
+) -> EscDiagnostics {
+    for (i, port) in diagnostics.ports.iter_mut().enumerate() {
+        match previous {
+            Some(previous) => {
+                port.delta = port.counters.delta(&previous.ports[i].counters);
+                port.total = previous.ports[i].total;
+            }
+            // the errors counted before the start of the master
+            None => port.delta = port.counters,
+        }
+        let delta = port.delta;
+        port.total.add(&delta);
+    }
+    diagnostics
+}
+
+// the cable the errors of a port point to (line topology)
+pub fn port_location(port: usize) -> &'static str {
+    match port {
+        0 => "cable from the previous slave",
+        1 => "cable to the next slave",
+        _ => "additional port",
+    }
+}
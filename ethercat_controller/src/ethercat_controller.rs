@@ -5,44 +5,100 @@ use std::{
     ops::Range,
     sync::{Arc, Condvar, Mutex, RwLock},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use ethercat::{
     AlState, DataType, DomainIdx, Master, MasterAccess, Offset, PdoCfg, PdoEntryIdx, PdoEntryInfo,
-    PdoEntryPos, PdoIdx, PdoPos, SdoData, SdoIdx, SdoPos, SlaveAddr, SlaveId, SlavePos, SmCfg,
-    SmIdx, SmInfo, SubIdx,
+    PdoEntryPos, PdoIdx, PdoPos, SdoData, SdoEntryAddr, SdoIdx, SdoPos, SlaveAddr, SlaveId,
+    SlavePos, SmCfg, SmIdx, SmInfo, SubIdx, WcState,
 };
 
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
 
-use crate::{watchdog, MailboxPdoEntries, PdoOffsets, SlaveNames, SlaveOffsets, SlaveSetup};
+use crate::{
+    identity, watchdog, InputPdoEntries, MailboxPdoEntries, PdoDataTypes, PdoOffsets, PdoValue,
+    SlaveIdentity, SlaveKey, SlaveNames, SlaveOffsets, SlaveSetup,
+};
 
 // function not available in the ethercat-rs crate
 use crate::ethercat_patch::master_configure_sync;
 
-#[cfg(feature = "verify_mailbox_pdos")]
-use crate::mailboxes::{init_mailbox_pdo_verification, verify_mailbox_pdos};
-#[cfg(feature = "enable_watchdog")]
-use crate::watchdog::{init_watchdog_settings, verify_watchdog};
+use crate::mailboxes::{
+    init_mailbox_pdo_verification, report_slaves_without_counter, verify_mailbox_pdos,
+    SlaveMailboxPdos,
+};
+use crate::watchdog::{init_watchdog_settings, verify_watchdog, SlaveWatchdog};
 
+use crate::addressing::resolve_slave_addresses;
+use crate::al_status::{read_al_status, AlStatus, AlStatusCode};
+use crate::config::{EthercatConfig, EthercatSafetyConfig, PdoMappingConfig, SlaveAddressConfig};
+use crate::esc_diagnostics::{
+    port_location, read_esc_diagnostics, update_esc_diagnostics, EscDiagnostics,
+};
+use crate::link::{check_backup_device, read_link_stats, LinkStats};
 use crate::mailboxes::mailbox_sdo_read;
-
-#[derive(Debug)]
+use crate::metrics::{BusMetrics, LoopMetrics, SlaveMetrics};
+use crate::pdo_entry::{entry_byte_range, read_entry, PdoWrite};
+use crate::pdo_mapping::{
+    check_pdo_mapping_config, entry_name, find_pdo_mapping_config, select_pdos,
+};
+use crate::pdo_value::parse_data_type;
+use crate::replay::{ProcessImageHeader, ProcessImageRecorder, ProcessImageReplay};
+
+// period at which the link statistics are read
+const LINK_STATS_PERIOD: Duration = Duration::from_secs(1);
+// period at which the ESC error counters are read
+const ESC_DIAGNOSTICS_PERIOD: Duration = Duration::from_secs(1);
+// period at which the AL states of the slaves are read
+const SLAVE_AL_STATES_PERIOD: Duration = Duration::from_millis(10);
+
+// (cloned handles share the EtherCAT loop)
+#[derive(Debug, Clone)]
 pub struct EtherCatController {
     offsets: SlaveOffsets,
     slave_names: SlaveNames,
+    identities: Vec<SlaveIdentity>,
+    // position on the bus of each slave (by slave id, see addressing)
+    slave_positions: Vec<u16>,
+    // data types of the entries (if known)
+    data_types: PdoDataTypes,
 
     data_lock: Arc<RwLock<Option<Vec<u8>>>>,
     ready_condvar: Arc<(Mutex<bool>, Condvar)>,
-    cycle_condvar: Arc<(Mutex<bool>, Condvar)>,
+    cycle_condvar: Arc<(Mutex<u64>, Condvar)>,
     slave_states_condvar: Arc<(Mutex<Vec<u8>>, Condvar)>,
 
-    cmd_buff: Sender<(Range<usize>, Vec<u8>)>,
+    cmd_buff: Sender<PdoWrite>,
 
     // is poulpe setup
     setup_condvar: Arc<(Mutex<SlaveSetup>, Condvar)>,
 
+    // process image recording
+    cycle_period: Duration,
+    input_pdo_entries: InputPdoEntries,
+    recorder: Arc<Mutex<Option<ProcessImageRecorder>>>,
+
+    // bus metrics published by the EtherCAT loop
+    metrics: Arc<RwLock<BusMetrics>>,
+    // AL status of the slaves that refused a state change
+    al_status: Arc<RwLock<HashMap<u16, AlStatus>>>,
+
+    // safety policies in effect
+    safety: EthercatSafetyConfig,
+
+    // slaves deliberately taken out of OP and the state requested for them
+    // (a slave requested back to OP stays here until the loop sees it operational again)
+    master_id: u32,
+    out_of_op: Arc<RwLock<HashMap<u16, AlState>>>,
+
+    // mailbox PDO entries and when their cached value was received (by slave id)
+    mailbox_pdo_entries: MailboxPdoEntries,
+    mailbox_timestamps: Arc<RwLock<HashMap<u16, Instant>>>,
+
+    // ESC error counters and port status of the slaves (by slave id)
+    esc_diagnostics: Arc<RwLock<HashMap<u16, EscDiagnostics>>>,
+
     pub command_drop_time_us: u32,
 }
 
@@ -54,38 +110,105 @@ impl EtherCatController {
         watchdog_timeout_ms: u32,
         mailbox_wait_time_ms: u32,
     ) -> Result<Self, io::Error> {
-        let (mut master, domain_idx, offsets, slave_names, mailbox_pdo_entries) =
-            init_master(master_id)?;
+        Self::open_from_config(&EthercatConfig {
+            master_id,
+            cycle_time_us: cycle_period.as_micros() as u32,
+            command_drop_time_us,
+            watchdog_timeout_ms,
+            mailbox_wait_time_ms,
+            ..Default::default()
+        })
+    }
+
+    // open the master using the ethercat section of the configuration file
+    pub fn open_from_config(config: &EthercatConfig) -> Result<Self, io::Error> {
+        let cycle_period = Duration::from_micros(config.cycle_time_us as u64);
+        let command_drop_time_us = config.command_drop_time_us;
+        let mailbox_wait_time_ms = config.mailbox_wait_time_ms;
+        let safety = config.safety.clone();
+        log::info!("EtherCAT master safety configuration: {:?}", safety);
+
+        let (
+            mut master,
+            domain_idx,
+            offsets,
+            slave_names,
+            mailbox_pdo_entries,
+            input_pdo_entries,
+            data_types,
+            slave_positions,
+        ) = init_master(config.master_id, &config.pdo_mapping, &config.slaves)?;
 
         // read the slave info using SDOs
         // IMPORTANT !!!!!!!
         // must be done before master.activate()
-        for slave_id in 0..slave_names.len() {
+        let mut firmware_versions = vec![];
+        let mut identities = vec![];
+        for (slave_id, &position) in slave_positions.iter().enumerate() {
             let mut data = vec![0u8; 1];
-            match mailbox_sdo_read(&master, slave_id as u16, 0x201, 0x1, &mut data) {
+            let dxl_id = match mailbox_sdo_read(&master, position, 0x201, 0x1, &mut data) {
                 Ok(_) => {
                     log::info!("Slave {}, DXL_ID: {:?}", slave_id, data[0]);
+                    Some(data[0])
                 }
                 Err(_) => {
                     log::warn!("Slave {}, DXL_ID unknown!", slave_id);
+                    None
                 }
-            }
+            };
+            // identity of the slave (name, alias, serial number and DXL_ID)
+            let info = master.get_slave_info(SlavePos::from(position))?;
+            identities.push(SlaveIdentity {
+                id: slave_id as u16,
+                position,
+                name: info.name.clone(),
+                alias: info.alias,
+                serial_number: info.rev.serial_number,
+                dxl_id,
+            });
 
             let mut data = vec![0u8; 40];
-            match mailbox_sdo_read(&master, slave_id as u16, 0x200, 0x1, &mut data) {
+            match mailbox_sdo_read(&master, position, 0x200, 0x1, &mut data) {
                 Ok(_) => {
-                    log::info!(
-                        "Slave {} firmware version: {:?}",
-                        slave_id,
-                        String::from_utf8(data).unwrap()
-                    );
+                    let version = String::from_utf8_lossy(&data)
+                        .trim_end_matches('\0')
+                        .to_string();
+                    log::info!("Slave {} firmware version: {:?}", slave_id, version);
+                    firmware_versions.push(Some(version));
                 }
                 Err(_) => {
                     log::warn!("Slave {}, firmware version unknown!", slave_id);
+                    firmware_versions.push(None);
                 }
             }
         }
 
+        // report the slaves that have the same name (or alias, serial number, DXL_ID)
+        identity::report_duplicates(&identities);
+
+        // network devices of the master (main and backup)
+        let link_stats = match read_link_stats(config.master_id) {
+            Ok(stats) => {
+                for d in &stats.devices {
+                    log::info!(
+                        "Network device {} | {} (attached: {}, link up: {})",
+                        d.index,
+                        d.address,
+                        d.attached,
+                        d.link_up
+                    );
+                }
+                stats
+            }
+            Err(e) => {
+                log::warn!("Failed reading the network devices of the master: {}", e);
+                LinkStats::default()
+            }
+        };
+        if let Some(backup_device) = &config.backup_device {
+            check_backup_device(&link_stats, backup_device)?;
+        }
+
         master.activate()?;
 
         // log the pdo offsets (debug)
@@ -101,7 +224,7 @@ impl EtherCatController {
         let write_ready_condvar = Arc::clone(&ready_condvar);
 
         // ethercat master cycle mutex
-        let cycle_condvar = Arc::new((Mutex::new(false), Condvar::new()));
+        let cycle_condvar = Arc::new((Mutex::new(0u64), Condvar::new()));
         let write_cycle_condvar = Arc::clone(&cycle_condvar);
 
         // ethercat slave states mutex
@@ -124,38 +247,184 @@ impl EtherCatController {
         // create a sync channel to send data to the master
         // crossbeam_channel is more efficient than std::sync::mcsp::SyncChannel
         let buffer_size = (slave_number * 20) as usize;
-        let (tx, rx): (
-            crossbeam_channel::Sender<(Range<usize>, Vec<u8>)>,
-            Receiver<(Range<usize>, Vec<u8>)>,
-        ) = bounded(buffer_size);
+        let (tx, rx): (crossbeam_channel::Sender<PdoWrite>, Receiver<PdoWrite>) =
+            bounded(buffer_size);
 
-        #[cfg(feature = "verify_mailbox_pdos")]
         // initialize the mailbox verification
-        let (
-            mut slave_mailbox_pdo_offsets,
-            mut slave_mailbox_pdo_timestamps,
-            mut slave_is_mailbox_pdo_responding,
-            mut slave_mailbox_pdo_data_buffer,
-        ) = init_mailbox_pdo_verification(
+        let mut slave_mailbox_pdos = init_mailbox_pdo_verification(
             slave_number,
             &mailbox_pdo_entries,
             &offsets,
+            &config.mailbox,
+            &slave_name_from_id,
+            &firmware_versions,
             &get_reg_addr_ranges,
         );
+        report_slaves_without_counter(&slave_mailbox_pdos, safety.verify_mailbox_pdos);
 
-        #[cfg(feature = "enable_watchdog")]
         // initialize the watchdog settings
-        let (
-            slave_watchdog_control_offsets,
-            slave_watchdog_status_offsets,
-            mut slave_watchdog_timestamps,
-            mut slave_is_watchdog_responding,
-            mut slave_previous_watchdog_counter,
-        ) = init_watchdog_settings(slave_number, &offsets, &get_reg_addr_ranges);
+        let mut slave_watchdogs = init_watchdog_settings(
+            slave_number,
+            &offsets,
+            &config.watchdog,
+            config.watchdog_timeout_ms,
+            &slave_name_from_id,
+            &firmware_versions,
+            &get_reg_addr_ranges,
+        )?;
+
+        // process image recorder (not recording by default)
+        let recorder: Arc<Mutex<Option<ProcessImageRecorder>>> = Arc::new(Mutex::new(None));
+        let cycle_recorder = Arc::clone(&recorder);
+
+        // AL status of the slaves that refused a state change
+        // the registers are read by a separate thread on request of the EtherCAT loop
+        // (register requests are processed by the master in the loop, they would block it)
+        let al_status: Arc<RwLock<HashMap<u16, AlStatus>>> = Arc::new(RwLock::new(HashMap::new()));
+        let cycle_al_status = Arc::clone(&al_status);
+        let (al_status_request, al_status_requests) = bounded::<()>(1);
+        let master_id = config.master_id;
+        let diag_al_status = Arc::clone(&al_status);
+        let diag_slave_name_from_id = create_slave_name_mapper(slave_names.clone());
+        let diag_slave_positions = slave_positions.clone();
+        thread::spawn(move || {
+            while al_status_requests.recv().is_ok() {
+                for i in 0..slave_number {
+                    match read_al_status(
+                        master_id,
+                        SlavePos::from(diag_slave_positions[i as usize]),
+                    ) {
+                        Ok(status) if status.error => {
+                            let previous = diag_al_status.write().unwrap().insert(i as u16, status);
+                            if previous != Some(status) {
+                                log::error!(
+                                    "Slave {:?} (pos: {}) refused the state change, AL status code {}",
+                                    diag_slave_name_from_id(i as u16),
+                                    diag_slave_positions[i as usize],
+                                    status.code
+                                );
+                            }
+                        }
+                        Ok(_) => {
+                            diag_al_status.write().unwrap().remove(&(i as u16));
+                        }
+                        Err(e) => {
+                            log::debug!("Failed reading the AL status of slave {}: {}", i, e);
+                        }
+                    }
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+
+        // ESC error counters and port status of the slaves
+        // read periodically by a separate thread (register requests, see the AL status above)
+        // and on request of the EtherCAT loop when slaves stop responding
+        let esc_diagnostics: Arc<RwLock<HashMap<u16, EscDiagnostics>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let (esc_diagnostics_request, esc_diagnostics_requests) = bounded::<()>(1);
+        let diag_esc_diagnostics = Arc::clone(&esc_diagnostics);
+        let diag_slave_name_from_id = create_slave_name_mapper(slave_names.clone());
+        let diag_slave_positions = slave_positions.clone();
+        thread::spawn(move || loop {
+            for (i, &position) in diag_slave_positions.iter().enumerate() {
+                match read_esc_diagnostics(master_id, SlavePos::from(position)) {
+                    Ok(diagnostics) => {
+                        let mut all_diagnostics = diag_esc_diagnostics.write().unwrap();
+                        let diagnostics =
+                            update_esc_diagnostics(diagnostics, all_diagnostics.get(&(i as u16)));
+                        for (port, p) in diagnostics.ports.iter().enumerate() {
+                            if !p.delta.is_zero() {
+                                log::warn!(
+                                    "Slave {:?} (pos: {}) port {} ({}) | new errors: {:?}, link: {}",
+                                    diag_slave_name_from_id(i as u16),
+                                    position,
+                                    port,
+                                    port_location(port),
+                                    p.delta,
+                                    p.link
+                                );
+                            }
+                        }
+                        all_diagnostics.insert(i as u16, diagnostics);
+                    }
+                    Err(e) => {
+                        log::debug!(
+                            "Failed reading the ESC error counters of slave {}: {}",
+                            i,
+                            e
+                        );
+                    }
+                }
+            }
+            if let Err(RecvTimeoutError::Disconnected) =
+                esc_diagnostics_requests.recv_timeout(ESC_DIAGNOSTICS_PERIOD)
+            {
+                break;
+            }
+        });
+
+        // bus metrics published by the loop
+        let metrics = Arc::new(RwLock::new(BusMetrics::default()));
+        let cycle_metrics = Arc::clone(&metrics);
+
+        // frame counters of the network devices
+        // read by a separate thread as the request takes the master lock
+        let link_stats = Arc::new(RwLock::new(link_stats));
+        let cycle_link_stats = Arc::clone(&link_stats);
+        thread::spawn(move || loop {
+            match read_link_stats(master_id) {
+                Ok(stats) => *link_stats.write().unwrap() = stats,
+                Err(e) => log::debug!("Failed reading the link statistics: {}", e),
+            }
+            thread::sleep(LINK_STATS_PERIOD);
+        });
+
+        // AL state of each slave (None if it could not be read)
+        // read by a separate thread as the slave info request takes the master lock
+        let slave_al_states: Arc<RwLock<Vec<Option<AlState>>>> =
+            Arc::new(RwLock::new(vec![None; slave_number as usize]));
+        let cycle_slave_al_states = Arc::clone(&slave_al_states);
+        let diag_slave_positions = slave_positions.clone();
+        thread::spawn(move || {
+            let mut master = match Master::open(master_id, MasterAccess::ReadOnly) {
+                Ok(master) => master,
+                Err(e) => {
+                    log::error!("Failed opening the master to read the slave states: {}", e);
+                    return;
+                }
+            };
+            loop {
+                let states = diag_slave_positions
+                    .iter()
+                    .map(|&position| {
+                        master
+                            .get_slave_info(SlavePos::from(position))
+                            .ok()
+                            .map(|info| info.al_state)
+                    })
+                    .collect();
+                *slave_al_states.write().unwrap() = states;
+                thread::sleep(SLAVE_AL_STATES_PERIOD);
+            }
+        });
+
+        // slaves deliberately taken out of OP (see request_slave_state)
+        let out_of_op: Arc<RwLock<HashMap<u16, AlState>>> = Arc::new(RwLock::new(HashMap::new()));
+        let cycle_out_of_op = Arc::clone(&out_of_op);
+
+        // when the cached mailbox PDO data of each slave was received
+        let mailbox_timestamps: Arc<RwLock<HashMap<u16, Instant>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let cycle_mailbox_timestamps = Arc::clone(&mailbox_timestamps);
+        let publish_mailbox_timestamps =
+            safety.verify_mailbox_pdos && slave_mailbox_pdos.iter().any(|mb| mb.has_mailbox_pdos());
 
-        let mut watchdog_counter = 0;
+        // position on the bus of each slave id
+        let cycle_slave_positions = slave_positions.clone();
 
         thread::spawn(move || {
+            let slave_pos_from_id = |id: u16| SlavePos::from(cycle_slave_positions[id as usize]);
             // is master operational flag
             let mut master_operational = false;
             // timestamp to say from when the master is not operational
@@ -164,6 +433,14 @@ impl EtherCatController {
             let mut loop_period_timestamp = std::time::Instant::now();
             let mut debug_loop_timestamp = std::time::Instant::now();
             let mut debug_loop_counter = 0;
+            // loop timing and working counter metrics
+            let mut loop_metrics = LoopMetrics::new(cycle_period);
+            // local copy of the slaves deliberately out of OP
+            let mut out_of_op: HashMap<u16, AlState> = HashMap::new();
+            // last link statistics (frame counters from the link statistics thread)
+            let mut link = LinkStats::default();
+            // last AL state of each slave (from the slave states thread)
+            let mut al_states: Vec<Option<AlState>> = vec![None; slave_number as usize];
             // spawn a thread to handle the master
             loop {
                 // check the loop period
@@ -176,6 +453,8 @@ impl EtherCatController {
                 }
                 // set the loop period timestamp
                 loop_period_timestamp = std::time::Instant::now();
+                // the previous cycle overran if there was no time left to sleep
+                loop_metrics.start_cycle(dt_sleep <= 0.0);
 
                 // debugging output
                 debug_loop_counter += 1;
@@ -193,6 +472,14 @@ impl EtherCatController {
                 master.domain(domain_idx).process().unwrap();
                 master.domain(domain_idx).queue().unwrap();
 
+                // check the working counter of the received frame
+                if let Ok(domain_state) = master.domain(domain_idx).state() {
+                    loop_metrics.working_counter(
+                        domain_state.working_counter,
+                        matches!(domain_state.wc_state, WcState::Complete),
+                    );
+                }
+
                 // get the domain data
                 let mut data = master.domain_data(domain_idx).unwrap();
 
@@ -206,21 +493,35 @@ impl EtherCatController {
                 //
                 // if the slaves are responding it will update the data buffer
                 // with the mailbox data (which might have been read some time ago (but less than 1s ago))
-                #[cfg(feature = "verify_mailbox_pdos")]
-                let all_slaves_responding = verify_mailbox_pdos(
-                    slave_number,
-                    &mut data,
-                    &mut slave_mailbox_pdo_offsets,
-                    &mut slave_mailbox_pdo_timestamps,
-                    &mut slave_is_mailbox_pdo_responding,
-                    &mut slave_mailbox_pdo_data_buffer,
-                    mailbox_wait_time_ms,
-                );
+                let all_mailbox_pdos_responding = !safety.verify_mailbox_pdos
+                    || verify_mailbox_pdos(
+                        &mut data,
+                        &mut slave_mailbox_pdos,
+                        mailbox_wait_time_ms,
+                    );
 
                 // write the data to the data lock
                 if let Ok(mut write_guard) = write_data_lock.write() {
                     *write_guard = Some(data.to_vec());
                 }
+                // and the age of the cached mailbox data
+                if publish_mailbox_timestamps {
+                    if let Ok(mut timestamps) = cycle_mailbox_timestamps.write() {
+                        for (i, mb) in slave_mailbox_pdos.iter().enumerate() {
+                            if mb.has_mailbox_pdos() {
+                                timestamps.insert(i as u16, mb.timestamp);
+                            }
+                        }
+                    }
+                }
+
+                // record the process image as seen by the readers
+                // try_lock to never block the loop while the recording is started or stopped
+                if let Ok(recorder) = cycle_recorder.try_lock() {
+                    if let Some(recorder) = recorder.as_ref() {
+                        recorder.record(data);
+                    }
+                }
 
                 // notify the next cycle
                 notify_next_cycle(&write_cycle_condvar);
@@ -231,38 +532,153 @@ impl EtherCatController {
                     // check if the RX buffer is getting full!!!
                     // if rx.len() > 40 {log::warn!("RX buffer almost full: {}/{}", rx.len(), buffer_size)}
                     // update the data buffer with the new data to send
-                    while let Ok((reg_addr_range, value)) = rx.try_recv() {
-                        data[reg_addr_range].copy_from_slice(&value);
+                    // (read-modify-write for the entries that are not byte aligned)
+                    while let Ok(pdo_write) = rx.try_recv() {
+                        pdo_write.apply(data);
                     }
                 }
 
-                #[cfg(feature = "enable_watchdog")]
                 // verify the watchdog
-                let all_slaves_have_watchdog = verify_watchdog(
-                    slave_number,
-                    &mut data,
-                    watchdog_timeout_ms,
-                    watchdog_counter,
-                    &slave_watchdog_control_offsets,
-                    &slave_watchdog_status_offsets,
-                    &mut slave_watchdog_timestamps,
-                    &mut slave_is_watchdog_responding,
-                    &mut slave_previous_watchdog_counter,
-                    &slave_name_from_id,
-                );
-                // update the watchdog counter
-                watchdog_counter = (watchdog_counter + 1) % 8;
+                let all_watchdogs_responding = !safety.enable_watchdog
+                    || verify_watchdog(&mut data, &mut slave_watchdogs, &slave_name_from_id);
 
                 // send the data to the slaves
                 master.send().unwrap();
 
                 // get the master state
                 let m_state = master.state().unwrap();
-                #[cfg(not(feature = "verify_mailbox_pdos"))]
-                // get the slave states without mailbox verification
-                let all_slaves_responding = m_state.slaves_responding == slave_number;
-                #[cfg(not(feature = "enable_watchdog"))]
-                let all_slaves_have_watchdog = m_state.slaves_responding == slave_number;
+
+                // update the AL states of the slaves (try_read to never block the loop)
+                if let Ok(states) = cycle_slave_al_states.try_read() {
+                    al_states.clone_from(&states);
+                }
+                let is_slave_op = |i: usize| al_states[i] == Some(AlState::Op);
+
+                // update the slaves deliberately out of OP (try_read to never block the loop)
+                let mut out_of_op_changed = false;
+                if let Ok(requested) = cycle_out_of_op.try_read() {
+                    if *requested != out_of_op {
+                        out_of_op = requested.clone();
+                        out_of_op_changed = true;
+                    }
+                }
+                // the slaves requested back to OP are considered back once
+                // they are operational and responding again
+                for (&id, _) in out_of_op.iter().filter(|(_, s)| **s == AlState::Op) {
+                    let i = id as usize;
+                    let back = is_slave_op(i)
+                        && (!safety.verify_mailbox_pdos || slave_mailbox_pdos[i].is_responding)
+                        && (!safety.enable_watchdog || slave_watchdogs[i].is_responding);
+                    if back {
+                        if let Ok(mut requested) = cycle_out_of_op.try_write() {
+                            requested.remove(&id);
+                            log::info!(
+                                "Slave {:?} (pos: {}) is back in OP",
+                                slave_name_from_id(id),
+                                cycle_slave_positions[i]
+                            );
+                        }
+                    }
+                }
+                let is_in_use = |i: u32| !out_of_op.contains_key(&(i as u16));
+
+                // the slaves deliberately out of OP are not verified
+                let all_mailbox_pdos_responding = all_mailbox_pdos_responding
+                    || (0..slave_number)
+                        .filter(|&i| is_in_use(i))
+                        .all(|i| slave_mailbox_pdos[i as usize].is_responding);
+                let all_watchdogs_responding = all_watchdogs_responding
+                    || (0..slave_number)
+                        .filter(|&i| is_in_use(i))
+                        .all(|i| slave_watchdogs[i as usize].is_responding);
+                // the master AL state is the combination of the slave states
+                // so with slaves out of OP each of the other slaves is checked
+                let master_al_op = m_state.al_states == AlState::Op as u8
+                    || (!out_of_op.is_empty()
+                        && (0..slave_number)
+                            .filter(|&i| is_in_use(i))
+                            .all(|i| is_slave_op(i as usize)));
+
+                // without mailbox and watchdog verification
+                // only check that all the slaves are responding
+                let all_slaves_responding = match safety.verify_mailbox_pdos {
+                    true => all_mailbox_pdos_responding,
+                    false => m_state.slaves_responding == slave_number,
+                };
+                let all_slaves_have_watchdog = match safety.enable_watchdog {
+                    true => all_watchdogs_responding,
+                    false => m_state.slaves_responding == slave_number,
+                };
+
+                // publish the bus metrics
+                if loop_metrics.should_publish() {
+                    let slaves = (0..slave_number)
+                        .map(|i| SlaveMetrics {
+                            position: cycle_slave_positions[i as usize],
+                            name: slave_name_from_id(i as u16),
+                            al_state: al_states[i as usize].map_or(0, |s| s as u8),
+                            watchdog_age_ms: (safety.enable_watchdog
+                                && slave_watchdogs[i as usize].is_enabled())
+                            .then(|| {
+                                slave_watchdogs[i as usize]
+                                    .timestamp
+                                    .elapsed()
+                                    .as_secs_f32()
+                                    * 1e3
+                            }),
+                            al_status_code: cycle_al_status
+                                .try_read()
+                                .ok()
+                                .and_then(|s| s.get(&(i as u16)).map(|s| s.code)),
+                            mailbox_age_ms: (safety.verify_mailbox_pdos
+                                && slave_mailbox_pdos[i as usize].has_mailbox_pdos())
+                            .then(|| {
+                                slave_mailbox_pdos[i as usize]
+                                    .timestamp
+                                    .elapsed()
+                                    .as_secs_f32()
+                                    * 1e3
+                            }),
+                        })
+                        .collect();
+
+                    // link state and slaves responding on each link
+                    if let Ok(stats) = cycle_link_stats.try_read() {
+                        link.devices = stats.devices.clone();
+                        link.lost_frames = stats.lost_frames;
+                    }
+                    for d in link.devices.iter_mut() {
+                        if let Ok(state) = master.link_state(d.index as u32) {
+                            d.link_up = state.link_up;
+                            d.slaves_responding = state.slaves_responding;
+                        }
+                    }
+                    // with a backup device, the main link reaches all the slaves only if the ring is closed
+                    let redundancy_degraded = link.devices.len() > 1
+                        && (link.devices.iter().any(|d| !d.link_up)
+                            || link.devices[0].slaves_responding < slave_number);
+                    if redundancy_degraded != link.redundancy_degraded {
+                        match redundancy_degraded {
+                            true => log::warn!(
+                                "Ring redundancy degraded! Links: {:?}",
+                                link.devices
+                                    .iter()
+                                    .map(|d| (d.address.as_str(), d.link_up, d.slaves_responding))
+                                    .collect::<Vec<_>>()
+                            ),
+                            false => log::info!("Ring redundancy restored"),
+                        }
+                    }
+                    link.redundancy_degraded = redundancy_degraded;
+
+                    loop_metrics.publish(
+                        &cycle_metrics,
+                        m_state.link_up,
+                        master_operational,
+                        slaves,
+                        link.clone(),
+                    );
+                }
 
                 // master opration state machine
                 // if the master is not operational
@@ -291,7 +707,7 @@ impl EtherCatController {
                     if all_slaves_responding
                         && all_slaves_have_watchdog
                         && m_state.link_up
-                        && m_state.al_states == AlState::Op as u8 // OP = 8 is operational
+                        && master_al_op // OP = 8 is operational
                         && m_state.slaves_responding == slave_number
                     {
                         // notify the operational state to the master
@@ -300,10 +716,16 @@ impl EtherCatController {
                         // notify the operational state to the slaves
                         notify_slave_state(
                             &sstate_condvar,
-                            vec![AlState::Op as u8; slave_number as usize],
+                            requested_slave_states(slave_number, &out_of_op),
                         );
                         log::info!("Master and all slaves operational!");
+                        if !out_of_op.is_empty() {
+                            log::warn!("Slaves deliberately out of OP: {:?}", out_of_op);
+                        }
                     } else {
+                        // request the AL status codes of the slaves (never blocks)
+                        let _ = al_status_request.try_send(());
+
                         // check each second
                         if display_not_operational_timestamp.elapsed().as_secs() > 1 {
                             display_not_operational_timestamp = std::time::Instant::now();
@@ -312,21 +734,18 @@ impl EtherCatController {
                             // if the master is not operational
                             log_master_state(
                                 &master,
-                                slave_number,
-                                #[cfg(feature = "verify_mailbox_pdos")]
+                                &cycle_slave_positions,
                                 mailbox_wait_time_ms,
-                                #[cfg(feature = "enable_watchdog")]
-                                watchdog_timeout_ms,
                                 &slave_name_from_id,
-                                #[cfg(feature = "verify_mailbox_pdos")]
-                                &slave_is_mailbox_pdo_responding,
-                                #[cfg(feature = "enable_watchdog")]
-                                &slave_is_watchdog_responding,
+                                &slave_mailbox_pdos,
+                                &slave_watchdogs,
+                                &cycle_al_status,
                             );
 
                             // kill the master if error recovery not supported
-                            #[cfg(feature = "stop_opeation_on_error")]
-                            std::process::exit(10);
+                            if safety.stop_operation_on_error {
+                                std::process::exit(10);
+                            }
                         }
                     }
                 } else {
@@ -350,18 +769,19 @@ impl EtherCatController {
                             ),
                             _ => {}
                         }
+                        // read the error counters of the slaves to find the faulty cable
+                        let _ = esc_diagnostics_request.try_send(());
 
                         // update the slave states
                         let slave_current_state = (0..slave_number)
                             .map(|i| {
                                 get_slave_current_state(
                                     &master,
-                                    SlavePos::from(i as u16),
+                                    i as u16,
+                                    slave_pos_from_id(i as u16),
                                     &slave_name_from_id,
-                                    #[cfg(feature = "verify_mailbox_pdos")]
-                                    slave_is_mailbox_pdo_responding[i as usize],
-                                    #[cfg(feature = "enable_watchdog")]
-                                    slave_is_watchdog_responding[i as usize],
+                                    slave_mailbox_pdos[i as usize].is_responding,
+                                    slave_watchdogs[i as usize].is_responding,
                                 )
                             })
                             .collect::<Vec<_>>();
@@ -374,12 +794,9 @@ impl EtherCatController {
                     }
 
                     // if master state has changed or not all slaves are responding
-                    if m_state.al_states != AlState::Op as u8
-                        || !all_slaves_responding
-                        || !all_slaves_have_watchdog
-                    {
+                    if !master_al_op || !all_slaves_responding || !all_slaves_have_watchdog {
                         // master state has changed
-                        if m_state.al_states != AlState::Op as u8 {
+                        if !master_al_op {
                             log::error!(
                                 "Master is not operational! State: {:?}",
                                 m_state.al_states
@@ -402,12 +819,11 @@ impl EtherCatController {
                             .map(|i| {
                                 get_slave_current_state(
                                     &master,
-                                    SlavePos::from(i as u16),
+                                    i as u16,
+                                    slave_pos_from_id(i as u16),
                                     &slave_name_from_id,
-                                    #[cfg(feature = "verify_mailbox_pdos")]
-                                    slave_is_mailbox_pdo_responding[i as usize],
-                                    #[cfg(feature = "enable_watchdog")]
-                                    slave_is_watchdog_responding[i as usize],
+                                    slave_mailbox_pdos[i as usize].is_responding,
+                                    slave_watchdogs[i as usize].is_responding,
                                 )
                             })
                             .collect::<Vec<_>>();
@@ -419,6 +835,12 @@ impl EtherCatController {
                         set_ready_flag(&write_ready_condvar, false);
                         // master is not operational
                         master_operational = false;
+                    } else if out_of_op_changed {
+                        // a slave was taken out of OP or is back in OP
+                        notify_slave_state(
+                            &sstate_condvar,
+                            requested_slave_states(slave_number, &out_of_op),
+                        );
                     }
                 }
             }
@@ -427,16 +849,349 @@ impl EtherCatController {
         Ok(EtherCatController {
             offsets,
             slave_names,
+            identities,
+            slave_positions,
+            data_types,
             data_lock,
             ready_condvar,
             cycle_condvar,
             slave_states_condvar,
             setup_condvar,
             cmd_buff: tx,
+            cycle_period,
+            input_pdo_entries,
+            recorder,
+            metrics,
+            al_status,
+            safety: config.safety.clone(),
+            master_id,
+            out_of_op,
+            mailbox_pdo_entries,
+            mailbox_timestamps,
+            esc_diagnostics,
             command_drop_time_us,
         })
     }
 
+    // replay a process image log recorded on the robot instead of using the EtherCAT master
+    // - the input entries are fed to the readers cycle by cycle, as they were recorded
+    // - the outputs are kept in the process image as they are set by the writers (nothing is sent)
+    // - the speed is the replay speed factor (1.0 real-time, 2.0 twice as fast, etc.)
+    //   if the speed is 0 or negative the frames are replayed as fast as possible
+    //
+    // the master and all the slaves are considered operational until the end of the log
+    pub fn open_replay(
+        path: &str,
+        speed: f32,
+        command_drop_time_us: u32,
+    ) -> Result<Self, io::Error> {
+        let mut replay = ProcessImageReplay::open(path)?;
+        let header = replay.header.clone();
+
+        let offsets = header.slave_offsets();
+        let slave_names = header.slave_names();
+        let identities = header.slave_identities();
+        let input_pdo_entries = header.input_pdo_entries();
+        let data_types = header.data_types();
+        let input_ranges = header.input_ranges();
+        let cycle_period = Duration::from_micros(header.cycle_time_us as u64);
+        let slave_number = slave_names.len() as u32;
+
+        log::info!(
+            "Replaying {} ({} slaves, cycle {}us) at speed x{}",
+            path,
+            slave_number,
+            header.cycle_time_us,
+            speed
+        );
+        log_pdo_offsets(&offsets);
+
+        let data_lock = Arc::new(RwLock::new(None));
+        let write_data_lock = Arc::clone(&data_lock);
+        let ready_condvar = Arc::new((Mutex::new(false), Condvar::new()));
+        let write_ready_condvar = Arc::clone(&ready_condvar);
+        let cycle_condvar = Arc::new((Mutex::new(0u64), Condvar::new()));
+        let write_cycle_condvar = Arc::clone(&cycle_condvar);
+        let slave_states_condvar = Arc::new((Mutex::new(vec![0]), Condvar::new()));
+        let sstate_condvar = Arc::clone(&slave_states_condvar);
+
+        let mut is_poulpe_setup: SlaveSetup = HashMap::new();
+        for i in 0..slave_names.len() {
+            is_poulpe_setup.insert(SlavePos::from(i as u16), false);
+        }
+        let setup_condvar = Arc::new((Mutex::new(is_poulpe_setup), Condvar::new()));
+
+        let buffer_size = (slave_number * 20) as usize;
+        let (tx, rx): (crossbeam_channel::Sender<PdoWrite>, Receiver<PdoWrite>) =
+            bounded(buffer_size);
+
+        let path = path.to_string();
+        thread::spawn(move || {
+            // process image as seen by the readers
+            // the outputs are only modified by the writers
+            let mut data: Option<Vec<u8>> = None;
+            let replay_start = std::time::Instant::now();
+            loop {
+                let (timestamp, frame) = match replay.next_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => {
+                        log::info!("End of the replay {}", path);
+                        break;
+                    }
+                    Err(e) => {
+                        log::error!("Failed reading the replay {}: {}", path, e);
+                        break;
+                    }
+                };
+
+                // wait for the frame time (scaled by the replay speed)
+                if speed > 0.0 {
+                    let frame_time = timestamp.div_f32(speed);
+                    let elapsed = replay_start.elapsed();
+                    if frame_time > elapsed {
+                        thread::sleep(frame_time - elapsed);
+                    }
+                }
+
+                match data.as_mut() {
+                    Some(d) if d.len() == frame.len() => {
+                        // only the inputs are taken from the recording
+                        for range in &input_ranges {
+                            d[range.clone()].copy_from_slice(&frame[range.clone()]);
+                        }
+                    }
+                    _ => {
+                        // first frame, the outputs are initialised as recorded
+                        data = Some(frame);
+                        notify_slave_state(
+                            &sstate_condvar,
+                            vec![AlState::Op as u8; slave_number as usize],
+                        );
+                    }
+                }
+                let d = data.as_mut().unwrap();
+
+                // the replay is operational as long as there are frames
+                // (set on each cycle so that wait_for_ready never misses it)
+                set_ready_flag(&write_ready_condvar, true);
+
+                // write the data to the data lock
+                if let Ok(mut write_guard) = write_data_lock.write() {
+                    *write_guard = Some(d.clone());
+                }
+
+                // notify the next cycle
+                notify_next_cycle(&write_cycle_condvar);
+
+                // update the outputs with the new data from the writers
+                while let Ok(pdo_write) = rx.try_recv() {
+                    pdo_write.apply(d);
+                }
+            }
+
+            // the recording is over, nothing is operational anymore
+            notify_slave_state(&sstate_condvar, vec![0; slave_number as usize]);
+            set_ready_flag(&write_ready_condvar, false);
+        });
+
+        Ok(EtherCatController {
+            offsets,
+            slave_names,
+            slave_positions: identities.iter().map(|i| i.position).collect(),
+            identities,
+            data_types,
+            data_lock,
+            ready_condvar,
+            cycle_condvar,
+            slave_states_condvar,
+            setup_condvar,
+            cmd_buff: tx,
+            cycle_period,
+            input_pdo_entries,
+            recorder: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(RwLock::new(BusMetrics::default())),
+            al_status: Arc::new(RwLock::new(HashMap::new())),
+            // no safety checks on a replayed log
+            safety: EthercatSafetyConfig {
+                enable_watchdog: false,
+                verify_mailbox_pdos: false,
+                stop_operation_on_error: false,
+            },
+            master_id: 0,
+            out_of_op: Arc::new(RwLock::new(HashMap::new())),
+            mailbox_pdo_entries: HashMap::new(),
+            mailbox_timestamps: Arc::new(RwLock::new(HashMap::new())),
+            esc_diagnostics: Arc::new(RwLock::new(HashMap::new())),
+            command_drop_time_us,
+        })
+    }
+
+    // start recording the process image of each cycle to the file
+    // (see the replay module for the file format)
+    pub fn start_recording(&self, path: &str) -> Result<(), io::Error> {
+        let header = ProcessImageHeader::new(
+            self.cycle_period,
+            &self.offsets,
+            &self.identities,
+            &self.input_pdo_entries,
+            &self.data_types,
+        );
+        let recorder = ProcessImageRecorder::create(path, &header)?;
+        *self.recorder.lock().unwrap() = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) {
+        if self.recorder.lock().unwrap().take().is_some() {
+            log::info!("Process image recording stopped");
+        }
+    }
+
+    pub fn get_safety_config(&self) -> &EthercatSafetyConfig {
+        &self.safety
+    }
+
+    // snapshot of the bus metrics (updated every 500ms)
+    pub fn get_bus_metrics(&self) -> BusMetrics {
+        self.metrics.read().unwrap().clone()
+    }
+
+    // link state and frame counters of the network devices (main and backup)
+    pub fn get_link_stats(&self) -> LinkStats {
+        self.metrics.read().unwrap().link.clone()
+    }
+
+    // AL status code of the slave if it refused the last state change
+    pub fn get_al_status_code(&self, slave_id: u16) -> Option<AlStatusCode> {
+        self.al_status
+            .read()
+            .unwrap()
+            .get(&slave_id)
+            .map(|status| status.code)
+    }
+
+    // error counters and port status of the ESC of the slave
+    // (None until the registers have been read or if the slave is not responding)
+    pub fn get_esc_diagnostics(&self, slave_id: u16) -> Option<EscDiagnostics> {
+        self.esc_diagnostics.read().unwrap().get(&slave_id).copied()
+    }
+
+    // request a state change of one slave (ex. PREOP for FoE/SDO configuration or to isolate it)
+    // and wait for the slave to confirm the transition
+    // - a slave taken out of OP is not considered a bus fault, the other slaves keep running
+    //   and the slave is not ready (is_slave_ready) until it is requested back to OP
+    // - a slave requested back to OP is confirmed once the EtherCAT loop sees it operational
+    //   and responding (mailbox and watchdog) again
+    pub fn request_slave_state(
+        &self,
+        slave_id: u16,
+        state: AlState,
+        timeout: Duration,
+    ) -> Result<(), io::Error> {
+        if slave_id as usize >= self.slave_names.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Slave {} not found", slave_id),
+            ));
+        }
+        let slave_pos = SlavePos::from(self.slave_positions[slave_id as usize]);
+
+        // mark the slave before requesting the change so that the loop never sees it as a fault
+        self.out_of_op.write().unwrap().insert(slave_id, state);
+        log::info!(
+            "Slave {:?} (pos: {}) requested to {:?}",
+            self.get_slave_name(slave_id),
+            u16::from(slave_pos),
+            state
+        );
+
+        // the request needs write access to the master
+        let mut master = Master::open(self.master_id, MasterAccess::ReadWrite)?;
+        master.request_state(slave_pos, state)?;
+
+        let start = std::time::Instant::now();
+        loop {
+            let reached = match state {
+                // removed from the list by the loop once verified
+                AlState::Op => !self.out_of_op.read().unwrap().contains_key(&slave_id),
+                _ => master
+                    .get_slave_info(slave_pos)
+                    .is_ok_and(|info| info.al_state == state),
+            };
+            if reached {
+                log::info!(
+                    "Slave {:?} (pos: {}) is in {:?}",
+                    self.get_slave_name(slave_id),
+                    u16::from(slave_pos),
+                    state
+                );
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                log::error!(
+                    "Slave {:?} (pos: {}) did not reach {:?} in {:?}, AL status code: {:?}",
+                    self.get_slave_name(slave_id),
+                    u16::from(slave_pos),
+                    state,
+                    timeout,
+                    self.get_al_status_code(slave_id)
+                );
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Slave {} did not reach {:?}", slave_id, state),
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // read an SDO entry of the slave (CoE), returns the number of bytes read
+    // - the request is processed by the master and blocks until the slave answers,
+    //   it must not be called from the EtherCAT loop thread
+    // - the poulpe boards only answer the SDO requests in PREOP (see request_slave_state)
+    pub fn sdo_upload(
+        &self,
+        slave_id: u16,
+        idx: u16,
+        sub_idx: u8,
+        data: &mut [u8],
+    ) -> Result<usize, io::Error> {
+        let slave_pos = SlavePos::from(self.get_slave_position(slave_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Slave {} not found", slave_id),
+            )
+        })?);
+        let master = Master::open(self.master_id, MasterAccess::ReadOnly)?;
+        let data = master.sdo_upload(slave_pos, SdoIdx::new(idx, sub_idx), false, data)?;
+        Ok(data.len())
+    }
+
+    // write an SDO entry of the slave (CoE), see sdo_upload
+    pub fn sdo_download<T: SdoData>(
+        &self,
+        slave_id: u16,
+        idx: u16,
+        sub_idx: u8,
+        value: &T,
+    ) -> Result<(), io::Error> {
+        let slave_pos = SlavePos::from(self.get_slave_position(slave_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Slave {} not found", slave_id),
+            )
+        })?);
+        // the request needs write access to the master
+        let mut master = Master::open(self.master_id, MasterAccess::ReadWrite)?;
+        master.sdo_download(slave_pos, SdoIdx::new(idx, sub_idx), false, value)?;
+        Ok(())
+    }
+
+    // slaves deliberately taken out of OP and their requested state
+    pub fn get_slaves_out_of_op(&self) -> HashMap<u16, AlState> {
+        self.out_of_op.read().unwrap().clone()
+    }
+
     pub fn get_slave_ids(&self) -> Vec<u16> {
         let mut ids: Vec<u16> = self
             .offsets
@@ -453,56 +1208,249 @@ impl EtherCatController {
         register: &String,
         index: usize,
     ) -> Option<Vec<u8>> {
-        let reg_addr_range = self.get_reg_addr_range(slave_id, register, index);
+        let (bit_len, offset) = self.get_entry(slave_id, register, index);
 
         (*self.data_lock.read().unwrap())
             .as_ref()
-            .map(|data| data[reg_addr_range].to_vec())
+            .map(|data| read_entry(data, &offset, bit_len))
     }
 
+    // the value of the entries shorter than a byte or not byte aligned is right aligned
+    // (the first bit of the entry is the bit 0 of the value), only the bits of the entry are written
     pub fn set_pdo_register(&self, slave_id: u16, register: &String, index: usize, value: Vec<u8>) {
-        let reg_addr_range = self.get_reg_addr_range(slave_id, register, index);
+        let (bit_len, offset) = self.get_entry(slave_id, register, index);
 
-        self.cmd_buff.send((reg_addr_range, value)).unwrap();
+        self.cmd_buff
+            .send(PdoWrite::new(&offset, bit_len, value))
+            .unwrap();
     }
 
     pub fn get_pdo_registers(&self, slave_id: u16, register: &String) -> Option<Vec<Vec<u8>>> {
-        let reg_addr_ranges = self.get_reg_addr_ranges(slave_id, register);
-
-        let vals = reg_addr_ranges
-            .iter()
-            .map(|reg_addr_range| {
-                (*self.data_lock.read().unwrap())
-                    .as_ref()
-                    .map(|data| data[reg_addr_range.clone()].to_vec())
-            })
-            .collect::<Option<Vec<Vec<u8>>>>()?;
-        Some(vals)
+        let entries = self.get_entries(slave_id, register);
+
+        let data = self.data_lock.read().unwrap();
+        let data = data.as_ref()?;
+        Some(
+            entries
+                .iter()
+                .map(|(bit_len, offset)| read_entry(data, offset, *bit_len))
+                .collect(),
+        )
     }
 
     pub fn set_pdo_registers(&self, slave_id: u16, register: &String, values: Vec<Vec<u8>>) {
-        let reg_addr_ranges = self.get_reg_addr_ranges(slave_id, register);
+        let entries = self.get_entries(slave_id, register);
 
-        if values.len() != reg_addr_ranges.len() {
+        if values.len() != entries.len() {
             // log::error!("values: {:?}", values);
             log::warn!(
                 "Values length does not match register count, using first {} elements!",
-                reg_addr_ranges.len()
+                entries.len()
             );
         }
 
-        for (reg_addr_range, v) in reg_addr_ranges.iter().zip(values) {
-            self.cmd_buff.send((reg_addr_range.clone(), v)).unwrap();
+        for ((bit_len, offset), v) in entries.iter().zip(values) {
+            self.cmd_buff
+                .send(PdoWrite::new(offset, *bit_len, v))
+                .unwrap();
+        }
+    }
+
+    // age of the value of the entry if it is served from the cache
+    // the mailbox PDOs are only written by the slave from time to time (ex. 10Hz), the last data received
+    // is kept and served until new data is received (only with the mailbox PDO verification)
+    // None if the entry is updated on every cycle
+    pub fn get_pdo_register_age(&self, slave_id: u16, register: &str) -> Option<Duration> {
+        let is_mailbox_entry = self
+            .mailbox_pdo_entries
+            .get(&SlavePos::from(slave_id))
+            .is_some_and(|entries| entries.iter().any(|e| e == register));
+        if !is_mailbox_entry {
+            return None;
+        }
+        self.mailbox_timestamps
+            .read()
+            .unwrap()
+            .get(&slave_id)
+            .map(|timestamp| timestamp.elapsed())
+    }
+
+    // value of the entry with the age of its cached value (see get_pdo_register_age)
+    pub fn get_with_age<T: PdoValue>(
+        &self,
+        slave_id: u16,
+        register: &str,
+        index: usize,
+    ) -> Result<(T, Option<Duration>), io::Error> {
+        let value = self.get::<T>(slave_id, register, index)?;
+        Ok((value, self.get_pdo_register_age(slave_id, register)))
+    }
+
+    // names of the PDO entries of the slave (sorted)
+    pub fn get_entry_names(&self, slave_id: u16) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .offsets
+            .get(&SlavePos::from(slave_id))
+            .map(|o| o.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    // true if the entry is an input (written by the slave)
+    pub fn is_input_entry(&self, slave_id: u16, register: &str) -> bool {
+        self.input_pdo_entries
+            .get(&SlavePos::from(slave_id))
+            .is_some_and(|inputs| inputs.iter().any(|i| i == register))
+    }
+
+    // data type of the entry (None if unknown)
+    pub fn get_entry_data_type(
+        &self,
+        slave_id: u16,
+        register: &str,
+        index: usize,
+    ) -> Option<DataType> {
+        let (pdo_entry_idx, _bit_len, _offset) = self
+            .offsets
+            .get(&SlavePos::from(slave_id))?
+            .get(register)?
+            .get(index)?;
+        self.data_types
+            .get(&SlavePos::from(slave_id))?
+            .get(pdo_entry_idx)
+            .copied()
+    }
+
+    // bit length and offset of the entry if it can hold a value of type T
+    // (error if the entry does not exist or if its data type or size do not match)
+    fn get_typed_entries<T: PdoValue>(
+        &self,
+        slave_id: u16,
+        register: &str,
+    ) -> Result<Vec<(u8, Offset)>, io::Error> {
+        let entries = self
+            .offsets
+            .get(&SlavePos::from(slave_id))
+            .and_then(|o| o.get(register))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Slave {} has no entry {:?}", slave_id, register),
+                )
+            })?;
+        let data_types = self.data_types.get(&SlavePos::from(slave_id));
+        entries
+            .iter()
+            .map(|(pdo_entry_idx, bit_len, offset)| {
+                let data_type = data_types.and_then(|t| t.get(pdo_entry_idx));
+                if data_type.is_some_and(|t| !T::DATA_TYPES.contains(t)) || !T::fits(*bit_len) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Slave {} entry {:?} is {} ({} bits), cannot be accessed as {}",
+                            slave_id,
+                            register,
+                            data_type.map_or("of unknown type".to_string(), |t| format!("{:?}", t)),
+                            bit_len,
+                            std::any::type_name::<T>()
+                        ),
+                    ));
+                }
+                Ok((*bit_len, *offset))
+            })
+            .collect()
+    }
+
+    // typed read of an entry, ex. get::<f32>(slave_id, "actual_position", 0)
+    pub fn get<T: PdoValue>(
+        &self,
+        slave_id: u16,
+        register: &str,
+        index: usize,
+    ) -> Result<T, io::Error> {
+        self.get_all::<T>(slave_id, register)?
+            .get(index)
+            .copied()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Slave {} has no entry {:?}[{}]", slave_id, register, index),
+                )
+            })
+    }
+
+    // typed read of all the entries with the same name
+    pub fn get_all<T: PdoValue>(&self, slave_id: u16, register: &str) -> Result<Vec<T>, io::Error> {
+        let entries = self.get_typed_entries::<T>(slave_id, register)?;
+        let data = self.data_lock.read().unwrap();
+        let data = data
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "No process data yet"))?;
+        Ok(entries
+            .iter()
+            .map(|(bit_len, offset)| T::from_le_slice(&read_entry(data, offset, *bit_len)))
+            .collect())
+    }
+
+    // typed write of an entry, ex. set::<u16>(slave_id, "controlword", 0, 0x0F)
+    pub fn set<T: PdoValue>(
+        &self,
+        slave_id: u16,
+        register: &str,
+        index: usize,
+        value: T,
+    ) -> Result<(), io::Error> {
+        let entries = self.get_typed_entries::<T>(slave_id, register)?;
+        let (bit_len, offset) = entries.get(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Slave {} has no entry {:?}[{}]", slave_id, register, index),
+            )
+        })?;
+        self.send_pdo_write(PdoWrite::new(offset, *bit_len, value.to_le_vec()))
+    }
+
+    // typed write of all the entries with the same name (one value per entry)
+    // as set_pdo_registers, only the first values are used if there are more values than entries
+    pub fn set_all<T: PdoValue>(
+        &self,
+        slave_id: u16,
+        register: &str,
+        values: &[T],
+    ) -> Result<(), io::Error> {
+        let entries = self.get_typed_entries::<T>(slave_id, register)?;
+        if values.len() != entries.len() {
+            log::warn!(
+                "Values length does not match register count, using first {} elements!",
+                entries.len()
+            );
         }
+        for ((bit_len, offset), v) in entries.iter().zip(values) {
+            self.send_pdo_write(PdoWrite::new(offset, *bit_len, v.to_le_vec()))?;
+        }
+        Ok(())
+    }
+
+    fn send_pdo_write(&self, pdo_write: PdoWrite) -> Result<(), io::Error> {
+        self.cmd_buff
+            .send(pdo_write)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "EtherCAT loop stopped"))
+    }
+
+    // number of the current EtherCAT cycle (incremented at each cycle, the writes made during a
+    // cycle are sent together at the next one)
+    pub fn get_cycle(&self) -> u64 {
+        *self.cycle_condvar.0.lock().unwrap()
     }
 
     pub fn wait_for_next_cycle(&self) {
         let (lock, cvar) = &*self.cycle_condvar;
-        let mut next_cycle = lock.lock().unwrap();
+        let mut cycle = lock.lock().unwrap();
 
-        *next_cycle = false;
-        while !*next_cycle {
-            next_cycle = cvar.wait(next_cycle).unwrap();
+        let current = *cycle;
+        while *cycle == current {
+            cycle = cvar.wait(cycle).unwrap();
         }
     }
 
@@ -546,6 +1494,24 @@ impl EtherCatController {
         self
     }
 
+    // same as wait_for_ready but gives up after the timeout
+    pub fn wait_for_ready_timeout(self, timeout: Duration) -> Result<Self, io::Error> {
+        {
+            let (lock, cvar) = &*self.ready_condvar;
+            let mut ready = lock.lock().unwrap();
+
+            *ready = false;
+            let (_ready, result) = cvar.wait_timeout_while(ready, timeout, |r| !*r).unwrap();
+            if result.timed_out() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("EtherCAT master not ready after {:?}", timeout),
+                ));
+            }
+        }
+        Ok(self)
+    }
+
     pub fn get_slave_setup(&self, slave_id: u16) -> bool {
         {
             let (lock, _cvar) = &*self.setup_condvar;
@@ -562,31 +1528,54 @@ impl EtherCatController {
         }
     }
 
-    fn get_reg_addr_range(&self, slave_id: u16, register: &String, index: usize) -> Range<usize> {
-        get_reg_addr_range(&self.offsets, slave_id, register, index)
+    // bit length and offset of the entry
+    fn get_entry(&self, slave_id: u16, register: &String, index: usize) -> (u8, Offset) {
+        let (_pdo_entry_idx, bit_len, offset) =
+            self.offsets[&SlavePos::from(slave_id)][register][index];
+        (bit_len, offset)
     }
 
-    fn get_reg_addr_ranges(&self, slave_id: u16, register: &String) -> Vec<Range<usize>> {
-        get_reg_addr_ranges(&self.offsets, slave_id, register)
+    fn get_entries(&self, slave_id: u16, register: &String) -> Vec<(u8, Offset)> {
+        self.offsets[&SlavePos::from(slave_id)][register]
+            .iter()
+            .map(|(_pdo_entry_idx, bit_len, offset)| (*bit_len, *offset))
+            .collect()
     }
 
     pub fn get_slave_name(&self, slave_id: u16) -> Option<String> {
-        self.slave_names
-            .iter()
-            .find(|(_, id)| u16::from(**id) == slave_id)
-            .map(|(name, _)| name.clone())
+        self.slave_names.get(&SlavePos::from(slave_id)).cloned()
     }
 
+    // None if no slave or more than one slave have this name (see find_slave)
     pub fn get_slave_id(&self, slave_name: &String) -> Option<u16> {
-        self.slave_names.get(slave_name).map(|id| u16::from(*id))
+        self.find_slave(&SlaveKey::Name(slave_name.clone())).ok()
     }
 
     pub fn get_slave_ids_and_names(&self) -> Vec<(u16, String)> {
         self.slave_names
             .iter()
-            .map(|(name, id)| (u16::from(*id), name.clone()))
+            .map(|(id, name)| (u16::from(*id), name.clone()))
             .collect()
     }
+
+    // id of the slave identified by the key (id, position, name, alias, serial number or DXL_ID)
+    // error if no slave or more than one slave match the key
+    pub fn find_slave(&self, key: &SlaveKey) -> Result<u16, io::Error> {
+        identity::find_slave(&self.identities, key)
+    }
+
+    pub fn get_slave_identity(&self, slave_id: u16) -> Option<&SlaveIdentity> {
+        self.identities.get(slave_id as usize)
+    }
+
+    // position of the slave on the bus (differs from its id with the station alias addressing)
+    pub fn get_slave_position(&self, slave_id: u16) -> Option<u16> {
+        self.slave_positions.get(slave_id as usize).copied()
+    }
+
+    pub fn get_slave_identities(&self) -> &[SlaveIdentity] {
+        &self.identities
+    }
 }
 
 pub fn get_reg_addr_range(
@@ -598,10 +1587,9 @@ pub fn get_reg_addr_range(
     let slave_pos = SlavePos::from(slave_id);
 
     let (_pdo_entry_idx, bit_len, offset) = offsets[&slave_pos][register][index];
-    let addr = offset.byte;
-    let bytes_len = (bit_len / 8) as usize;
 
-    addr..addr + bytes_len
+    // bytes covering the entry (including the entries shorter than a byte or not byte aligned)
+    entry_byte_range(&offset, bit_len)
 }
 
 fn get_reg_addr_ranges(
@@ -707,8 +1695,15 @@ pub fn init_master_for_foe(idx: u32) -> Result<Master, io::Error> {
     Ok(master)
 }
 
+// initialize the master and map the PDOs of the slaves
+// the PDOs and entries mapped for each slave are selected by the pdo_mapping configuration
+// (all the PDOs reported by the slave are mapped if no configuration matches)
+// the slaves listed in the slaves configuration are configured by station alias (see addressing)
+// everything is returned by slave id, with the position on the bus of each slave
 pub fn init_master(
     idx: u32,
+    pdo_mapping: &[PdoMappingConfig],
+    slaves: &[SlaveAddressConfig],
 ) -> Result<
     (
         Master,
@@ -716,6 +1711,9 @@ pub fn init_master(
         SlaveOffsets,
         SlaveNames,
         MailboxPdoEntries,
+        InputPdoEntries,
+        PdoDataTypes,
+        Vec<u16>,
     ),
     io::Error,
 > {
@@ -739,6 +1737,8 @@ pub fn init_master(
     let mut slave_names: SlaveNames = HashMap::new();
 
     let mut mailbox_pdos: MailboxPdoEntries = HashMap::new();
+    let mut input_pdos: InputPdoEntries = HashMap::new();
+    let mut data_types: PdoDataTypes = HashMap::new();
 
     let slave_num = master.get_info().unwrap().slave_count;
     log::info!("Found {:?} slaves", slave_num);
@@ -749,10 +1749,20 @@ pub fn init_master(
         return Err(io::Error::new(io::ErrorKind::Other, "No slaves found"));
     }
 
-    for i in 0..slave_num {
-        let slave_info = master.get_slave_info(SlavePos::from(i as u16)).unwrap();
-        log::info!("Slave {:?} at position {:?}", slave_info.name, i);
-        slave_names.insert(slave_info.name.clone(), SlavePos::from(i as u16));
+    // address of each slave (by position or by station alias)
+    let addresses = resolve_slave_addresses(&master, slave_num, slaves)?;
+
+    for (i, address) in addresses.iter().enumerate() {
+        let slave_pos = SlavePos::from(address.position);
+        let slave_info = master.get_slave_info(slave_pos).unwrap();
+        log::info!(
+            "Slave {:?} at position {:?} | id {}",
+            slave_info.name,
+            address.position,
+            i
+        );
+        // (keyed by slave id as the other slave maps)
+        slave_names.insert(SlavePos::from(i as u16), slave_info.name.clone());
         log::debug!("Found device {:?}", slave_info);
         log::debug!(
             "Vendor ID: {:X}, Product Code: {:X}, SM count {:?}",
@@ -760,20 +1770,35 @@ pub fn init_master(
             slave_info.id.product_code,
             slave_info.sync_count
         );
-        let slave_addr = SlaveAddr::ByPos(i as u16);
+        let slave_addr = address.addr;
         let slave_id = SlaveId {
             vendor_id: slave_info.id.vendor_id,
             product_code: slave_info.id.product_code,
         };
 
+        // PDO mapping selection of the slave (if any)
+        let mapping_config =
+            find_pdo_mapping_config(pdo_mapping, &slave_info.name, slave_info.id.product_code);
+        if let Some(c) = mapping_config {
+            log::info!(
+                "Slave {:?} at position {:?} | PDO mapping {:?}",
+                slave_info.name,
+                i,
+                c
+            );
+        }
+
         let mut pdos: Vec<Vec<PdoCfg>> = vec![];
         let mut sms = vec![];
         let mut mailbox = vec![];
         let mut direction = vec![];
         let mut mailbox_entires = vec![];
+        let mut input_entries = vec![];
+        // PDOs of all the sync managers, to check the mapping configuration once per slave
+        let mut all_pdos = vec![];
         for j in 0..slave_info.sync_count {
             let sm_idx = SmIdx::new(j);
-            let sm_info = master.get_sync(SlavePos::from(i as u16), sm_idx).unwrap();
+            let sm_info = master.get_sync(slave_pos, sm_idx).unwrap();
             log::debug!("Found sm {:?}, pdo_count {:?}", sm_info, sm_info.pdo_count);
 
             // sanity check
@@ -804,7 +1829,7 @@ pub fn init_master(
             for pdo_ind in 0..sm_info.pdo_count {
                 let pdo_cfg: PdoCfg = {
                     let pdo_info = master
-                        .get_pdo(SlavePos::from(i as u16), sm_idx, PdoPos::new(pdo_ind))
+                        .get_pdo(slave_pos, sm_idx, PdoPos::new(pdo_ind))
                         .unwrap();
                     log::debug!(
                         "Found pdo {:?}, entry_count {:?}",
@@ -816,7 +1841,7 @@ pub fn init_master(
                         .map(|e| {
                             let entry_info = master
                                 .get_pdo_entry(
-                                    SlavePos::from(i as u16),
+                                    slave_pos,
                                     sm_idx,
                                     PdoPos::new(pdo_ind),
                                     PdoEntryPos::new(e),
@@ -842,9 +1867,63 @@ pub fn init_master(
                 };
                 pdo_cfgs.push(pdo_cfg.clone());
             }
+            all_pdos.extend(pdo_cfgs.iter().cloned());
+            // keep only the selected PDOs and entries
+            if let Some(c) = mapping_config {
+                pdo_cfgs = select_pdos(c, pdo_cfgs);
+            }
             pdos.push(pdo_cfgs.clone());
             sms.push(sm_info);
         }
+        if let Some(c) = mapping_config {
+            check_pdo_mapping_config(c, i as u16, &slave_info.name, &all_pdos);
+        }
+
+        // data types of the mapped entries
+        // from the configuration if given, otherwise from the CoE dictionary of the slave
+        let mut entry_types = HashMap::new();
+        for entry in pdos.iter().flatten().flat_map(|pdo| pdo.entries.iter()) {
+            // padding entries
+            if u16::from(entry.entry_idx.idx) == 0 {
+                continue;
+            }
+            let configured = mapping_config.and_then(|c| c.data_types.get(&entry.name));
+            let data_type = match configured {
+                Some(name) => {
+                    let data_type = parse_data_type(name);
+                    if data_type.is_none() {
+                        log::warn!(
+                            "Slave {} | Unknown data type {:?} for entry {:?}",
+                            i,
+                            name,
+                            entry.name
+                        );
+                    }
+                    data_type
+                }
+                None => master
+                    .get_sdo_entry(
+                        slave_pos,
+                        SdoEntryAddr::ByIdx(SdoIdx {
+                            idx: entry.entry_idx.idx,
+                            sub_idx: entry.entry_idx.sub_idx,
+                        }),
+                    )
+                    .ok()
+                    .map(|info| info.data_type)
+                    .filter(|data_type| *data_type != DataType::Raw),
+            };
+            match data_type {
+                Some(data_type) => {
+                    entry_types.insert(entry.entry_idx, data_type);
+                }
+                None => log::debug!(
+                    "Slave {} | Data type of entry {:?} unknown, only its size is checked",
+                    i,
+                    entry.name
+                ),
+            }
+        }
 
         let mut config = master.configure_slave(slave_addr, slave_id)?;
         let mut entry_offsets: PdoOffsets = HashMap::new();
@@ -871,7 +1950,7 @@ pub fn init_master(
             for pdo in pds {
                 for entry in &pdo.entries {
                     let offset = config.register_pdo_entry(entry.entry_idx, domain_idx)?;
-                    let name = entry.name.clone();
+                    let name = entry_name(mapping_config, &entry.name);
                     if entry_offsets.contains_key(&name) {
                         entry_offsets.get_mut(&name).unwrap().push((
                             entry.entry_idx,
@@ -879,11 +1958,16 @@ pub fn init_master(
                             offset,
                         ));
                     } else {
-                        entry_offsets.insert(name, vec![(entry.entry_idx, entry.bit_len, offset)]);
+                        entry_offsets
+                            .insert(name.clone(), vec![(entry.entry_idx, entry.bit_len, offset)]);
                     }
                     if mailbox[i] && direction[i] < 0 {
                         // add the input mailbox to the list
-                        mailbox_entires.push(entry.name.clone());
+                        mailbox_entires.push(name.clone());
+                    }
+                    if direction[i] < 0 && !input_entries.contains(&name) {
+                        // add the input entry to the list
+                        input_entries.push(name);
                     }
                 }
             }
@@ -901,9 +1985,20 @@ pub fn init_master(
         }
         offsets.insert(SlavePos::new(i as u16), entry_offsets);
         mailbox_pdos.insert(SlavePos::new(i as u16), mailbox_entires);
+        input_pdos.insert(SlavePos::new(i as u16), input_entries);
+        data_types.insert(SlavePos::new(i as u16), entry_types);
     }
 
-    Ok((master, domain_idx, offsets, slave_names, mailbox_pdos))
+    Ok((
+        master,
+        domain_idx,
+        offsets,
+        slave_names,
+        mailbox_pdos,
+        input_pdos,
+        data_types,
+        addresses.iter().map(|a| a.position).collect(),
+    ))
 }
 
 // log the pdo offsets
@@ -929,11 +2024,9 @@ fn log_pdo_offsets(offsets: &SlaveOffsets) {
 fn create_slave_name_mapper(slave_names: SlaveNames) -> impl Fn(u16) -> String {
     move |id: u16| -> String {
         slave_names
-            .iter()
-            .find(|(_, sid)| u16::from(**sid) == id)
-            .unwrap()
-            .0
-            .clone()
+            .get(&SlavePos::from(id))
+            .cloned()
+            .unwrap_or_default()
     }
 }
 
@@ -953,35 +2046,47 @@ fn notify_slave_state(condvar: &Arc<(Mutex<Vec<u8>>, Condvar)>, state: Vec<u8>)
     cvar.notify_one();
 }
 
-fn notify_next_cycle(condvar: &Arc<(Mutex<bool>, Condvar)>) {
+// slave states as requested: OP for all the slaves except the ones deliberately out of OP
+// (the ones requested back to OP are reported 0 until they are verified)
+fn requested_slave_states(slave_number: u32, out_of_op: &HashMap<u16, AlState>) -> Vec<u8> {
+    (0..slave_number)
+        .map(|i| match out_of_op.get(&(i as u16)) {
+            None => AlState::Op as u8,
+            Some(AlState::Op) => 0,
+            Some(state) => *state as u8,
+        })
+        .collect()
+}
+
+// (cycle counter, so that several threads can wait for the next cycle)
+fn notify_next_cycle(condvar: &Arc<(Mutex<u64>, Condvar)>) {
     let (lock, cvar) = &**condvar;
-    let mut next_cycle = lock.lock().unwrap();
-    *next_cycle = true;
-    cvar.notify_one();
+    let mut cycle = lock.lock().unwrap();
+    *cycle = cycle.wrapping_add(1);
+    cvar.notify_all();
 }
 
 // Function to get the current state of a slave
 fn get_slave_current_state(
     master: &Master,
+    slave_id: u16,
     slave_pos: SlavePos,
     slave_name_from_id: &impl Fn(u16) -> String,
-    #[cfg(feature = "verify_mailbox_pdos")] slave_is_mailbox_pdo_responding: bool,
-    #[cfg(feature = "enable_watchdog")] slave_is_watchdog_responding: bool,
+    slave_is_mailbox_pdo_responding: bool,
+    slave_is_watchdog_responding: bool,
 ) -> u8 {
-    #[cfg(feature = "verify_mailbox_pdos")]
     if !slave_is_mailbox_pdo_responding {
         log::error!(
             "Slave {:?} (pos: {:?}) is not responding (mailbox check failed)!",
-            slave_name_from_id(slave_pos.into()),
+            slave_name_from_id(slave_id),
             slave_pos
         );
         return 0;
     }
-    #[cfg(feature = "enable_watchdog")]
     if !slave_is_watchdog_responding {
         log::error!(
             "Slave {:?} (pos: {:?}) is not responding (watchdog check failed)!",
-            slave_name_from_id(slave_pos.into()),
+            slave_name_from_id(slave_id),
             slave_pos
         );
         return 0;
@@ -1004,7 +2109,7 @@ fn get_slave_current_state(
             log::error!(
                 "Failed to get slave info for slave {:?}, name: {:?}",
                 slave_pos,
-                slave_name_from_id(slave_pos.into())
+                slave_name_from_id(slave_id)
             );
             255
         }
@@ -1014,13 +2119,14 @@ fn get_slave_current_state(
 // Function that logs the current state of the master
 fn log_master_state(
     master: &Master,
-    slave_number: u32,
-    #[cfg(feature = "verify_mailbox_pdos")] maibox_timeout_ms: u32,
-    #[cfg(feature = "enable_watchdog")] watchdog_timeout_ms: u32,
+    slave_positions: &[u16],
+    maibox_timeout_ms: u32,
     slave_name_from_id: &impl Fn(u16) -> String,
-    #[cfg(feature = "verify_mailbox_pdos")] slave_is_mailbox_pdo_responding: &Vec<bool>,
-    #[cfg(feature = "enable_watchdog")] slave_is_watchdog_responding: &Vec<bool>,
+    slave_mailbox_pdos: &[SlaveMailboxPdos],
+    slave_watchdogs: &[SlaveWatchdog],
+    al_status: &RwLock<HashMap<u16, AlStatus>>,
 ) {
+    let slave_number = slave_positions.len() as u32;
     let m_state = master.state().unwrap();
     log::debug!(
         "Master State: {:?}, Link up: {}, Slaves connected: {} out of {}",
@@ -1053,7 +2159,7 @@ fn log_master_state(
     // print the state of each slave
     log::info!("Connected slaves:");
     for i in 0..slave_number {
-        match master.get_slave_info(SlavePos::from(i as u16)) {
+        match master.get_slave_info(SlavePos::from(slave_positions[i as usize])) {
             Ok(info) => {
                 if info.al_state == AlState::Op {
                     log::info!(
@@ -1070,6 +2176,19 @@ fn log_master_state(
                         info.al_state
                     );
                 }
+                // reason of the refused state change (if already read)
+                if let Some(status) = al_status
+                    .try_read()
+                    .ok()
+                    .and_then(|s| s.get(&(i as u16)).copied())
+                {
+                    log::error!(
+                        "Slave {:?} (id: {}) AL status code: {}",
+                        info.name,
+                        i,
+                        status.code
+                    );
+                }
             }
             Err(_) => {
                 log::error!("Slave {:?} not connected!", i);
@@ -1078,11 +2197,10 @@ fn log_master_state(
     }
 
     // notify the operational state to the master
-    #[cfg(feature = "verify_mailbox_pdos")]
-    if !slave_is_mailbox_pdo_responding.iter().all(|&r| r) {
+    if !slave_mailbox_pdos.iter().all(|mb| mb.is_responding) {
         log::error!("Not all slaves are responding!");
-        for i in 0..slave_number {
-            if !slave_is_mailbox_pdo_responding[i as usize] {
+        for (i, mb) in slave_mailbox_pdos.iter().enumerate() {
+            if !mb.is_responding {
                 log::error!(
                     "Poulpe {:?} (pos: {:?}) not responding for more than {}ms",
                     slave_name_from_id(i as u16),
@@ -1093,16 +2211,15 @@ fn log_master_state(
         }
     }
 
-    #[cfg(feature = "enable_watchdog")]
-    if !slave_is_watchdog_responding.iter().all(|&r| r) {
+    if !slave_watchdogs.iter().all(|wd| wd.is_responding) {
         log::error!("Not all slaves have watchdog!");
-        for i in 0..slave_number {
-            if !slave_is_watchdog_responding[i as usize] {
+        for (i, wd) in slave_watchdogs.iter().enumerate() {
+            if !wd.is_responding {
                 log::error!(
                     "Poulpe {:?} (pos: {:?}) watchdog not responding for more than {}ms",
                     slave_name_from_id(i as u16),
                     i,
-                    watchdog_timeout_ms
+                    wd.timeout_ms
                 );
             }
         }
@@ -34,3 +34,152 @@ pub fn master_configure_sync(
     sync.default_size = sm.default_size;
     ioctl!(master, ec::ioctl::SLAVE_SYNC, &mut sync).map(|_| ())
 }
+
+// read the registers of a slave (ESC memory) starting at the address
+// the request is processed by the master thread, so it must not be called from the
+// thread running the EtherCAT loop once the master is activated (it would block the loop)
+pub fn slave_reg_read(
+    master_id: u32,
+    slave_pos: SlavePos,
+    address: u16,
+    data: &mut [u8],
+) -> Result<(), ethercat::Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(false)
+        .open(format!("/dev/EtherCAT{}", master_id))?;
+    let mut reg = ec::ec_ioctl_slave_reg_t {
+        slave_position: u16::from(slave_pos),
+        emergency: 0,
+        address,
+        size: data.len() as _,
+        data: data.as_mut_ptr(),
+    };
+    let res = unsafe { ec::ioctl::SLAVE_REG_READ(file.as_raw_fd(), &mut reg) };
+    if res < 0 {
+        Err(ethercat::Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+// read the master information (including the statistics of the network devices)
+// the request takes the master lock, so it should not be called from the thread running the EtherCAT loop
+pub fn master_info(master_id: u32) -> Result<ec::ec_ioctl_master_t, ethercat::Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(false)
+        .open(format!("/dev/EtherCAT{}", master_id))?;
+    let mut data = ec::ec_ioctl_master_t::default();
+    let res = unsafe { ec::ioctl::MASTER(file.as_raw_fd(), &mut data) };
+    if res < 0 {
+        Err(ethercat::Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(data)
+    }
+}
+
+// read only requests on the master, used to inspect the process data of the application owning
+// the master (see process_image), they never take the master lock of the application
+fn read_only_ioctl<T>(
+    master_id: u32,
+    request: unsafe fn(std::os::raw::c_int, *mut T) -> std::os::raw::c_int,
+    data: &mut T,
+) -> Result<(), ethercat::Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(false)
+        .open(format!("/dev/EtherCAT{}", master_id))?;
+    let res = unsafe { request(file.as_raw_fd(), data) };
+    if res < 0 {
+        Err(ethercat::Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn domain_info(
+    master_id: u32,
+    domain_idx: u32,
+) -> Result<ec::ec_ioctl_domain_t, ethercat::Error> {
+    let mut data = ec::ec_ioctl_domain_t {
+        index: domain_idx,
+        ..Default::default()
+    };
+    read_only_ioctl(master_id, ec::ioctl::DOMAIN, &mut data)?;
+    Ok(data)
+}
+
+pub fn domain_fmmu(
+    master_id: u32,
+    domain_idx: u32,
+    fmmu_idx: u32,
+) -> Result<ec::ec_ioctl_domain_fmmu_t, ethercat::Error> {
+    let mut data = ec::ec_ioctl_domain_fmmu_t {
+        domain_index: domain_idx,
+        fmmu_index: fmmu_idx,
+        ..Default::default()
+    };
+    read_only_ioctl(master_id, ec::ioctl::DOMAIN_FMMU, &mut data)?;
+    Ok(data)
+}
+
+// copy of the process data of the domain (the size of the domain)
+pub fn domain_data(
+    master_id: u32,
+    domain_idx: u32,
+    data: &mut [u8],
+) -> Result<(), ethercat::Error> {
+    let mut request = ec::ec_ioctl_domain_data_t {
+        domain_index: domain_idx,
+        data_size: data.len() as u32,
+        target: data.as_mut_ptr(),
+    };
+    read_only_ioctl(master_id, ec::ioctl::DOMAIN_DATA, &mut request)
+}
+
+pub fn config_info(
+    master_id: u32,
+    config_idx: u32,
+) -> Result<ec::ec_ioctl_config_t, ethercat::Error> {
+    let mut data = ec::ec_ioctl_config_t {
+        config_index: config_idx,
+        ..Default::default()
+    };
+    read_only_ioctl(master_id, ec::ioctl::CONFIG, &mut data)?;
+    Ok(data)
+}
+
+pub fn config_pdo(
+    master_id: u32,
+    config_idx: u32,
+    sync_idx: u8,
+    pdo_pos: u16,
+) -> Result<ec::ec_ioctl_config_pdo_t, ethercat::Error> {
+    let mut data = ec::ec_ioctl_config_pdo_t {
+        config_index: config_idx,
+        sync_index: sync_idx,
+        pdo_pos,
+        ..Default::default()
+    };
+    read_only_ioctl(master_id, ec::ioctl::CONFIG_PDO, &mut data)?;
+    Ok(data)
+}
+
+pub fn config_pdo_entry(
+    master_id: u32,
+    config_idx: u32,
+    sync_idx: u8,
+    pdo_pos: u16,
+    entry_pos: u8,
+) -> Result<ec::ec_ioctl_config_pdo_entry_t, ethercat::Error> {
+    let mut data = ec::ec_ioctl_config_pdo_entry_t {
+        config_index: config_idx,
+        sync_index: sync_idx,
+        pdo_pos,
+        entry_pos,
+        ..Default::default()
+    };
+    read_only_ioctl(master_id, ec::ioctl::CONFIG_PDO_ENTRY, &mut data)?;
+    Ok(data)
+}
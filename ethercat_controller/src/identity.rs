@@ -0,0 +1,186 @@
+// Identity of the slaves
+//
+// The SII name of a slave is not unique (ex. two boards of the same type), so the slaves
+// can also be identified by a stable key: the station alias, the serial number (SII) or
+// the DXL_ID (SDO 0x201:1 of the poulpe boards). The keys that are not set on a slave
+// (alias or serial number 0, DXL_ID not readable) never match.
+
+use std::{fmt, io, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SlaveIdentity {
+    // id of the slave (its index in the configuration)
+    pub id: u16,
+    // position on the bus (differs from the id with the station alias addressing)
+    pub position: u16,
+    pub name: String,
+    // station alias (0 if not set)
+    pub alias: u16,
+    // serial number of the SII (0 if not set)
+    pub serial_number: u32,
+    pub dxl_id: Option<u8>,
+}
+
+impl SlaveIdentity {
+    // true if the two slaves can be told apart by at least one of their keys
+    pub fn is_distinguishable_from(&self, other: &SlaveIdentity) -> bool {
+        self.name != other.name
+            || (self.alias != 0 && other.alias != 0 && self.alias != other.alias)
+            || (self.serial_number != 0
+                && other.serial_number != 0
+                && self.serial_number != other.serial_number)
+            || self.dxl_id.zip(other.dxl_id).is_some_and(|(a, b)| a != b)
+    }
+}
+
+// key used to find a slave
+// parsed from "id:<n>", "pos:<n>", "alias:<n>", "serial:<n>", "dxl:<n>" or "name:<name>"
+// (numbers in decimal or hexadecimal with 0x, any other string is a name)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SlaveKey {
+    Id(u16),
+    Position(u16),
+    Name(String),
+    Alias(u16),
+    SerialNumber(u32),
+    DxlId(u8),
+}
+
+impl SlaveKey {
+    pub fn matches(&self, identity: &SlaveIdentity) -> bool {
+        match self {
+            SlaveKey::Id(i) => identity.id == *i,
+            SlaveKey::Position(p) => identity.position == *p,
+            SlaveKey::Name(n) => identity.name == *n,
+            SlaveKey::Alias(a) => *a != 0 && identity.alias == *a,
+            SlaveKey::SerialNumber(s) => *s != 0 && identity.serial_number == *s,
+            SlaveKey::DxlId(d) => identity.dxl_id == Some(*d),
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, io::Error> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid slave key value {:?}", value),
+        )
+    })
+}
+
+impl FromStr for SlaveKey {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let out_of_range =
+            |_| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} out of range", s));
+        let key = match s.split_once(':') {
+            Some(("id", v)) => SlaveKey::Id(parse_number(v)?.try_into().map_err(out_of_range)?),
+            Some(("pos", v)) => {
+                SlaveKey::Position(parse_number(v)?.try_into().map_err(out_of_range)?)
+            }
+            Some(("alias", v)) => {
+                SlaveKey::Alias(parse_number(v)?.try_into().map_err(out_of_range)?)
+            }
+            Some(("serial", v)) => SlaveKey::SerialNumber(parse_number(v)?),
+            Some(("dxl", v)) => SlaveKey::DxlId(parse_number(v)?.try_into().map_err(out_of_range)?),
+            Some(("name", v)) => SlaveKey::Name(v.to_string()),
+            _ => SlaveKey::Name(s.to_string()),
+        };
+        Ok(key)
+    }
+}
+
+impl fmt::Display for SlaveKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlaveKey::Id(i) => write!(f, "id:{}", i),
+            SlaveKey::Position(p) => write!(f, "pos:{}", p),
+            SlaveKey::Name(n) => write!(f, "name:{}", n),
+            SlaveKey::Alias(a) => write!(f, "alias:{}", a),
+            SlaveKey::SerialNumber(s) => write!(f, "serial:0x{:08X}", s),
+            SlaveKey::DxlId(d) => write!(f, "dxl:{}", d),
+        }
+    }
+}
+
+// id of the slave matching the key
+// error if no slave or more than one slave match
+pub fn find_slave(identities: &[SlaveIdentity], key: &SlaveKey) -> Result<u16, io::Error> {
+    let mut matching = identities.iter().filter(|i| key.matches(i));
+    match (matching.next(), matching.next()) {
+        (Some(identity), None) => Ok(identity.id),
+        (None, _) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No slave matching {}", key),
+        )),
+        (Some(first), Some(second)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "More than one slave matching {} (ids {}, {}...)",
+                key, first.id, second.id
+            ),
+        )),
+    }
+}
+
+// report the slaves sharing the same name, alias, serial number or DXL_ID
+// returns false if some slaves cannot be told apart by any of their keys
+pub fn report_duplicates(identities: &[SlaveIdentity]) -> bool {
+    let mut all_distinguishable = true;
+    for (i, a) in identities.iter().enumerate() {
+        for b in identities.iter().skip(i + 1) {
+            if a.name == b.name {
+                if a.is_distinguishable_from(b) {
+                    log::warn!(
+                        "Slaves {} and {} have the same name {:?}, use their alias, serial number or DXL_ID to identify them ({:?} / {:?})",
+                        a.id,
+                        b.id,
+                        a.name,
+                        a,
+                        b
+                    );
+                } else {
+                    log::error!(
+                        "Slaves {} and {} have the same name {:?} and cannot be told apart (set a station alias or a DXL_ID)! ({:?} / {:?})",
+                        a.id,
+                        b.id,
+                        a.name,
+                        a,
+                        b
+                    );
+                    all_distinguishable = false;
+                }
+            }
+            if a.alias != 0 && a.alias == b.alias {
+                log::warn!(
+                    "Slaves {} and {} have the same station alias {}",
+                    a.id,
+                    b.id,
+                    a.alias
+                );
+            }
+            if a.serial_number != 0 && a.serial_number == b.serial_number {
+                log::warn!(
+                    "Slaves {} and {} have the same serial number 0x{:08X}",
+                    a.id,
+                    b.id,
+                    a.serial_number
+                );
+            }
+            if a.dxl_id.is_some() && a.dxl_id == b.dxl_id {
+                log::warn!(
+                    "Slaves {} and {} have the same DXL_ID {:?}",
+                    a.id,
+                    b.id,
+                    a.dxl_id
+                );
+            }
+        }
+    }
+    all_distinguishable
+}
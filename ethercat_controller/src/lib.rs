@@ -4,16 +4,34 @@ pub use config::Config;
 pub mod ethercat_controller;
 pub use ethercat_controller::EtherCatController;
 
-use ethercat::{Offset, PdoEntryIdx, SlavePos};
+use ethercat::{DataType, Offset, PdoEntryIdx, SlavePos};
+// state of the slaves (see EtherCatController::request_slave_state)
+pub use ethercat::AlState;
 use std::collections::HashMap;
 
 pub type PdoOffsets = HashMap<String, Vec<(PdoEntryIdx, u8, Offset)>>;
 pub type SlaveOffsets = HashMap<SlavePos, PdoOffsets>;
-pub type SlaveNames = HashMap<String, SlavePos>;
+// name of the slaves by id (the names are not necessarily unique, see identity)
+pub type SlaveNames = HashMap<SlavePos, String>;
 pub type SlaveSetup = HashMap<SlavePos, bool>;
 pub type MailboxPdoEntries = HashMap<SlavePos, Vec<String>>;
+pub type InputPdoEntries = HashMap<SlavePos, Vec<String>>;
+pub type PdoDataTypes = HashMap<SlavePos, HashMap<PdoEntryIdx, DataType>>;
 
+mod addressing;
+pub mod al_status;
+pub mod esc_diagnostics;
+pub mod identity;
+pub mod link;
+pub use identity::{SlaveIdentity, SlaveKey};
 pub mod mailboxes;
+pub mod metrics;
+mod pdo_entry;
+mod pdo_mapping;
+pub mod pdo_value;
+pub use pdo_value::PdoValue;
+pub mod process_image;
+pub mod replay;
 mod watchdog;
 
 pub mod ethercat_patch;
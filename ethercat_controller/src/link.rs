@@ -0,0 +1,108 @@
+// Link statistics of the EtherCAT master
+//
+// The master can use a backup network device for the ring redundancy. The backup device is set when
+// loading the master module (ex. MASTER0_BACKUP in /etc/sysconfig/ethercat), the configuration
+// file only gives the expected backup device so that the controller can verify it at startup.
+// The frame counters of each device are read with the MASTER ioctl (from a separate thread as it
+// takes the master lock), the link state and the slaves responding on each link are read by the
+// EtherCAT loop.
+
+use std::io;
+
+use crate::ethercat_patch::master_info;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceStats {
+    // 0 for the main device, 1 for the backup device
+    pub index: usize,
+    // MAC address of the device
+    pub address: String,
+    pub attached: bool,
+    pub link_up: bool,
+    // slaves responding on this link
+    pub slaves_responding: u32,
+    // frame and byte counters since the start of the master
+    pub tx_frames: u64,
+    pub rx_frames: u64,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub tx_errors: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkStats {
+    pub devices: Vec<DeviceStats>,
+    // frames sent and never received back (all the devices, since the start of the master)
+    pub lost_frames: u64,
+    // a backup device is used and one of the links is down or the ring is broken
+    // (the main link does not reach all the slaves anymore)
+    pub redundancy_degraded: bool,
+}
+
+pub fn format_mac(address: &[u8]) -> String {
+    address
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// read the frame counters of the network devices of the master
+// must not be called from the EtherCAT loop thread (see master_info)
+pub fn read_link_stats(master_id: u32) -> Result<LinkStats, ethercat::Error> {
+    let info = master_info(master_id)?;
+    let devices = info
+        .devices
+        .iter()
+        .take(info.num_devices as usize)
+        .enumerate()
+        .map(|(index, d)| DeviceStats {
+            index,
+            address: format_mac(&d.address),
+            attached: d.attached != 0,
+            link_up: d.link_state != 0,
+            slaves_responding: 0,
+            tx_frames: d.tx_count,
+            rx_frames: d.rx_count,
+            tx_bytes: d.tx_bytes,
+            rx_bytes: d.rx_bytes,
+            tx_errors: d.tx_errors,
+        })
+        .collect();
+    Ok(LinkStats {
+        devices,
+        lost_frames: info.tx_count.saturating_sub(info.rx_count),
+        redundancy_degraded: false,
+    })
+}
+
+// verify that the master uses the expected backup device (MAC address)
+pub fn check_backup_device(stats: &LinkStats, backup_device: &str) -> Result<(), io::Error> {
+    let backup = stats.devices.get(1).ok_or_else(|| {
+        log::error!(
+            "No backup device found, expected {}! Set it in the EtherCAT master configuration (ex. MASTER0_BACKUP) and check that the ethercat-sys bindings support it",
+            backup_device
+        );
+        io::Error::new(io::ErrorKind::NotFound, "No backup device")
+    })?;
+    if !backup.address.eq_ignore_ascii_case(backup_device) {
+        log::error!(
+            "Backup device is {}, expected {}!",
+            backup.address,
+            backup_device
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unexpected backup device {}", backup.address),
+        ));
+    }
+    if !backup.attached {
+        log::warn!("Backup device {} is not attached!", backup.address);
+    }
+    log::info!(
+        "Backup device {} (link up: {})",
+        backup.address,
+        backup.link_up
+    );
+    Ok(())
+}
@@ -1,14 +1,87 @@
-use std::{io, ops::Range};
+use std::{io, ops::Range, time::Instant};
 
 use ethercat::{Master, SdoData, SdoIdx};
 
+use crate::config::MailboxConfig;
 use crate::{MailboxPdoEntries, SlaveOffsets, SlavePos};
 
-// init the necessary variables for mailbox pdo verification
-// offsets of the mailboxes data in the domain data
-// last read timestamp of the mailbox data
-// flag to check if the slave is responding
-// buffer to store the mailbox data (that are read asynchronusly from the slaves)
+// how the master knows that the slave wrote new mailbox data
+//
+// NOTE:
+// - mailbox PDOs are different from the normal buffered PDOs as they are only updated once the slave writes to them
+//   and if the slave is not writing to them, the values will be read as zero
+#[derive(Debug, Clone, PartialEq)]
+pub enum MailboxLiveness {
+    // new data when the counter (or timestamp) entry changes
+    // a zero counter is read when the mailbox is empty, so it is never considered new data
+    Counter(Vec<Range<usize>>),
+    // new data when the mailbox data is not all zeros
+    // (a stuck non-zero value looks alive and a zero value looks dead, used only without counter entry)
+    NonZero,
+}
+
+// mailbox PDOs of one slave
+#[derive(Debug)]
+pub struct SlaveMailboxPdos {
+    // offsets of the mailboxes data in the domain data
+    offsets: Vec<Range<usize>>,
+    liveness: MailboxLiveness,
+    // last counter value received
+    previous_counter: Vec<u8>,
+    // last mailbox data received (copied to the domain data until new data is received)
+    buffer: Vec<Vec<u8>>,
+    // when the last mailbox data was received (or the start of the master)
+    pub timestamp: Instant,
+    pub is_responding: bool,
+}
+
+impl SlaveMailboxPdos {
+    pub fn has_mailbox_pdos(&self) -> bool {
+        !self.offsets.is_empty()
+    }
+
+    // true if the data contains new mailbox data
+    fn is_new_data(&mut self, data: &[u8], mailbox_data: &[Vec<u8>]) -> bool {
+        match &self.liveness {
+            MailboxLiveness::Counter(counter_offsets) => {
+                let counter = counter_offsets
+                    .iter()
+                    .flat_map(|range| data[range.clone()].iter().copied())
+                    .collect::<Vec<_>>();
+                if counter.iter().all(|&x| x == 0) || counter == self.previous_counter {
+                    return false;
+                }
+                self.previous_counter = counter;
+                true
+            }
+            MailboxLiveness::NonZero => !mailbox_data.iter().all(|d| d.iter().all(|&x| x == 0)),
+        }
+    }
+}
+
+// find the mailbox configuration of a slave
+// the first configuration matching the slave name and firmware version is used
+pub fn find_mailbox_config<'a>(
+    configs: &'a [MailboxConfig],
+    slave_name: &str,
+    firmware_version: Option<&str>,
+) -> Option<&'a MailboxConfig> {
+    configs.iter().find(|c| {
+        let name_matches = c
+            .slave_name
+            .as_ref()
+            .is_none_or(|n| slave_name.contains(n.as_str()));
+        let firmware_matches = c
+            .firmware_version
+            .as_ref()
+            .is_none_or(|f| firmware_version.is_some_and(|v| v.contains(f.as_str())));
+        name_matches && firmware_matches
+    })
+}
+
+// init the mailbox pdo verification of each slave
+// - offsets of the mailboxes data in the domain data
+// - liveness verification (counter entry from the configuration, or non zero data if none)
 //
 // NOTE:
 // - mailbox PDOs are different from the normal buffered PDOs and they are not always present
@@ -16,47 +89,99 @@ pub fn init_mailbox_pdo_verification(
     slave_number: u32,
     mailbox_pdo_entries: &MailboxPdoEntries,
     offsets: &SlaveOffsets,
+    configs: &[MailboxConfig],
+    slave_name_from_id: &impl Fn(u16) -> String,
+    firmware_versions: &[Option<String>],
     get_reg_addr_ranges: &impl Fn(&SlaveOffsets, u16, &String) -> Vec<Range<usize>>,
-) -> (
-    Vec<Vec<Range<usize>>>,
-    Vec<std::time::Instant>,
-    Vec<bool>,
-    Vec<Vec<Vec<u8>>>,
-) {
-    // initialize the mailbox verification variables
-    // offsets of the mailboxes data in the domain data
-    let mut slave_mailbox_pdo_offsets = vec![];
-    // last read timestamp of the mailbox data
-    let slave_mailbox_pdo_timestamps = vec![std::time::Instant::now(); slave_number as usize];
-    // flag to check if the slave is responding
-    let slave_is_mailbox_pdo_responding = vec![true; slave_number as usize];
-    // buffer to store the mailbox data (that are read asynchronusly from the slaves)
-    let slave_mailbox_pdo_data_buffer = vec![vec![]; slave_number as usize];
-
-    // find the mailbox offsets for each slave
+) -> Vec<SlaveMailboxPdos> {
+    let mut slave_mailbox_pdos = vec![];
+
     for i in 0..slave_number {
+        // find the mailbox offsets of the slave
         let mut mailbox_offsets = vec![];
         for m in mailbox_pdo_entries.get(&SlavePos::from(i as u16)).unwrap() {
-            mailbox_offsets.append(&mut get_reg_addr_ranges(&offsets, i as u16, m));
+            mailbox_offsets.append(&mut get_reg_addr_ranges(offsets, i as u16, m));
         }
-        slave_mailbox_pdo_offsets.push(mailbox_offsets);
+
+        let name = slave_name_from_id(i as u16);
+        let firmware_version = firmware_versions.get(i as usize).cloned().flatten();
+        let counter_entry = find_mailbox_config(configs, &name, firmware_version.as_deref())
+            .and_then(|c| c.counter_entry.clone());
+
+        let liveness = match counter_entry {
+            _ if mailbox_offsets.is_empty() => MailboxLiveness::NonZero,
+            Some(entry) => {
+                let exists = offsets
+                    .get(&SlavePos::from(i as u16))
+                    .is_some_and(|o| o.contains_key(&entry));
+                if exists {
+                    log::info!(
+                        "Slave {} ({}) | Mailbox PDOs liveness from the {:?} entry",
+                        i,
+                        name,
+                        entry
+                    );
+                    MailboxLiveness::Counter(get_reg_addr_ranges(offsets, i as u16, &entry))
+                } else {
+                    log::warn!(
+                        "Slave {} ({}) | No {:?} entry, mailbox PDOs liveness from non zero data!",
+                        i,
+                        name,
+                        entry
+                    );
+                    MailboxLiveness::NonZero
+                }
+            }
+            None => {
+                log::warn!(
+                    "Slave {} ({}) | No mailbox counter entry configured, mailbox PDOs liveness from non zero data!",
+                    i,
+                    name
+                );
+                MailboxLiveness::NonZero
+            }
+        };
+
+        slave_mailbox_pdos.push(SlaveMailboxPdos {
+            offsets: mailbox_offsets,
+            liveness,
+            previous_counter: vec![],
+            buffer: vec![],
+            timestamp: Instant::now(),
+            is_responding: true,
+        });
     }
 
-    (
-        slave_mailbox_pdo_offsets,
-        slave_mailbox_pdo_timestamps,
-        slave_is_mailbox_pdo_responding,
-        slave_mailbox_pdo_data_buffer,
-    )
+    slave_mailbox_pdos
+}
+
+// log an error if the verification is enabled and some slaves have no counter entry
+// (the poulpe firmware has no counter entry in its mailbox PDOs)
+// the heuristic cannot tell a slave stuck on non zero data from a responding one
+pub fn report_slaves_without_counter(
+    slave_mailbox_pdos: &[SlaveMailboxPdos],
+    verify_mailbox_pdos: bool,
+) {
+    let without_counter = slave_mailbox_pdos
+        .iter()
+        .enumerate()
+        .filter(|(_, mb)| mb.has_mailbox_pdos() && mb.liveness == MailboxLiveness::NonZero)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if verify_mailbox_pdos && !without_counter.is_empty() {
+        log::error!(
+            "Mailbox PDOs verification without counter entry for the slaves {:?}: a slave stuck on non zero data is seen as responding and zero data as not responding! Set the `counter_entry` of the `mailbox` configuration",
+            without_counter
+        );
+    }
 }
 
 // verify the mailboxe pdos of the slaves (if they are available)
 // verify that the slaves are still writing
-// checking if all the mailbox values are zero for more than 1s
-// - if the values are not zero, update the timestamp
-// - if the values are zero, check if the timestamp is more than 1s
-//      - if the timestamp is more than 1s, set the slave as not responding
-// - if the values are not zero, update the timestamp
+// - if the slave wrote new data (see MailboxLiveness), buffer the data and update the timestamp
+// - if not, check if the timestamp is older than the mailbox wait time
+//      - if it is, set the slave as not responding
+// - copy the buffered data to the domain data (the readers always see the last data received)
 //
 // NOTE:
 //  - mailbox PDOs are different from the normal buffered PDOs as they are only updated once the slave writes to them
@@ -64,65 +189,44 @@ pub fn init_mailbox_pdo_verification(
 //  - therefore this function is used to check if the slaves are still writing to the mailbox PDOs
 //    and if they are the mailbox pdo data is buffered and copied to the domain data
 pub fn verify_mailbox_pdos(
-    slave_number: u32,
     data: &mut [u8],
-    slave_mailbox_pdo_offsets: &mut Vec<Vec<Range<usize>>>,
-    slave_mailbox_pdo_timestamps: &mut Vec<std::time::Instant>,
-    slave_is_mailbox_pdo_responding: &mut Vec<bool>,
-    slave_mailbox_pdo_data_buffer: &mut Vec<Vec<Vec<u8>>>,
+    slave_mailbox_pdos: &mut [SlaveMailboxPdos],
     mailbox_wait_time_ms: u32,
 ) -> bool {
     // return if all slaves responding
     let mut all_slaves_responding = true;
 
     // check each slave
-    for i in 0..slave_number {
-        // get slave mailbox offset
-        let offset = slave_mailbox_pdo_offsets[i as usize].clone();
-
-        if offset.is_empty() {
+    for mb in slave_mailbox_pdos.iter_mut() {
+        if !mb.has_mailbox_pdos() {
             // if there are no mailbox pdos for the slave, continue
             continue;
         }
         // get the mailbox data
-        let mailbox_data = offset
+        let mailbox_data = mb
+            .offsets
             .iter()
             .map(|range| data[range.clone()].to_vec())
             .collect::<Vec<_>>();
         log::debug!("{:?}", mailbox_data);
-        // check if all the values are zero
-        let is_all_zeros = mailbox_data.iter().all(|d| d.iter().all(|&x| x == 0));
-
-        // flag to check if slave is responding
-        slave_is_mailbox_pdo_responding[i as usize] = true;
-        // if all the values are zero for more than 1s
-        if is_all_zeros {
-            if slave_mailbox_pdo_timestamps[i as usize]
-                .elapsed()
-                .as_millis() as u32
-                > mailbox_wait_time_ms
-            {
-                all_slaves_responding &= false; // set the all slaves responding flag to false
-                slave_is_mailbox_pdo_responding[i as usize] = false;
-            }
-        } else {
-            // if the values are not zero
-            slave_mailbox_pdo_timestamps[i as usize] = std::time::Instant::now();
-            slave_is_mailbox_pdo_responding[i as usize] = true;
-            slave_mailbox_pdo_data_buffer[i as usize] = mailbox_data;
+
+        if mb.is_new_data(data, &mailbox_data) {
+            mb.timestamp = Instant::now();
+            mb.is_responding = true;
+            mb.buffer = mailbox_data;
+        } else if mb.timestamp.elapsed().as_millis() as u32 > mailbox_wait_time_ms {
+            all_slaves_responding = false;
+            mb.is_responding = false;
         }
 
-        if slave_is_mailbox_pdo_responding[i as usize] {
-            for (j, range) in offset.iter().enumerate() {
-                if slave_mailbox_pdo_data_buffer[i as usize].len() > j {
-                    data[range.clone()]
-                        .copy_from_slice(&slave_mailbox_pdo_data_buffer[i as usize][j]);
-                }
+        if mb.is_responding {
+            for (range, buffered) in mb.offsets.iter().zip(&mb.buffer) {
+                data[range.clone()].copy_from_slice(buffered);
             }
         }
     }
 
-    return all_slaves_responding;
+    all_slaves_responding
 }
 
 // write to the mailbox sdo
@@ -156,3 +260,75 @@ pub fn mailbox_sdo_read(
     master.sdo_upload(sdo_pos, sdo_idx, false, data)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // slave with one mailbox PDO entry in the byte 1 of the data
+    fn mailbox_pdos(liveness: MailboxLiveness) -> SlaveMailboxPdos {
+        SlaveMailboxPdos {
+            offsets: vec![1..2],
+            liveness,
+            previous_counter: vec![],
+            buffer: vec![],
+            timestamp: Instant::now(),
+            is_responding: true,
+        }
+    }
+
+    // is_new_data for the data [counter, mailbox data]
+    fn is_new_data(mb: &mut SlaveMailboxPdos, counter: u8, mailbox: u8) -> bool {
+        mb.is_new_data(&[counter, mailbox], &[vec![mailbox]])
+    }
+
+    #[test]
+    fn counter_wrap_to_zero() {
+        let mut mb = mailbox_pdos(MailboxLiveness::Counter(vec![0..1]));
+        assert!(is_new_data(&mut mb, 254, 7));
+        assert!(is_new_data(&mut mb, 255, 7));
+        // the zero counter is the empty mailbox, the sample is skipped
+        assert!(!is_new_data(&mut mb, 0, 7));
+        assert!(is_new_data(&mut mb, 1, 7));
+        assert!(!is_new_data(&mut mb, 1, 7));
+    }
+
+    #[test]
+    fn stuck_non_zero_data() {
+        // detected with a counter
+        let mut mb = mailbox_pdos(MailboxLiveness::Counter(vec![0..1]));
+        assert!(is_new_data(&mut mb, 3, 7));
+        for _ in 0..5 {
+            assert!(!is_new_data(&mut mb, 3, 7));
+        }
+
+        // not detected by the non zero heuristic
+        let mut mb = mailbox_pdos(MailboxLiveness::NonZero);
+        for _ in 0..5 {
+            assert!(is_new_data(&mut mb, 0, 7));
+        }
+    }
+
+    #[test]
+    fn legitimately_zero_data() {
+        // accepted with a counter
+        let mut mb = mailbox_pdos(MailboxLiveness::Counter(vec![0..1]));
+        for counter in 1..5 {
+            assert!(is_new_data(&mut mb, counter, 0));
+        }
+
+        // seen as an empty mailbox by the non zero heuristic
+        let mut mb = mailbox_pdos(MailboxLiveness::NonZero);
+        assert!(!is_new_data(&mut mb, 1, 0));
+    }
+
+    #[test]
+    fn multi_byte_counter() {
+        let mut mb = mailbox_pdos(MailboxLiveness::Counter(vec![0..1, 2..3]));
+        assert!(mb.is_new_data(&[0, 5, 1], &[vec![5]]));
+        assert!(!mb.is_new_data(&[0, 6, 1], &[vec![6]]));
+        assert!(mb.is_new_data(&[1, 6, 0], &[vec![6]]));
+        // all the bytes at zero
+        assert!(!mb.is_new_data(&[0, 6, 0], &[vec![6]]));
+    }
+}
@@ -0,0 +1,133 @@
+// Metrics of the EtherCAT bus
+//
+// The EtherCAT loop accumulates the loop timing and the working counter errors
+// in LoopMetrics and publishes a snapshot (BusMetrics) every METRICS_PUBLISH_PERIOD.
+// The snapshot is published with a try_write so that the loop never blocks on the readers.
+
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use crate::al_status::AlStatusCode;
+use crate::link::LinkStats;
+
+// period at which the metrics snapshot is published by the EtherCAT loop
+const METRICS_PUBLISH_PERIOD: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default)]
+pub struct SlaveMetrics {
+    pub position: u16,
+    pub name: String,
+    // AL state of the slave (0 if unknown)
+    pub al_state: u8,
+    // reason of the last refused state change (None if no error)
+    pub al_status_code: Option<AlStatusCode>,
+    // time since the last watchdog counter change (None if no watchdog)
+    pub watchdog_age_ms: Option<f32>,
+    // time since the last mailbox PDO update (None if no mailbox PDOs)
+    pub mailbox_age_ms: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BusMetrics {
+    // number of cycles since the start of the master
+    pub cycles: u64,
+    // loop frequency and jitter over the last publish period
+    pub loop_frequency_hz: f32,
+    pub jitter_mean_us: f32,
+    pub jitter_max_us: f32,
+    // number of cycles that took longer than the cycle period (since the start)
+    pub overruns: u64,
+    // number of cycles with an incomplete working counter (since the start)
+    pub wkc_errors: u64,
+    pub working_counter: u32,
+    pub link_up: bool,
+    pub operational: bool,
+    pub slaves: Vec<SlaveMetrics>,
+    // network devices of the master (main and backup)
+    pub link: LinkStats,
+}
+
+// accumulates the metrics in the EtherCAT loop
+#[derive(Debug)]
+pub struct LoopMetrics {
+    cycle_period: Duration,
+    last_cycle: Option<Instant>,
+    window_start: Instant,
+    window_cycles: u64,
+    jitter_sum_us: f64,
+    jitter_max_us: f32,
+    total: BusMetrics,
+}
+
+impl LoopMetrics {
+    pub fn new(cycle_period: Duration) -> Self {
+        LoopMetrics {
+            cycle_period,
+            last_cycle: None,
+            window_start: Instant::now(),
+            window_cycles: 0,
+            jitter_sum_us: 0.0,
+            jitter_max_us: 0.0,
+            total: BusMetrics::default(),
+        }
+    }
+
+    // to be called at the start of each cycle
+    // overrun is true if the previous cycle took longer than the cycle period
+    pub fn start_cycle(&mut self, overrun: bool) {
+        let now = Instant::now();
+        if let Some(last_cycle) = self.last_cycle {
+            let period_us = now.duration_since(last_cycle).as_secs_f32() * 1e6;
+            let jitter_us = (period_us - self.cycle_period.as_secs_f32() * 1e6).abs();
+            self.jitter_sum_us += jitter_us as f64;
+            self.jitter_max_us = self.jitter_max_us.max(jitter_us);
+            if overrun {
+                self.total.overruns += 1;
+            }
+        }
+        self.last_cycle = Some(now);
+        self.window_cycles += 1;
+        self.total.cycles += 1;
+    }
+
+    pub fn working_counter(&mut self, working_counter: u32, complete: bool) {
+        self.total.working_counter = working_counter;
+        if !complete {
+            self.total.wkc_errors += 1;
+        }
+    }
+
+    pub fn should_publish(&self) -> bool {
+        self.window_start.elapsed() >= METRICS_PUBLISH_PERIOD
+    }
+
+    // publish the snapshot and start a new window
+    pub fn publish(
+        &mut self,
+        metrics: &RwLock<BusMetrics>,
+        link_up: bool,
+        operational: bool,
+        slaves: Vec<SlaveMetrics>,
+        link: LinkStats,
+    ) {
+        let elapsed = self.window_start.elapsed().as_secs_f32();
+        self.total.loop_frequency_hz = self.window_cycles as f32 / elapsed;
+        self.total.jitter_mean_us = (self.jitter_sum_us / self.window_cycles.max(1) as f64) as f32;
+        self.total.jitter_max_us = self.jitter_max_us;
+        self.total.link_up = link_up;
+        self.total.operational = operational;
+        self.total.slaves = slaves;
+        self.total.link = link;
+
+        if let Ok(mut m) = metrics.try_write() {
+            *m = self.total.clone();
+        }
+
+        self.window_start = Instant::now();
+        self.window_cycles = 0;
+        self.jitter_sum_us = 0.0;
+        self.jitter_max_us = 0.0;
+    }
+}
@@ -0,0 +1,215 @@
+// Bit level access to the PDO entries in the process image
+//
+// The entries are not necessarily byte aligned nor a multiple of 8 bits long
+// (ex. BOOL, BIT2...BIT8 entries of the digital IO terminals or packed status entries).
+// The values are exchanged with the application right aligned (the first bit of the entry
+// is the bit 0 of the first byte), and the outputs are written with a read-modify-write
+// so that the entries sharing a byte are not overwritten.
+
+use std::ops::Range;
+
+use ethercat::Offset;
+
+// bytes of the process image covered by the entry
+pub fn entry_byte_range(offset: &Offset, bit_len: u8) -> Range<usize> {
+    let bytes_len = (offset.bit as usize + bit_len as usize).div_ceil(8).max(1);
+    offset.byte..offset.byte + bytes_len
+}
+
+// true if the entry can be accessed as bytes (byte aligned and multiple of 8 bits long)
+fn is_byte_aligned(offset: &Offset, bit_len: u8) -> bool {
+    offset.bit == 0 && bit_len.is_multiple_of(8)
+}
+
+// read the value of the entry (right aligned, ceil(bit_len / 8) bytes)
+pub fn read_entry(data: &[u8], offset: &Offset, bit_len: u8) -> Vec<u8> {
+    if is_byte_aligned(offset, bit_len) {
+        return data[offset.byte..offset.byte + bit_len as usize / 8].to_vec();
+    }
+    let mut value = vec![0u8; (bit_len as usize).div_ceil(8).max(1)];
+    for k in 0..bit_len as usize {
+        let bit = offset.bit as usize + k;
+        if data[offset.byte + bit / 8] & (1 << (bit % 8)) != 0 {
+            value[k / 8] |= 1 << (k % 8);
+        }
+    }
+    value
+}
+
+// write request of an entry sent to the EtherCAT loop
+#[derive(Debug, Clone)]
+pub struct PdoWrite {
+    // bytes of the process image to write
+    pub range: Range<usize>,
+    pub value: Vec<u8>,
+    // bits of the bytes to write (None if all the bits are written)
+    pub mask: Option<Vec<u8>>,
+}
+
+impl PdoWrite {
+    // value given right aligned (as returned by read_entry)
+    pub fn new(offset: &Offset, bit_len: u8, value: Vec<u8>) -> Self {
+        if is_byte_aligned(offset, bit_len) {
+            return PdoWrite {
+                range: offset.byte..offset.byte + bit_len as usize / 8,
+                value,
+                mask: None,
+            };
+        }
+        let range = entry_byte_range(offset, bit_len);
+        let mut shifted = vec![0u8; range.len()];
+        let mut mask = vec![0u8; range.len()];
+        for k in 0..bit_len as usize {
+            let bit = offset.bit as usize + k;
+            mask[bit / 8] |= 1 << (bit % 8);
+            if value.get(k / 8).is_some_and(|v| v & (1 << (k % 8)) != 0) {
+                shifted[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        PdoWrite {
+            range,
+            value: shifted,
+            mask: Some(mask),
+        }
+    }
+
+    // write the value to the process image
+    // only the bits of the entry are modified
+    pub fn apply(&self, data: &mut [u8]) {
+        match &self.mask {
+            None => data[self.range.clone()].copy_from_slice(&self.value),
+            Some(mask) => {
+                for ((d, v), m) in data[self.range.clone()]
+                    .iter_mut()
+                    .zip(&self.value)
+                    .zip(mask)
+                {
+                    *d = (*d & !m) | (v & m);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(byte: usize, bit: u32) -> Offset {
+        Offset { byte, bit }
+    }
+
+    // write the value to a copy of the data and read it back
+    fn write(data: &[u8], offset: &Offset, bit_len: u8, value: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        PdoWrite::new(offset, bit_len, value.to_vec()).apply(&mut data);
+        data
+    }
+
+    #[test]
+    fn byte_aligned_entry() {
+        let data = [0x11, 0x22, 0x33, 0x44];
+        assert_eq!(read_entry(&data, &offset(1, 0), 16), vec![0x22, 0x33]);
+
+        let w = PdoWrite::new(&offset(1, 0), 16, vec![0xAA, 0xBB]);
+        assert!(w.mask.is_none());
+        assert_eq!(w.range, 1..3);
+        assert_eq!(
+            write(&data, &offset(1, 0), 16, &[0xAA, 0xBB]),
+            vec![0x11, 0xAA, 0xBB, 0x44]
+        );
+    }
+
+    #[test]
+    fn unaligned_bool() {
+        for bit in 0..8 {
+            let data = [0x00, 1 << bit, 0x00];
+            assert_eq!(read_entry(&data, &offset(1, bit), 1), vec![1]);
+            assert_eq!(
+                read_entry(&[0x00, !(1 << bit), 0x00], &offset(1, bit), 1),
+                vec![0]
+            );
+
+            let w = PdoWrite::new(&offset(1, bit), 1, vec![1]);
+            assert_eq!(w.range, 1..2);
+            assert_eq!(w.mask, Some(vec![1 << bit]));
+            assert_eq!(
+                write(&[0; 3], &offset(1, bit), 1, &[1]),
+                vec![0, 1 << bit, 0]
+            );
+            assert_eq!(
+                write(&[0xFF; 3], &offset(1, bit), 1, &[0]),
+                vec![0xFF, !(1 << bit), 0xFF]
+            );
+        }
+    }
+
+    #[test]
+    fn bit_strings() {
+        // BIT2..BIT8 at every bit offset of the byte (crossing into the next byte or not)
+        for bit_len in 2..=8u8 {
+            let max = ((1u16 << bit_len) - 1) as u8;
+            for bit in 0..8 {
+                for value in [0, 1, max / 2, max] {
+                    let data = write(&[0; 3], &offset(0, bit), bit_len, &[value]);
+                    assert_eq!(
+                        read_entry(&data, &offset(0, bit), bit_len),
+                        vec![value],
+                        "BIT{} at bit {} value {:#x}",
+                        bit_len,
+                        bit,
+                        value
+                    );
+                    let expected = (value as u32) << bit;
+                    assert_eq!(data, vec![expected as u8, (expected >> 8) as u8, 0]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn entry_crossing_a_byte_boundary() {
+        // 12 bits entry starting at bit 6 of byte 1: bits 6..8 of byte 1, byte 2, bits 0..2 of byte 3
+        let o = offset(1, 6);
+        assert_eq!(entry_byte_range(&o, 12), 1..4);
+
+        let data = write(&[0; 5], &o, 12, &[0x5A, 0x0C]);
+        assert_eq!(data, vec![0x00, 0x80, 0x16, 0x03, 0x00]);
+        assert_eq!(read_entry(&data, &o, 12), vec![0x5A, 0x0C]);
+
+        // 16 bits entry not byte aligned
+        let o = offset(0, 4);
+        assert_eq!(entry_byte_range(&o, 16), 0..3);
+        let data = write(&[0; 3], &o, 16, &[0x34, 0x12]);
+        assert_eq!(data, vec![0x40, 0x23, 0x01]);
+        assert_eq!(read_entry(&data, &o, 16), vec![0x34, 0x12]);
+    }
+
+    #[test]
+    fn neighbouring_bits_preserved() {
+        // BIT3 at bit 3 of byte 0: bits 0..3 and 6..8 of the byte are not modified
+        let data = write(&[0xFF, 0xFF], &offset(0, 3), 3, &[0b010]);
+        assert_eq!(data, vec![0b1101_0111, 0xFF]);
+        let data = write(&[0x00, 0x00], &offset(0, 3), 3, &[0b101]);
+        assert_eq!(data, vec![0b0010_1000, 0x00]);
+
+        // entry crossing a byte boundary: the bits around it in both bytes are not modified
+        let data = write(&[0xFF, 0xFF, 0xFF], &offset(0, 6), 4, &[0]);
+        assert_eq!(data, vec![0x3F, 0xFC, 0xFF]);
+        let data = write(&[0x00, 0x00, 0x00], &offset(0, 6), 4, &[0x0F]);
+        assert_eq!(data, vec![0xC0, 0x03, 0x00]);
+
+        // bits of the value beyond the length of the entry are ignored
+        let data = write(&[0x00, 0x00], &offset(0, 2), 2, &[0xFF]);
+        assert_eq!(data, vec![0b0000_1100, 0x00]);
+
+        // consecutive BOOL entries written one by one
+        let mut data = vec![0u8; 1];
+        for bit in [0, 2, 5, 7] {
+            PdoWrite::new(&offset(0, bit), 1, vec![1]).apply(&mut data);
+        }
+        assert_eq!(data, vec![0b1010_0101]);
+        PdoWrite::new(&offset(0, 2), 1, vec![0]).apply(&mut data);
+        assert_eq!(data, vec![0b1010_0001]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::PdoMappingConfig;
+use ethercat::PdoCfg;
+
+// find the PDO mapping configuration of a slave
+// the first configuration matching the slave name and product code is used
+// if none match, all the PDOs reported by the slave are mapped
+pub fn find_pdo_mapping_config<'a>(
+    configs: &'a [PdoMappingConfig],
+    slave_name: &str,
+    product_code: u32,
+) -> Option<&'a PdoMappingConfig> {
+    configs.iter().find(|c| {
+        let name_matches = c
+            .slave_name
+            .as_ref()
+            .is_none_or(|n| slave_name.contains(n.as_str()));
+        let product_matches = c.product_code.is_none_or(|p| p == product_code);
+        name_matches && product_matches
+    })
+}
+
+// select the PDOs and the entries of a sync manager to map
+// - only the PDOs listed in the configuration are kept (all if not set)
+// - only the entries listed in the configuration are kept (all if not set)
+// - the PDOs left without entries are not mapped
+pub fn select_pdos(config: &PdoMappingConfig, pdos: Vec<PdoCfg>) -> Vec<PdoCfg> {
+    pdos.into_iter()
+        .filter(|pdo| {
+            config
+                .pdos
+                .as_ref()
+                .is_none_or(|p| p.contains(&u16::from(pdo.idx)))
+        })
+        .map(|mut pdo| {
+            if let Some(entries) = &config.entries {
+                pdo.entries.retain(|e| entries.contains(&e.name));
+            }
+            pdo
+        })
+        .filter(|pdo| !pdo.entries.is_empty())
+        .collect()
+}
+
+// warn about the PDOs and entries of the configuration that the slave does not have
+// (most probably a typo or a different firmware version)
+// all_pdos are the PDOs of all the sync managers of the slave
+pub fn check_pdo_mapping_config(
+    config: &PdoMappingConfig,
+    slave_pos: u16,
+    slave_name: &str,
+    all_pdos: &[PdoCfg],
+) {
+    for idx in config.pdos.iter().flatten() {
+        if !all_pdos.iter().any(|pdo| u16::from(pdo.idx) == *idx) {
+            log::warn!(
+                "Slave {} ({}) | PDO 0x{:X} not found, not mapped!",
+                slave_pos,
+                slave_name,
+                idx
+            );
+        }
+    }
+    let has_entry = |name: &String| {
+        all_pdos
+            .iter()
+            .any(|pdo| pdo.entries.iter().any(|e| &e.name == name))
+    };
+    for name in config.entries.iter().flatten() {
+        if !has_entry(name) {
+            log::warn!(
+                "Slave {} ({}) | Entry {:?} not found, not mapped!",
+                slave_pos,
+                slave_name,
+                name
+            );
+        }
+    }
+    for name in config.rename.keys() {
+        if !has_entry(name) {
+            log::warn!(
+                "Slave {} ({}) | Entry {:?} not found, cannot be renamed!",
+                slave_pos,
+                slave_name,
+                name
+            );
+        }
+    }
+}
+
+// name of the entry used by the application (renamed if configured)
+pub fn entry_name(config: Option<&PdoMappingConfig>, name: &str) -> String {
+    config
+        .and_then(|c| c.rename.get(name))
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
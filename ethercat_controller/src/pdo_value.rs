@@ -0,0 +1,174 @@
+// Typed access to the PDO entries
+//
+// The data type of each entry is read from the CoE dictionary of the slave when the master
+// is configured (or given in the pdo_mapping configuration with the ESI type names).
+// The typed accessors of the EtherCatController (get, get_all, set, set_all) check the
+// requested type and size against the entry and return an error on mismatch.
+
+use ethercat::DataType;
+
+// value that can be read from/written to a PDO entry
+pub trait PdoValue: Sized + Copy {
+    // data types of the entries the value can be read from/written to
+    const DATA_TYPES: &'static [DataType];
+    const BIT_LEN: u8;
+
+    // true if the value can be read from/written to an entry of this length
+    fn fits(bit_len: u8) -> bool {
+        bit_len == Self::BIT_LEN
+    }
+
+    // value from the (right aligned) little endian bytes of the entry
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    fn to_le_vec(self) -> Vec<u8>;
+}
+
+impl PdoValue for bool {
+    const DATA_TYPES: &'static [DataType] = &[DataType::Bool, DataType::Bit1];
+    const BIT_LEN: u8 = 1;
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        bytes[0] & 1 != 0
+    }
+
+    fn to_le_vec(self) -> Vec<u8> {
+        vec![self as u8]
+    }
+}
+
+// u8 is also used for the bit strings (BIT2...BIT8)
+impl PdoValue for u8 {
+    const DATA_TYPES: &'static [DataType] = &[
+        DataType::U8,
+        DataType::Byte,
+        DataType::Bit2,
+        DataType::Bit3,
+        DataType::Bit4,
+        DataType::Bit5,
+        DataType::Bit6,
+        DataType::Bit7,
+        DataType::Bit8,
+    ];
+    const BIT_LEN: u8 = 8;
+
+    fn fits(bit_len: u8) -> bool {
+        (1..=8).contains(&bit_len)
+    }
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+
+    fn to_le_vec(self) -> Vec<u8> {
+        vec![self]
+    }
+}
+
+macro_rules! impl_pdo_value {
+    ($t:ty, $($data_type:expr),+) => {
+        impl PdoValue for $t {
+            const DATA_TYPES: &'static [DataType] = &[$($data_type),+];
+            const BIT_LEN: u8 = (std::mem::size_of::<$t>() * 8) as u8;
+
+            fn from_le_slice(bytes: &[u8]) -> Self {
+                let mut b = [0u8; std::mem::size_of::<$t>()];
+                b.copy_from_slice(&bytes[..std::mem::size_of::<$t>()]);
+                <$t>::from_le_bytes(b)
+            }
+
+            fn to_le_vec(self) -> Vec<u8> {
+                self.to_le_bytes().to_vec()
+            }
+        }
+    };
+}
+
+impl_pdo_value!(i8, DataType::I8);
+impl_pdo_value!(i16, DataType::I16);
+impl_pdo_value!(u16, DataType::U16);
+impl_pdo_value!(i32, DataType::I32);
+impl_pdo_value!(u32, DataType::U32);
+impl_pdo_value!(i64, DataType::I64);
+impl_pdo_value!(u64, DataType::U64);
+impl_pdo_value!(f32, DataType::F32);
+impl_pdo_value!(f64, DataType::F64);
+
+// data type from its ESI name (ex. "REAL", "UINT16", "BOOL")
+pub fn parse_data_type(name: &str) -> Option<DataType> {
+    let data_type = match name.trim().to_uppercase().as_str() {
+        "BOOL" | "BIT" | "BIT1" => DataType::Bool,
+        "BIT2" => DataType::Bit2,
+        "BIT3" => DataType::Bit3,
+        "BIT4" => DataType::Bit4,
+        "BIT5" => DataType::Bit5,
+        "BIT6" => DataType::Bit6,
+        "BIT7" => DataType::Bit7,
+        "BIT8" => DataType::Bit8,
+        "BYTE" => DataType::Byte,
+        "SINT" | "INT8" => DataType::I8,
+        "INT" | "INT16" => DataType::I16,
+        "DINT" | "INT32" => DataType::I32,
+        "LINT" | "INT64" => DataType::I64,
+        "USINT" | "UINT8" => DataType::U8,
+        "UINT" | "UINT16" | "WORD" => DataType::U16,
+        "UDINT" | "UINT32" | "DWORD" => DataType::U32,
+        "ULINT" | "UINT64" | "LWORD" => DataType::U64,
+        "REAL" | "FLOAT" | "FLOAT32" => DataType::F32,
+        "LREAL" | "DOUBLE" | "FLOAT64" => DataType::F64,
+        _ => return None,
+    };
+    Some(data_type)
+}
+
+// ESI name of the data type (None if not supported by the typed access)
+pub fn data_type_name(data_type: DataType) -> Option<&'static str> {
+    let name = match data_type {
+        DataType::Bool | DataType::Bit1 => "BOOL",
+        DataType::Bit2 => "BIT2",
+        DataType::Bit3 => "BIT3",
+        DataType::Bit4 => "BIT4",
+        DataType::Bit5 => "BIT5",
+        DataType::Bit6 => "BIT6",
+        DataType::Bit7 => "BIT7",
+        DataType::Bit8 => "BIT8",
+        DataType::Byte => "BYTE",
+        DataType::I8 => "SINT",
+        DataType::I16 => "INT",
+        DataType::I32 => "DINT",
+        DataType::I64 => "LINT",
+        DataType::U8 => "USINT",
+        DataType::U16 => "UINT",
+        DataType::U32 => "UDINT",
+        DataType::U64 => "ULINT",
+        DataType::F32 => "REAL",
+        DataType::F64 => "LREAL",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// human readable value of an entry (hexadecimal if the data type is unknown)
+pub fn format_value(data_type: Option<DataType>, bytes: &[u8]) -> String {
+    fn typed<T: PdoValue + ToString>(bytes: &[u8]) -> Option<String> {
+        (bytes.len() >= T::BIT_LEN.div_ceil(8) as usize)
+            .then(|| T::from_le_slice(bytes).to_string())
+    }
+    let value = match data_type {
+        Some(DataType::Bool | DataType::Bit1) => typed::<bool>(bytes),
+        Some(DataType::U8) => typed::<u8>(bytes),
+        Some(DataType::I8) => typed::<i8>(bytes),
+        Some(DataType::I16) => typed::<i16>(bytes),
+        Some(DataType::U16) => typed::<u16>(bytes),
+        Some(DataType::I32) => typed::<i32>(bytes),
+        Some(DataType::U32) => typed::<u32>(bytes),
+        Some(DataType::I64) => typed::<i64>(bytes),
+        Some(DataType::U64) => typed::<u64>(bytes),
+        Some(DataType::F32) => typed::<f32>(bytes),
+        Some(DataType::F64) => typed::<f64>(bytes),
+        _ => None,
+    };
+    value.unwrap_or_else(|| {
+        let hex: String = bytes.iter().rev().map(|b| format!("{:02X}", b)).collect();
+        format!("0x{}", hex)
+    })
+}
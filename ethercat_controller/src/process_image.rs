@@ -0,0 +1,263 @@
+// Read only view of the process data of a running master
+//
+// Attaches to the master like `ethercat data` and `ethercat pdos`: the master is opened read only,
+// it is neither reserved nor activated, so the view can be used while the application owning the
+// master (ex. the GRPC server) is running, and nothing is ever written to the slaves.
+//
+// The layout of the domain is found from the master configuration of the application:
+// - the slave configurations are the slaves of the application in the order of their ids
+// - the PDO entries of each sync manager are read from the PDO mapping of the configuration
+// - the offset of each entry is the logical address of the FMMU of its sync manager in the domain
+//   plus the bit lengths of the entries before it
+// The entry names are the SII names of the slave (renamed as in the `pdo_mapping` configuration)
+// and the data types come from the configuration or from the CoE dictionary of the slave.
+
+use std::{collections::HashMap, io};
+
+use ethercat::{
+    DataType, Master, MasterAccess, Offset, PdoEntryPos, PdoPos, SdoEntryAddr, SdoIdx, SlavePos,
+    SmIdx,
+};
+use ethercat_sys as ec;
+
+use crate::{
+    config::PdoMappingConfig,
+    ethercat_patch,
+    identity::SlaveIdentity,
+    pdo_entry::read_entry,
+    pdo_mapping::{entry_name, find_pdo_mapping_config},
+    pdo_value::parse_data_type,
+};
+
+// max number of sync managers of a slave configuration
+const MAX_SYNC_MANAGERS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ImageEntry {
+    pub name: String,
+    // written by the slave
+    pub input: bool,
+    pub bit_len: u8,
+    pub offset: Offset,
+    // None if unknown
+    pub data_type: Option<DataType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageSlave {
+    // identity of the slave (id in the application and position on the bus)
+    pub identity: SlaveIdentity,
+    pub entries: Vec<ImageEntry>,
+}
+
+impl ImageSlave {
+    pub fn id(&self) -> u16 {
+        self.identity.id
+    }
+
+    // names of the entries (sorted)
+    pub fn entry_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.entries.iter().map(|e| e.name.clone()).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // entries with the name (one per PDO mapping it, in the order of the PDOs)
+    pub fn entries<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a ImageEntry> {
+        self.entries.iter().filter(move |e| e.name == name)
+    }
+
+    // value of each entry with the name in the process data (right aligned bytes)
+    pub fn values(&self, data: &[u8], name: &str) -> Vec<Vec<u8>> {
+        self.entries(name)
+            .map(|e| read_entry(data, &e.offset, e.bit_len))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessImage {
+    master_id: u32,
+    domain_idx: u32,
+    size: usize,
+    slaves: Vec<ImageSlave>,
+}
+
+fn to_io_error(e: ethercat::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+impl ProcessImage {
+    // find the layout of the domain of the application owning the master
+    // (error if no application has configured the domain yet)
+    pub fn attach(
+        master_id: u32,
+        domain_idx: u32,
+        pdo_mapping: &[PdoMappingConfig],
+    ) -> Result<Self, io::Error> {
+        let info = ethercat_patch::master_info(master_id).map_err(to_io_error)?;
+        if domain_idx >= info.domain_count {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No domain {} on master {}, is the application running?",
+                    domain_idx, master_id
+                ),
+            ));
+        }
+        let domain = ethercat_patch::domain_info(master_id, domain_idx).map_err(to_io_error)?;
+        if domain.data_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Domain {} is empty, is the application running?",
+                    domain_idx
+                ),
+            ));
+        }
+
+        // offset in the domain of each sync manager by slave configuration (alias, position)
+        let mut sm_offsets = HashMap::new();
+        for f in 0..domain.fmmu_count {
+            let fmmu =
+                ethercat_patch::domain_fmmu(master_id, domain_idx, f).map_err(to_io_error)?;
+            sm_offsets.insert(
+                (
+                    fmmu.slave_config_alias,
+                    fmmu.slave_config_position,
+                    fmmu.sync_index,
+                ),
+                (fmmu.logical_address - domain.logical_base_address) as usize,
+            );
+        }
+
+        let mut master = Master::open(master_id, MasterAccess::ReadOnly)?;
+        let mut slaves = vec![];
+        for config_idx in 0..info.config_count {
+            let config = ethercat_patch::config_info(master_id, config_idx).map_err(to_io_error)?;
+            let Ok(position) = u16::try_from(config.slave_position) else {
+                log::warn!(
+                    "Slave configuration {} not attached to a slave, skipped",
+                    config_idx
+                );
+                continue;
+            };
+            let slave_pos = SlavePos::from(position);
+            let slave_info = master.get_slave_info(slave_pos).map_err(to_io_error)?;
+            let mapping_config =
+                find_pdo_mapping_config(pdo_mapping, &slave_info.name, slave_info.id.product_code);
+
+            // SII names of the entries of the slave
+            let mut sii_names = HashMap::new();
+            for sm in 0..slave_info.sync_count {
+                let sm_idx = SmIdx::new(sm);
+                let sm_info = master.get_sync(slave_pos, sm_idx).map_err(to_io_error)?;
+                for pdo in 0..sm_info.pdo_count {
+                    let pdo_info = master
+                        .get_pdo(slave_pos, sm_idx, PdoPos::new(pdo))
+                        .map_err(to_io_error)?;
+                    for e in 0..pdo_info.entry_count {
+                        let entry = master
+                            .get_pdo_entry(slave_pos, sm_idx, PdoPos::new(pdo), PdoEntryPos::new(e))
+                            .map_err(to_io_error)?;
+                        sii_names.insert(entry.entry_idx, entry.name);
+                    }
+                }
+            }
+
+            let mut entries = vec![];
+            for (sm, sync) in config.syncs.iter().enumerate().take(MAX_SYNC_MANAGERS) {
+                let Some(&sm_offset) = sm_offsets.get(&(config.alias, config.position, sm as u8))
+                else {
+                    continue;
+                };
+                let input = sync.dir == ec::EC_DIR_INPUT;
+                let mut bit = 0usize;
+                for pdo_pos in 0..sync.pdo_count as u16 {
+                    let pdo = ethercat_patch::config_pdo(master_id, config_idx, sm as u8, pdo_pos)
+                        .map_err(to_io_error)?;
+                    for entry_pos in 0..pdo.entry_count {
+                        let entry = ethercat_patch::config_pdo_entry(
+                            master_id, config_idx, sm as u8, pdo_pos, entry_pos,
+                        )
+                        .map_err(to_io_error)?;
+                        let offset = Offset {
+                            byte: sm_offset + bit / 8,
+                            bit: (bit % 8) as u32,
+                        };
+                        bit += entry.bit_length as usize;
+                        // padding entries
+                        if entry.index == 0 {
+                            continue;
+                        }
+                        let entry_idx = ethercat::PdoEntryIdx::new(entry.index, entry.subindex);
+                        let sii_name = sii_names.get(&entry_idx).cloned().unwrap_or_else(|| {
+                            format!("0x{:04X}:{:02X}", entry.index, entry.subindex)
+                        });
+                        let data_type = match mapping_config
+                            .and_then(|c| c.data_types.get(&sii_name))
+                        {
+                            Some(name) => parse_data_type(name),
+                            None => master
+                                .get_sdo_entry(
+                                    slave_pos,
+                                    SdoEntryAddr::ByIdx(SdoIdx::new(entry.index, entry.subindex)),
+                                )
+                                .ok()
+                                .map(|info| info.data_type)
+                                .filter(|data_type| *data_type != DataType::Raw),
+                        };
+                        entries.push(ImageEntry {
+                            name: entry_name(mapping_config, &sii_name),
+                            input,
+                            bit_len: entry.bit_length,
+                            offset,
+                            data_type,
+                        });
+                    }
+                }
+            }
+
+            slaves.push(ImageSlave {
+                identity: SlaveIdentity {
+                    id: config_idx as u16,
+                    position,
+                    name: slave_info.name.clone(),
+                    alias: slave_info.alias,
+                    serial_number: slave_info.rev.serial_number,
+                    // (read with an SDO, not available read only)
+                    dxl_id: None,
+                },
+                entries,
+            });
+        }
+
+        Ok(ProcessImage {
+            master_id,
+            domain_idx,
+            size: domain.data_size as usize,
+            slaves,
+        })
+    }
+
+    pub fn slaves(&self) -> &[ImageSlave] {
+        &self.slaves
+    }
+
+    pub fn slave(&self, id: u16) -> Option<&ImageSlave> {
+        self.slaves.iter().find(|s| s.id() == id)
+    }
+
+    pub fn identities(&self) -> Vec<SlaveIdentity> {
+        self.slaves.iter().map(|s| s.identity.clone()).collect()
+    }
+
+    // copy of the process data of the domain (as last exchanged by the application)
+    pub fn read(&self) -> Result<Vec<u8>, io::Error> {
+        let mut data = vec![0u8; self.size];
+        ethercat_patch::domain_data(self.master_id, self.domain_idx, &mut data)
+            .map_err(to_io_error)?;
+        Ok(data)
+    }
+}
@@ -0,0 +1,332 @@
+// Recording and replay of the process image exchanged with the slaves
+//
+// The log file is structured as follows:
+// - magic bytes "ECPI"
+// - u32 (LE) : size of the header in bytes
+// - header   : yaml description of the slaves and their PDO entries (see ProcessImageHeader)
+// - frames   : one frame per EtherCAT cycle
+//      - u64 (LE) : time since the start of the recording in microseconds
+//      - u32 (LE) : size of the process image in bytes
+//      - [u8]     : the process image (domain data) as seen by the readers
+//
+// The recorder writes the frames from a separate thread so that the file access
+// never blocks the EtherCAT loop. If the writer cannot keep up the frames are dropped.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded, Sender, TrySendError};
+use ethercat::{Offset, PdoEntryIdx, SlavePos};
+use serde::{Deserialize, Serialize};
+
+use crate::pdo_entry::entry_byte_range;
+use crate::pdo_value::{data_type_name, parse_data_type};
+use crate::{InputPdoEntries, PdoDataTypes, PdoOffsets, SlaveIdentity, SlaveNames, SlaveOffsets};
+
+const MAGIC: &[u8; 4] = b"ECPI";
+// number of frames buffered before the recorder starts dropping them
+const RECORDER_BUFFER_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    pub name: String,
+    pub index: u16,
+    pub sub_index: u8,
+    pub bit_len: u8,
+    pub byte: usize,
+    pub bit: u32,
+    pub input: bool,
+    // ESI name of the data type (if known)
+    #[serde(default)]
+    pub data_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedSlave {
+    // id of the slave ("position" in the older recordings)
+    #[serde(alias = "position")]
+    pub id: u16,
+    // position on the bus (the id in the older recordings)
+    #[serde(default)]
+    pub bus_position: Option<u16>,
+    pub name: String,
+    #[serde(default)]
+    pub alias: u16,
+    #[serde(default)]
+    pub serial_number: u32,
+    #[serde(default)]
+    pub dxl_id: Option<u8>,
+    pub entries: Vec<RecordedEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessImageHeader {
+    pub cycle_time_us: u32,
+    pub slaves: Vec<RecordedSlave>,
+}
+
+impl ProcessImageHeader {
+    // build the header from the slave configuration found by the master
+    pub fn new(
+        cycle_period: Duration,
+        offsets: &SlaveOffsets,
+        identities: &[SlaveIdentity],
+        input_pdo_entries: &InputPdoEntries,
+        data_types: &PdoDataTypes,
+    ) -> Self {
+        let mut slaves = identities
+            .iter()
+            .map(|identity| {
+                let pos = &SlavePos::from(identity.id);
+                let inputs = input_pdo_entries.get(pos).cloned().unwrap_or_default();
+                let types = data_types.get(pos);
+                let mut entries = vec![];
+                if let Some(pdo_offsets) = offsets.get(pos) {
+                    for (entry_name, pdos) in pdo_offsets {
+                        for (pdo_entry_idx, bit_len, offset) in pdos {
+                            entries.push(RecordedEntry {
+                                name: entry_name.clone(),
+                                index: u16::from(pdo_entry_idx.idx),
+                                sub_index: u8::from(pdo_entry_idx.sub_idx),
+                                bit_len: *bit_len,
+                                byte: offset.byte,
+                                bit: offset.bit,
+                                input: inputs.contains(entry_name),
+                                data_type: types
+                                    .and_then(|t| t.get(pdo_entry_idx))
+                                    .and_then(|t| data_type_name(*t))
+                                    .map(|t| t.to_string()),
+                            });
+                        }
+                    }
+                }
+                // keep the entries in the domain order
+                entries.sort_by_key(|e| (e.byte, e.bit));
+                RecordedSlave {
+                    id: identity.id,
+                    bus_position: Some(identity.position),
+                    name: identity.name.clone(),
+                    alias: identity.alias,
+                    serial_number: identity.serial_number,
+                    dxl_id: identity.dxl_id,
+                    entries,
+                }
+            })
+            .collect::<Vec<_>>();
+        slaves.sort_by_key(|s| s.id);
+
+        ProcessImageHeader {
+            cycle_time_us: cycle_period.as_micros() as u32,
+            slaves,
+        }
+    }
+
+    // reconstruct the slave offsets as they were on the robot
+    pub fn slave_offsets(&self) -> SlaveOffsets {
+        let mut offsets: SlaveOffsets = HashMap::new();
+        for slave in &self.slaves {
+            let mut entry_offsets: PdoOffsets = HashMap::new();
+            for e in &slave.entries {
+                entry_offsets.entry(e.name.clone()).or_default().push((
+                    PdoEntryIdx::new(e.index, e.sub_index),
+                    e.bit_len,
+                    Offset {
+                        byte: e.byte,
+                        bit: e.bit,
+                    },
+                ));
+            }
+            offsets.insert(SlavePos::from(slave.id), entry_offsets);
+        }
+        offsets
+    }
+
+    pub fn slave_names(&self) -> SlaveNames {
+        self.slaves
+            .iter()
+            .map(|s| (SlavePos::from(s.id), s.name.clone()))
+            .collect()
+    }
+
+    pub fn slave_identities(&self) -> Vec<SlaveIdentity> {
+        self.slaves
+            .iter()
+            .map(|s| SlaveIdentity {
+                id: s.id,
+                position: s.bus_position.unwrap_or(s.id),
+                name: s.name.clone(),
+                alias: s.alias,
+                serial_number: s.serial_number,
+                dxl_id: s.dxl_id,
+            })
+            .collect()
+    }
+
+    pub fn data_types(&self) -> PdoDataTypes {
+        self.slaves
+            .iter()
+            .map(|s| {
+                let types = s
+                    .entries
+                    .iter()
+                    .filter_map(|e| {
+                        let data_type = parse_data_type(e.data_type.as_ref()?)?;
+                        Some((PdoEntryIdx::new(e.index, e.sub_index), data_type))
+                    })
+                    .collect();
+                (SlavePos::from(s.id), types)
+            })
+            .collect()
+    }
+
+    pub fn input_pdo_entries(&self) -> InputPdoEntries {
+        self.slaves
+            .iter()
+            .map(|s| {
+                let mut inputs: Vec<String> = vec![];
+                for e in s.entries.iter().filter(|e| e.input) {
+                    if !inputs.contains(&e.name) {
+                        inputs.push(e.name.clone());
+                    }
+                }
+                (SlavePos::from(s.id), inputs)
+            })
+            .collect()
+    }
+
+    // byte ranges of the input entries in the process image
+    // these are the parts of the image written by the slaves
+    pub fn input_ranges(&self) -> Vec<Range<usize>> {
+        self.slaves
+            .iter()
+            .flat_map(|s| s.entries.iter())
+            .filter(|e| e.input)
+            .map(|e| {
+                entry_byte_range(
+                    &Offset {
+                        byte: e.byte,
+                        bit: e.bit,
+                    },
+                    e.bit_len,
+                )
+            })
+            .collect()
+    }
+}
+
+// records the process image of each cycle to a file
+#[derive(Debug)]
+pub struct ProcessImageRecorder {
+    start: Instant,
+    frame_buff: Sender<(u64, Vec<u8>)>,
+    path: String,
+}
+
+impl ProcessImageRecorder {
+    pub fn create(path: &str, header: &ProcessImageHeader) -> Result<Self, io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let header = serde_yaml::to_string(header)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        writer.flush()?;
+
+        let (tx, rx) = bounded::<(u64, Vec<u8>)>(RECORDER_BUFFER_SIZE);
+        let log_path = path.to_string();
+        thread::spawn(move || {
+            // the thread stops once the recorder is dropped
+            while let Ok((timestamp_us, data)) = rx.recv() {
+                let written = writer
+                    .write_all(&timestamp_us.to_le_bytes())
+                    .and_then(|_| writer.write_all(&(data.len() as u32).to_le_bytes()))
+                    .and_then(|_| writer.write_all(&data));
+                if let Err(e) = written {
+                    log::error!("Failed writing the process image log {}: {}", log_path, e);
+                    return;
+                }
+            }
+            if let Err(e) = writer.flush() {
+                log::error!("Failed writing the process image log {}: {}", log_path, e);
+            }
+        });
+
+        log::info!("Recording the process image to {}", path);
+        Ok(ProcessImageRecorder {
+            start: Instant::now(),
+            frame_buff: tx,
+            path: path.to_string(),
+        })
+    }
+
+    // never blocks, drops the frame if the writer thread is too slow
+    pub fn record(&self, data: &[u8]) {
+        let timestamp_us = self.start.elapsed().as_micros() as u64;
+        match self.frame_buff.try_send((timestamp_us, data.to_vec())) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                log::warn!("Process image recorder buffer full, dropping a frame!");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                log::error!("Process image recorder for {} stopped!", self.path);
+            }
+        }
+    }
+}
+
+// reads the process image log frame by frame
+#[derive(Debug)]
+pub struct ProcessImageReplay {
+    pub header: ProcessImageHeader,
+    reader: BufReader<File>,
+}
+
+impl ProcessImageReplay {
+    pub fn open(path: &str) -> Result<Self, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a process image log",
+            ));
+        }
+        let mut header_len = [0u8; 4];
+        reader.read_exact(&mut header_len)?;
+        let mut header = vec![0u8; u32::from_le_bytes(header_len) as usize];
+        reader.read_exact(&mut header)?;
+        let header: ProcessImageHeader = serde_yaml::from_slice(&header)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(ProcessImageReplay { header, reader })
+    }
+
+    // read the next frame
+    // returns the time of the frame since the start of the recording
+    // and None at the end of the log
+    pub fn next_frame(&mut self) -> Result<Option<(Duration, Vec<u8>)>, io::Error> {
+        let mut timestamp_us = [0u8; 8];
+        match self.reader.read_exact(&mut timestamp_us) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(Some((
+            Duration::from_micros(u64::from_le_bytes(timestamp_us)),
+            data,
+        )))
+    }
+}
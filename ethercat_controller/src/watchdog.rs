@@ -1,154 +1,293 @@
+use crate::config::WatchdogConfig;
 use crate::SlaveOffsets;
+use ethercat::SlavePos;
 use std::ops::Range;
 
-// watchdog is added to the manufcturer specific data of the statusword
-// bits 8, 14 and 15
-// parse the 3bit watchdog counter from the statusword
-fn parse_watchdog_from_status(statusword: Vec<u8>) -> u8 {
-    // bit 8
-    let mut watchdog_counter = (statusword[1] & 0b0000_0001);
-    // bits 14 and 15
-    watchdog_counter = watchdog_counter | ((statusword[1] & 0b1100_0000) >> 5);
-    // return the counter
+// default watchdog encoding (poulpe firmware)
+// the watchdog is added to the manufcturer specific data of the controlword (bits 11-13)
+// and echoed back in the manufacturer specific data of the statusword (bits 8, 14 and 15)
+pub const DEFAULT_CONTROL_ENTRY: &str = "controlword";
+pub const DEFAULT_CONTROL_BITS: [u8; 3] = [11, 12, 13];
+pub const DEFAULT_STATUS_ENTRY: &str = "statusword";
+pub const DEFAULT_STATUS_BITS: [u8; 3] = [8, 14, 15];
+
+// how the watchdog counter is exchanged with one slave
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogEncoding {
+    // entry to which the counter is written and the bits used (LSB first)
+    pub control_entry: String,
+    pub control_bits: Vec<u8>,
+    // entry from which the echoed counter is read and the bits used (LSB first)
+    pub status_entry: String,
+    pub status_bits: Vec<u8>,
+    // the counter is incremented once every N cycles
+    pub cycles_per_increment: u32,
+}
+
+impl Default for WatchdogEncoding {
+    fn default() -> Self {
+        WatchdogEncoding {
+            control_entry: DEFAULT_CONTROL_ENTRY.to_string(),
+            control_bits: DEFAULT_CONTROL_BITS.to_vec(),
+            status_entry: DEFAULT_STATUS_ENTRY.to_string(),
+            status_bits: DEFAULT_STATUS_BITS.to_vec(),
+            cycles_per_increment: 1,
+        }
+    }
+}
+
+impl WatchdogEncoding {
+    // the counter wraps around at 2^(number of bits)
+    pub fn counter_modulo(&self) -> u16 {
+        1 << self.status_bits.len()
+    }
+}
+
+// watchdog state of one slave
+#[derive(Debug)]
+pub struct SlaveWatchdog {
+    pub encoding: WatchdogEncoding,
+    pub timeout_ms: u32,
+    // offsets of the control and status entries in the domain data
+    // None if the watchdog is not used for this slave
+    control_offsets: Option<Vec<Range<usize>>>,
+    status_offsets: Option<Vec<Range<usize>>>,
+    // counter sent to the slave
+    counter: u8,
+    cycle: u32,
+    // last counter received from the slave and when it changed
+    previous_counter: u8,
+    pub timestamp: std::time::Instant,
+    pub is_responding: bool,
+}
+
+impl SlaveWatchdog {
+    pub fn is_enabled(&self) -> bool {
+        self.control_offsets.is_some() && self.status_offsets.is_some()
+    }
+}
+
+// parse the watchdog counter from the status data
+// bits are given in the order of the counter bits (LSB first)
+fn parse_watchdog_from_status(status: &[u8], bits: &[u8]) -> u8 {
+    let mut watchdog_counter = 0;
+    for (i, bit) in bits.iter().enumerate() {
+        let byte = (*bit / 8) as usize;
+        if byte < status.len() && status[byte] & (1 << (bit % 8)) != 0 {
+            watchdog_counter |= 1 << i;
+        }
+    }
     watchdog_counter
 }
 
-// write the watchdog counter to the controlword
-// to the bits 11-15 which are manufacturer specific
-fn write_watchdog_to_control(control_word: Vec<u8>, watchdog_counter: u8) -> Vec<u8> {
-    let mut control_word = control_word;
-    // clear the bits 11-15
-    control_word[1] &= 0b0000_0111;
-    // write the watchdog counter to the controlword
-    control_word[1] |= watchdog_counter << 3;
-    // return the controlword
-    control_word
+// write the watchdog counter to the control data
+// only the bits used by the watchdog are modified
+fn write_watchdog_to_control(control: &mut [u8], bits: &[u8], watchdog_counter: u8) {
+    for (i, bit) in bits.iter().enumerate() {
+        let byte = (*bit / 8) as usize;
+        if byte >= control.len() {
+            continue;
+        }
+        // clear the bit
+        control[byte] &= !(1 << (bit % 8));
+        // write the watchdog counter bit
+        if watchdog_counter & (1 << i) != 0 {
+            control[byte] |= 1 << (bit % 8);
+        }
+    }
 }
 
 // verify the watchdog of the slaves
 // verify that the slaves are still writing
 // checking if the watchdog counter is the same as the previous cycle
 // - if the counter is the same, check for how long has it been the same
-//      - if it is the same for more than 1s the slave is considered not responding
+//      - if it is the same for more than the timeout the slave is considered not responding
 // - if the counter is different, update the timestamp
-// - write the watchdog counter to the controlword
+// - write the watchdog counter to the control entry
+// - increment the counter (once every N cycles)
 pub fn verify_watchdog(
-    slave_number: u32,
     data: &mut [u8],
-    watchdog_timeout_ms: u32,
-    watchdog_counter: u8,
-    slave_watchdog_control_offsets: &Vec<Vec<Range<usize>>>,
-    slave_watchdog_status_offsets: &Vec<Vec<Range<usize>>>,
-    slave_watchdog_timestamps: &mut Vec<std::time::Instant>,
-    slave_is_watchdog_responding: &mut Vec<bool>,
-    slave_previous_watchdog_counter: &mut Vec<u8>,
+    slave_watchdogs: &mut [SlaveWatchdog],
     slave_name_from_id: &impl Fn(u16) -> String,
 ) -> bool {
     // return if all slaves responding
     let mut all_slaves_responding = true;
+
     // check each slave
+    for (i, wd) in slave_watchdogs.iter_mut().enumerate() {
+        let (control_offsets, status_offsets) = match (&wd.control_offsets, &wd.status_offsets) {
+            (Some(c), Some(s)) => (c, s),
+            // watchdog not used for this slave
+            _ => continue,
+        };
 
-    for i in 0..slave_number {
-        // get slave watchdog control offset
-        let status_offset = slave_watchdog_status_offsets[i as usize].clone();
-        // get the watchdog control data
-        let status_data = status_offset
-            .iter()
-            .map(|range| data[range.clone()].to_vec())
-            .collect::<Vec<_>>();
-
-        // doutput the watchdog status in binary
-        let counter = parse_watchdog_from_status(status_data[0].clone());
+        // read the echoed counter
+        let counter =
+            parse_watchdog_from_status(&data[status_offsets[0].clone()], &wd.encoding.status_bits);
         log::debug!(
             "Slave {} ({})| Watchdog counter received : {} ({:08b}), sent: {} ({:08b})",
             i,
             slave_name_from_id(i as u16),
             counter,
             counter,
-            watchdog_counter,
-            watchdog_counter
+            wd.counter,
+            wd.counter
         );
 
         // check if the watchdog counter is the same as the one in the previous cycle
         // if it is the same check for how long has it been the same
-        // if it is the same for more than 1s the slave is considered not responding
-        if slave_previous_watchdog_counter[i as usize] == counter {
-            if slave_watchdog_timestamps[i as usize].elapsed().as_millis() as u32
-                > watchdog_timeout_ms
-            {
+        // if it is the same for more than the timeout the slave is considered not responding
+        if wd.previous_counter == counter {
+            if wd.timestamp.elapsed().as_millis() as u32 > wd.timeout_ms {
                 all_slaves_responding &= false;
-                slave_is_watchdog_responding[i as usize] = false;
+                wd.is_responding = false;
             }
         } else {
             // if the watchdog counter is different
             // update the timestamp
-            slave_watchdog_timestamps[i as usize] = std::time::Instant::now();
-            slave_is_watchdog_responding[i as usize] = true;
-            slave_previous_watchdog_counter[i as usize] = counter;
+            wd.timestamp = std::time::Instant::now();
+            wd.is_responding = true;
+            wd.previous_counter = counter;
+        }
+
+        // write the counter to the control entries
+        for range in control_offsets {
+            write_watchdog_to_control(
+                &mut data[range.clone()],
+                &wd.encoding.control_bits,
+                wd.counter,
+            );
         }
 
-        // write the counter to the controlword
-        let control_offset = slave_watchdog_control_offsets[i as usize].clone();
-        for (j, range) in control_offset.iter().enumerate() {
-            let control_word = data[range.clone()].to_vec();
-            data[range.clone()]
-                .copy_from_slice(&write_watchdog_to_control(control_word, watchdog_counter));
+        // update the watchdog counter
+        wd.cycle = wd.cycle.wrapping_add(1);
+        if wd.cycle % wd.encoding.cycles_per_increment.max(1) == 0 {
+            wd.counter = ((wd.counter as u16 + 1) % wd.encoding.counter_modulo()) as u8;
         }
     }
     all_slaves_responding
 }
 
+// find the watchdog configuration of a slave
+// the first configuration matching the slave name and firmware version is used
+// if none match, the default encoding is used with the default timeout
+pub fn find_watchdog_config<'a>(
+    configs: &'a [WatchdogConfig],
+    slave_name: &str,
+    firmware_version: Option<&str>,
+) -> Option<&'a WatchdogConfig> {
+    configs.iter().find(|c| {
+        let name_matches = c
+            .slave_name
+            .as_ref()
+            .is_none_or(|n| slave_name.contains(n.as_str()));
+        let firmware_matches = c
+            .firmware_version
+            .as_ref()
+            .is_none_or(|f| firmware_version.is_some_and(|v| v.contains(f.as_str())));
+        name_matches && firmware_matches
+    })
+}
+
 // initialize the watchdog settings
-// find the offsets of the controlword and statusword data in the domain data
+// find the watchdog encoding of each slave
+// find the offsets of the control and status entries in the domain data
 // initialize the timestamp, flag and buffer for the watchdog data
 pub fn init_watchdog_settings(
     slave_number: u32,
     offsets: &SlaveOffsets,
+    configs: &[WatchdogConfig],
+    default_timeout_ms: u32,
+    slave_name_from_id: &impl Fn(u16) -> String,
+    firmware_versions: &[Option<String>],
     get_reg_addr_ranges: &impl Fn(&SlaveOffsets, u16, &String) -> Vec<Range<usize>>,
-) -> (
-    Vec<Vec<Range<usize>>>,
-    Vec<Vec<Range<usize>>>,
-    Vec<std::time::Instant>,
-    Vec<bool>,
-    Vec<u8>,
-) {
-    // initialize the watchdog variables
-    // offsets of the statusword data in the domain data
-    let mut slave_watchdog_status_offsets = vec![];
-    // offsets of the controlword data in the domain data
-    let mut slave_watchdog_control_offsets = vec![];
-    // last read timestamp of the watchdog data
-    let slave_watchdog_timestamps = vec![std::time::Instant::now(); slave_number as usize];
-    // flag to check if the slave is responding
-    let slave_is_watchdog_responding = vec![true; slave_number as usize];
-    // buffer to store the watchdog data (that are read asynchronusly from the slaves)
-    let slave_previous_watchdog_counter = vec![0u8; slave_number as usize];
-
-    // find the watchdog offsets for each slave
-    for i in 0..slave_number {
-        let mut watchdog_offsets = vec![];
-        watchdog_offsets.append(&mut get_reg_addr_ranges(
-            &offsets,
-            i as u16,
-            &"controlword".to_string(),
-        ));
-        slave_watchdog_control_offsets.push(watchdog_offsets);
-    }
+) -> Result<Vec<SlaveWatchdog>, std::io::Error> {
+    let mut slave_watchdogs = vec![];
+
     for i in 0..slave_number {
-        let mut watchdog_offsets = vec![];
-        watchdog_offsets.append(&mut get_reg_addr_ranges(
-            &offsets,
-            i as u16,
-            &"statusword".to_string(),
-        ));
-        slave_watchdog_status_offsets.push(watchdog_offsets);
+        let name = slave_name_from_id(i as u16);
+        let firmware_version = firmware_versions.get(i as usize).cloned().flatten();
+        let config = find_watchdog_config(configs, &name, firmware_version.as_deref());
+
+        let (encoding, timeout_ms, enabled) = match config {
+            Some(c) => (
+                WatchdogEncoding {
+                    control_entry: c.control_entry.clone(),
+                    control_bits: c.control_bits.clone(),
+                    status_entry: c.status_entry.clone(),
+                    status_bits: c.status_bits.clone(),
+                    cycles_per_increment: c.cycles_per_increment,
+                },
+                c.timeout_ms.unwrap_or(default_timeout_ms),
+                c.enabled,
+            ),
+            None => (WatchdogEncoding::default(), default_timeout_ms, true),
+        };
+
+        if encoding.status_bits.is_empty()
+            || encoding.status_bits.len() != encoding.control_bits.len()
+            || encoding.status_bits.len() > 8
+        {
+            log::error!(
+                "Slave {} ({}) | Invalid watchdog encoding, control bits {:?} and status bits {:?} should have the same length (1 to 8 bits)",
+                i,
+                name,
+                encoding.control_bits,
+                encoding.status_bits
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid watchdog encoding",
+            ));
+        }
+
+        // the entries might not exist on all slaves (ex. other slave types)
+        let entry_ranges = |entry: &String| {
+            offsets
+                .get(&SlavePos::from(i as u16))
+                .filter(|o| o.contains_key(entry))
+                .map(|_| get_reg_addr_ranges(offsets, i as u16, entry))
+        };
+        let (control_offsets, status_offsets) = if enabled {
+            (
+                entry_ranges(&encoding.control_entry),
+                entry_ranges(&encoding.status_entry),
+            )
+        } else {
+            (None, None)
+        };
+
+        if enabled && (control_offsets.is_none() || status_offsets.is_none()) {
+            log::warn!(
+                "Slave {} ({}) | No {:?}/{:?} entries, watchdog disabled for this slave!",
+                i,
+                name,
+                encoding.control_entry,
+                encoding.status_entry
+            );
+        }
+        log::debug!(
+            "Slave {} ({}) | Watchdog {:?}, timeout {}ms",
+            i,
+            name,
+            encoding,
+            timeout_ms
+        );
+
+        slave_watchdogs.push(SlaveWatchdog {
+            encoding,
+            timeout_ms,
+            control_offsets,
+            status_offsets,
+            counter: 0,
+            cycle: 0,
+            previous_counter: 0,
+            // last read timestamp of the watchdog data
+            timestamp: std::time::Instant::now(),
+            // flag to check if the slave is responding
+            is_responding: true,
+        });
     }
 
-    (
-        slave_watchdog_control_offsets,
-        slave_watchdog_status_offsets,
-        slave_watchdog_timestamps,
-        slave_is_watchdog_responding,
-        slave_previous_watchdog_counter,
-    )
+    Ok(slave_watchdogs)
 }
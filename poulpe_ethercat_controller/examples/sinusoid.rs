@@ -41,6 +41,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     pouple_controller.set_torque_limit(slave_id, vec![1.0; no_axis])?;
     pouple_controller.set_velocity_limit(slave_id, vec![1.0; no_axis])?;
 
+    // move smoothly to the start of the sinusoid
+    pouple_controller.move_to(slave_id, &vec![0.0; no_axis], 1.0, 2.0)?;
+
     let mut t0 = SystemTime::now();
     let mut t1 = SystemTime::now();
 
@@ -43,20 +43,22 @@ fn main() -> Result<(), Box<dyn Error>> {
     pouple_controller.set_torque_limit(slave_id, vec![0.4; no_axis])?; // torque limit at 40%
     pouple_controller.set_velocity_limit(slave_id, vec![0.1; no_axis])?; // velocity limit at 10%
 
+    // trapezoidal moves between 0 and 3.14 rad (1 rad/s, 2 rad/s^2)
     loop {
-        pouple_controller.set_target_position(slave_id, vec![0.0; no_axis])?;
-        sleep(Duration::from_secs(2));
+        for target in [0.0, 3.14] {
+            let outcome = pouple_controller.move_to(slave_id, &vec![target; no_axis], 1.0, 2.0)?;
+            log::info!(
+                "Move to {}: {:?}, max following error: {:?}",
+                target,
+                outcome.status,
+                outcome.max_following_error
+            );
+            sleep(Duration::from_secs(1));
 
-        log::info!(
-            "Current position: {:?}",
-            pouple_controller.get_current_position(slave_id)?
-        );
-
-        pouple_controller.set_target_position(slave_id, vec![3.14; no_axis])?;
-        sleep(Duration::from_secs(2));
-        log::info!(
-            "Current position: {:?}",
-            pouple_controller.get_current_position(slave_id)?
-        );
+            log::info!(
+                "Current position: {:?}",
+                pouple_controller.get_current_position(slave_id)?
+            );
+        }
     }
 }
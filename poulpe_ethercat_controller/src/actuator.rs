@@ -0,0 +1,259 @@
+// Typed actuator handles
+//
+// The PoulpeController methods take the slave id and read/write Vec<f32> with as many values
+// as the board has axes. The handles are obtained from the controller for a given number of axes,
+// the orbita type of the slave is verified once when the handle is created and the values are
+// read and written as [f32; N], so an axis count mistake does not compile.
+//
+// - Orbita2dHandle (Actuator<2>): 2 axes
+// - Orbita3dHandle (Actuator<3>): 3 axes
+
+use std::{error::Error, time::Duration};
+
+use crate::motion::MotionOutcome;
+use crate::pid::{ControlLoop, PidGains};
+use crate::state_machine::{CiA402State, ErrorFlags, FaultRecovery, HomingOutcome};
+use crate::PoulpeController;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Actuator<'a, const N: usize> {
+    controller: &'a PoulpeController,
+    id: u32,
+}
+
+pub type Orbita2dHandle<'a> = Actuator<'a, 2>;
+pub type Orbita3dHandle<'a> = Actuator<'a, 3>;
+
+// handle of an actuator whose type is only known at runtime
+#[derive(Debug, Clone, Copy)]
+pub enum OrbitaHandle<'a> {
+    Orbita2d(Orbita2dHandle<'a>),
+    Orbita3d(Orbita3dHandle<'a>),
+}
+
+impl PoulpeController {
+    // handle of the actuator with N axes
+    // fails if the slave is not a poulpe board or if it does not have N axes
+    pub fn actuator<const N: usize>(&self, id: u32) -> Result<Actuator<'_, N>, Box<dyn Error>> {
+        let orbita_type = match self.poulpe_config.get(&(id as u16)) {
+            Some(poulpe) => poulpe.orbita_type,
+            None => {
+                log::error!("Slave {} is not a poulpe board!", id);
+                return Err("Slave is not a poulpe board!".into());
+            }
+        };
+        if orbita_type as usize != N {
+            log::error!(
+                "Slave {} | {:?} has {} axes, not {}",
+                id,
+                self.get_slave_name(id as u16).unwrap_or_default(),
+                orbita_type,
+                N
+            );
+            return Err("Orbita type mismatch".into());
+        }
+        Ok(Actuator {
+            controller: self,
+            id,
+        })
+    }
+
+    pub fn orbita2d(&self, id: u32) -> Result<Orbita2dHandle<'_>, Box<dyn Error>> {
+        self.actuator::<2>(id)
+    }
+
+    pub fn orbita3d(&self, id: u32) -> Result<Orbita3dHandle<'_>, Box<dyn Error>> {
+        self.actuator::<3>(id)
+    }
+
+    // handle of the actuator of the type found on the bus
+    pub fn orbita(&self, id: u32) -> Result<OrbitaHandle<'_>, Box<dyn Error>> {
+        match self.poulpe_config.get(&(id as u16)).map(|p| p.orbita_type) {
+            Some(2) => Ok(OrbitaHandle::Orbita2d(self.orbita2d(id)?)),
+            Some(3) => Ok(OrbitaHandle::Orbita3d(self.orbita3d(id)?)),
+            Some(n) => {
+                log::error!("Slave {} | unsupported orbita type {}", id, n);
+                Err("Unsupported orbita type".into())
+            }
+            None => {
+                log::error!("Slave {} is not a poulpe board!", id);
+                Err("Slave is not a poulpe board!".into())
+            }
+        }
+    }
+}
+
+// convert the values read from the board to the number of axes of the handle
+fn to_axes<T, const N: usize>(id: u32, values: Vec<T>) -> Result<[T; N], Box<dyn Error>> {
+    let len = values.len();
+    values.try_into().map_err(|_| {
+        log::error!("Slave {} | expected {} values, got {}", id, N, len);
+        "Unexpected number of values".into()
+    })
+}
+
+impl<'a, const N: usize> Actuator<'a, N> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> String {
+        self.controller
+            .get_slave_name(self.id as u16)
+            .unwrap_or_default()
+    }
+
+    pub fn setup(&self) -> Result<(), Box<dyn Error>> {
+        self.controller.setup(self.id)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.controller.is_slave_ready(self.id as u16)
+    }
+
+    pub fn get_status(&self) -> Result<CiA402State, Box<dyn Error>> {
+        self.controller.get_status(self.id)
+    }
+
+    pub fn get_error_flags(&self) -> Result<ErrorFlags, Box<dyn Error>> {
+        self.controller.get_error_flags(self.id as u16)
+    }
+
+    // homing error code and the error code of each motor
+    pub fn get_error_codes(&self) -> Result<(u32, [u32; N]), Box<dyn Error>> {
+        let error_codes = self.controller.get_error_codes(self.id)?;
+        match error_codes.split_first() {
+            Some((homing, motors)) => Ok((*homing, to_axes(self.id, motors.to_vec())?)),
+            None => Err("No error codes".into()),
+        }
+    }
+
+    pub fn is_torque_on(&self) -> Result<bool, Box<dyn Error>> {
+        self.controller
+            .is_torque_on(self.id)?
+            .ok_or_else(|| "Error getting torque state!".into())
+    }
+
+    pub fn set_torque(&self, on: bool) -> Result<(), Box<dyn Error>> {
+        self.controller.set_torque(self.id, on)
+    }
+
+    pub fn set_mode_of_operation(&self, value: u8) -> Result<(), Box<dyn Error>> {
+        self.controller.set_mode_of_operation(self.id as u16, value)
+    }
+
+    pub fn emergency_stop(&self) -> Result<(), Box<dyn Error>> {
+        self.controller.emergency_stop(self.id)
+    }
+
+    pub fn clear_fault(&self) -> Result<FaultRecovery, Box<dyn Error>> {
+        self.controller.clear_fault(self.id)
+    }
+
+    // home the actuator (see homing)
+    pub fn home(&self, method: i8, timeout: Duration) -> Result<HomingOutcome, Box<dyn Error>> {
+        self.controller.home(self.id, method, timeout)
+    }
+
+    // move the axes to the target positions (see motion)
+    pub fn move_to(
+        &self,
+        target: [f32; N],
+        max_vel: f32,
+        max_acc: f32,
+    ) -> Result<MotionOutcome, Box<dyn Error>> {
+        self.controller.move_to(self.id, &target, max_vel, max_acc)
+    }
+
+    // gains of the control loop of each axis (see pid)
+    pub fn get_pid(&self, control_loop: ControlLoop) -> Result<[PidGains; N], Box<dyn Error>> {
+        to_axes(self.id, self.controller.get_pid(self.id, control_loop)?)
+    }
+
+    pub fn set_pid(
+        &self,
+        control_loop: ControlLoop,
+        gains: [PidGains; N],
+    ) -> Result<(), Box<dyn Error>> {
+        self.controller.set_pid(self.id, control_loop, &gains)
+    }
+
+    pub fn reactivate_after_emergency_stop(&self) -> Result<(), Box<dyn Error>> {
+        self.controller.reactivate_after_emergency_stop(self.id)
+    }
+
+    fn read(
+        &self,
+        values: Result<Option<Vec<f32>>, Box<dyn Error>>,
+    ) -> Result<[f32; N], Box<dyn Error>> {
+        match values? {
+            Some(values) => to_axes(self.id, values),
+            None => Err("Error reading register!".into()),
+        }
+    }
+
+    pub fn get_current_position(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_position(self.id))
+    }
+
+    pub fn get_current_velocity(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_velocity(self.id))
+    }
+
+    pub fn get_current_torque(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_torque(self.id))
+    }
+
+    pub fn get_current_axis_sensors(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_axis_sensors(self.id))
+    }
+
+    pub fn get_current_target_position(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_target_position(self.id))
+    }
+
+    pub fn get_axis_sensor_zeros(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_axis_sensor_zeros(self.id))
+    }
+
+    pub fn get_board_temperatures(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_board_temperatures(self.id))
+    }
+
+    pub fn get_motor_temperatures(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_motor_temperatures(self.id))
+    }
+
+    pub fn get_current_velocity_limit(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_velocity_limit(self.id))
+    }
+
+    pub fn get_current_torque_limit(&self) -> Result<[f32; N], Box<dyn Error>> {
+        self.read(self.controller.get_current_torque_limit(self.id))
+    }
+
+    pub fn set_target_position(&self, target_position: [f32; N]) -> Result<(), Box<dyn Error>> {
+        self.controller
+            .set_target_position(self.id, target_position.to_vec())
+    }
+
+    pub fn set_target_velocity(&self, target_velocity: [f32; N]) -> Result<(), Box<dyn Error>> {
+        self.controller
+            .set_target_velocity(self.id, target_velocity.to_vec())
+    }
+
+    pub fn set_target_torque(&self, target_torque: [f32; N]) -> Result<(), Box<dyn Error>> {
+        self.controller
+            .set_target_torque(self.id, target_torque.to_vec())
+    }
+
+    pub fn set_velocity_limit(&self, velocity_limit: [f32; N]) -> Result<(), Box<dyn Error>> {
+        self.controller
+            .set_velocity_limit(self.id, velocity_limit.to_vec())
+    }
+
+    pub fn set_torque_limit(&self, torque_limit: [f32; N]) -> Result<(), Box<dyn Error>> {
+        self.controller
+            .set_torque_limit(self.id, torque_limit.to_vec())
+    }
+}
@@ -0,0 +1,426 @@
+// CiA402 state machine of the slaves driven by the EtherCAT cycle
+//
+// A driver thread wakes up at each cycle of the EtherCAT loop, reads the statusword of each poulpe
+// board and:
+// - records each change of the CiA402 state with its timestamp (see get_state_transitions)
+// - for the slaves with a requested target state, sends the controlword of the next transition
+//   towards it (once per state entered) and completes the request once the target state is reached
+//
+// The path to the target state (CiA402):
+//   NotReadyToSwitchOn -(wait)-> SwitchOnDisabled -(Shutdown)-> ReadyToSwitchOn -(SwitchOn)->
+//   SwitchedOn -(EnableOperation)-> OperationEnabled
+//   OperationEnabled -(DisableOperation)-> SwitchedOn
+//   OperationEnabled -(QuickStop)-> QuickStopActive -(wait)-> SwitchOnDisabled
+//
+// The requests fail if the slave goes to the fault state or if it stays longer than its timeout in
+// a state on the way (the timeouts are read from the `timeouts` of the `cia402` entry of the
+// `poulpe` section of the configuration file).
+// The caller gets a handle on the request and can wait for it or poll it, the transitions do not
+// run on the caller's thread.
+// The controlwords written directly (emergency stop, fault reset, homing) cancel the active request
+// of the slave first, so that the driver never resumes the transitions after them.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    sync::{Arc, Condvar, Mutex, Weak},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ethercat_controller::EtherCatController;
+use serde::{Deserialize, Serialize};
+
+use crate::register::PdoRegister;
+use crate::state_machine::{
+    parse_state_from_status_bits, parse_status_word, CiA402State, ControlWord,
+};
+use crate::PoulpeController;
+
+// number of transitions kept per slave
+const TRANSITION_HISTORY: usize = 100;
+
+// read from the `cia402` entry of the `poulpe` section of the configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cia402Config {
+    pub timeouts: Cia402Timeouts,
+}
+
+// max time spent in each state on the way to the target state (ms)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cia402Timeouts {
+    // the board initialisation (NotReadyToSwitchOn)
+    pub not_ready_to_switch_on_ms: u64,
+    // the quick stop (QuickStopActive, can take up to 10s)
+    pub quick_stop_ms: u64,
+    // SwitchedOn -> OperationEnabled
+    pub enable_operation_ms: u64,
+    // OperationEnabled -> SwitchedOn
+    pub disable_operation_ms: u64,
+    // the other transitions (Shutdown, SwitchOn)
+    pub transition_ms: u64,
+}
+
+impl Default for Cia402Timeouts {
+    fn default() -> Self {
+        Cia402Timeouts {
+            not_ready_to_switch_on_ms: 100_000,
+            quick_stop_ms: 20_000,
+            enable_operation_ms: 20,
+            disable_operation_ms: 20,
+            transition_ms: 1000,
+        }
+    }
+}
+
+impl Cia402Timeouts {
+    // max time to reach any state (all the timeouts on the way)
+    fn total(&self) -> Duration {
+        Duration::from_millis(
+            self.not_ready_to_switch_on_ms
+                + self.quick_stop_ms
+                + self.enable_operation_ms
+                + self.disable_operation_ms
+                + 2 * self.transition_ms,
+        )
+    }
+
+    // max time in the state on the way to the target
+    fn timeout(&self, state: CiA402State, target: CiA402State) -> Duration {
+        let ms = match (state, target) {
+            (CiA402State::NotReadyToSwitchOn, _) => self.not_ready_to_switch_on_ms,
+            (CiA402State::QuickStopActive, _) => self.quick_stop_ms,
+            (CiA402State::SwitchedOn, CiA402State::OperationEnabled) => self.enable_operation_ms,
+            (CiA402State::OperationEnabled, _) => self.disable_operation_ms,
+            _ => self.transition_ms,
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+// the states that can be requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cia402Target {
+    // torque off, ready to be enabled
+    SwitchedOn,
+    // torque on
+    OperationEnabled,
+    // quick stop, the slave has to be switched on again
+    SwitchOnDisabled,
+}
+
+impl Cia402Target {
+    fn state(&self) -> CiA402State {
+        match self {
+            Cia402Target::SwitchedOn => CiA402State::SwitchedOn,
+            Cia402Target::OperationEnabled => CiA402State::OperationEnabled,
+            Cia402Target::SwitchOnDisabled => CiA402State::SwitchOnDisabled,
+        }
+    }
+
+    // controlword to send in the state (None: wait for the slave)
+    fn next_command(&self, state: CiA402State) -> Option<ControlWord> {
+        match (self, state) {
+            (Cia402Target::SwitchOnDisabled, CiA402State::SwitchOnDisabled) => None,
+            (Cia402Target::SwitchOnDisabled, CiA402State::QuickStopActive) => None,
+            (Cia402Target::SwitchOnDisabled, _) => Some(ControlWord::QuickStop),
+            (_, CiA402State::SwitchOnDisabled) => Some(ControlWord::Shutdown),
+            (_, CiA402State::ReadyToSwitchOn) => Some(ControlWord::SwitchOn),
+            (Cia402Target::OperationEnabled, CiA402State::SwitchedOn) => {
+                Some(ControlWord::EnableOperation)
+            }
+            (Cia402Target::SwitchedOn, CiA402State::OperationEnabled) => {
+                Some(ControlWord::DisableOperation)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateTransition {
+    // None for the first state read
+    pub from: Option<CiA402State>,
+    pub to: CiA402State,
+    pub time: SystemTime,
+}
+
+type RequestResult = Arc<(Mutex<Option<Result<CiA402State, String>>>, Condvar)>;
+
+// handle of a requested target state (see PoulpeController::request_state)
+#[derive(Debug, Clone)]
+pub struct StateRequest {
+    id: u32,
+    target: Cia402Target,
+    result: RequestResult,
+    // the request fails in the driver before (unless the driver is not running)
+    max_duration: Duration,
+}
+
+impl StateRequest {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn target(&self) -> Cia402Target {
+        self.target
+    }
+
+    // the result of the request if it is finished
+    pub fn try_result(&self) -> Option<Result<CiA402State, String>> {
+        self.result.0.lock().unwrap().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result.0.lock().unwrap().is_some()
+    }
+
+    // wait for the slave to reach the target state (or for the request to fail)
+    pub fn wait(&self) -> Result<CiA402State, Box<dyn Error>> {
+        let (lock, cvar) = &*self.result;
+        let (result, _) = cvar
+            .wait_timeout_while(lock.lock().unwrap(), self.max_duration, |r| r.is_none())
+            .unwrap();
+        match result.clone() {
+            Some(Ok(state)) => Ok(state),
+            Some(Err(e)) => {
+                log::error!(
+                    "Slave {} | {:?} request failed: {}",
+                    self.id,
+                    self.target,
+                    e
+                );
+                Err(e.into())
+            }
+            None => {
+                log::error!(
+                    "Slave {} | {:?} request not answered, is the EtherCAT loop running?",
+                    self.id,
+                    self.target
+                );
+                Err("State request not answered".into())
+            }
+        }
+    }
+}
+
+fn complete(result: &RequestResult, value: Result<CiA402State, String>) {
+    let (lock, cvar) = &**result;
+    *lock.lock().unwrap() = Some(value);
+    cvar.notify_all();
+}
+
+#[derive(Debug)]
+struct ActiveRequest {
+    target: Cia402Target,
+    result: RequestResult,
+    // the command has been sent in the current state
+    command_sent: bool,
+    // entry in the current state (or time of the request)
+    since: Instant,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SlaveStateMachine {
+    state: Option<CiA402State>,
+    transitions: VecDeque<StateTransition>,
+    request: Option<ActiveRequest>,
+}
+
+impl SlaveStateMachine {
+    fn fail_request(&mut self, e: &str) {
+        if let Some(request) = self.request.take() {
+            complete(&request.result, Err(e.to_string()));
+        }
+    }
+
+    // update with the state read in this cycle, returns the controlword to send
+    fn step(
+        &mut self,
+        id: u16,
+        state: CiA402State,
+        timeouts: &Cia402Timeouts,
+    ) -> Option<ControlWord> {
+        let now = Instant::now();
+        if self.state != Some(state) {
+            log::debug!("Slave {} | {:?} -> {:?}", id, self.state, state);
+            if self.transitions.len() == TRANSITION_HISTORY {
+                self.transitions.pop_front();
+            }
+            self.transitions.push_back(StateTransition {
+                from: self.state,
+                to: state,
+                time: SystemTime::now(),
+            });
+            self.state = Some(state);
+            if let Some(request) = &mut self.request {
+                request.command_sent = false;
+                request.since = now;
+            }
+        }
+
+        let request = self.request.as_mut()?;
+        let target = request.target.state();
+        let failure = match state {
+            s if s == target => {
+                complete(&request.result, Ok(state));
+                self.request = None;
+                return None;
+            }
+            CiA402State::Fault | CiA402State::FaultReactionActive => {
+                Some("Fault status".to_string())
+            }
+            _ if now.duration_since(request.since) > timeouts.timeout(state, target) => {
+                Some(format!("Timeout in {:?} on the way to {:?}", state, target))
+            }
+            _ => None,
+        };
+        if let Some(e) = failure {
+            self.fail_request(&e);
+            return None;
+        }
+
+        if request.command_sent {
+            return None;
+        }
+        request.command_sent = true;
+        request.target.next_command(state)
+    }
+}
+
+pub(crate) type StateMachines = Arc<Mutex<HashMap<u16, SlaveStateMachine>>>;
+
+pub(crate) fn new_state_machines(ids: impl Iterator<Item = u16>) -> StateMachines {
+    Arc::new(Mutex::new(
+        ids.map(|id| (id, SlaveStateMachine::default())).collect(),
+    ))
+}
+
+// run the state machines of the slaves at each cycle
+// (stops once the controller is dropped)
+pub(crate) fn spawn_driver(
+    inner: EtherCatController,
+    state_machines: Weak<Mutex<HashMap<u16, SlaveStateMachine>>>,
+    timeouts: Cia402Timeouts,
+) {
+    thread::spawn(move || loop {
+        inner.wait_for_next_cycle();
+        let Some(state_machines) = state_machines.upgrade() else {
+            break;
+        };
+        let mut state_machines = state_machines.lock().unwrap();
+        for (&id, state_machine) in state_machines.iter_mut() {
+            if !inner.is_slave_ready(id) {
+                state_machine.fail_request("Slave not ready");
+                continue;
+            }
+            let state = match inner.get::<u16>(id, PdoRegister::StatusWord.name(), 0) {
+                Ok(bits) => parse_state_from_status_bits(parse_status_word(bits)),
+                Err(_) => continue,
+            };
+            // (intermediate statusword values between two states)
+            let Ok(state) = state else {
+                continue;
+            };
+            if let Some(command) = state_machine.step(id, state, &timeouts) {
+                log::debug!("Slave {} | {:?} in {:?}", id, command, state);
+                if let Err(e) =
+                    inner.set::<u16>(id, PdoRegister::ControlWord.name(), 0, command.to_u16())
+                {
+                    log::error!("Slave {} | failed to send the controlword: {}", id, e);
+                }
+            }
+        }
+    });
+}
+
+impl PoulpeController {
+    // request the target state of the slave, the transitions are run by the driver thread
+    // (a previous request of the slave is cancelled)
+    pub fn request_state(
+        &self,
+        id: u32,
+        target: Cia402Target,
+    ) -> Result<StateRequest, Box<dyn Error>> {
+        let mut requests = self.request_states(&[id], target)?;
+        Ok(requests.remove(0))
+    }
+
+    // request the target state of several slaves at once
+    // (the driver sends their first controlwords in the same cycle)
+    pub fn request_states(
+        &self,
+        ids: &[u32],
+        target: Cia402Target,
+    ) -> Result<Vec<StateRequest>, Box<dyn Error>> {
+        let mut state_machines = self.state_machines.lock().unwrap();
+        if let Some(id) = ids
+            .iter()
+            .find(|id| !state_machines.contains_key(&(**id as u16)))
+        {
+            log::error!("Slave {} is not a poulpe board!", id);
+            return Err("Slave is not a poulpe board!".into());
+        }
+        let max_duration = self.config.cia402.timeouts.total() + Duration::from_secs(1);
+        let now = Instant::now();
+        let requests = ids
+            .iter()
+            .map(|&id| {
+                let state_machine = state_machines.get_mut(&(id as u16)).unwrap();
+                let result: RequestResult = Arc::new((Mutex::new(None), Condvar::new()));
+                if let Some(previous) = state_machine.request.replace(ActiveRequest {
+                    target,
+                    result: Arc::clone(&result),
+                    command_sent: false,
+                    since: now,
+                }) {
+                    complete(
+                        &previous.result,
+                        Err(format!("Cancelled by a request to {:?}", target)),
+                    );
+                }
+                log::debug!("Slave {} | requested {:?}", id, target);
+                StateRequest {
+                    id,
+                    target,
+                    result,
+                    max_duration,
+                }
+            })
+            .collect();
+        Ok(requests)
+    }
+
+    // the slave has an active request to the target state
+    pub fn is_state_requested(&self, id: u32, target: Cia402Target) -> bool {
+        self.state_machines
+            .lock()
+            .unwrap()
+            .get(&(id as u16))
+            .and_then(|s| s.request.as_ref())
+            .is_some_and(|r| r.target == target)
+    }
+
+    // cancel the active request of the slave (before writing the controlword directly,
+    // otherwise the driver would resume the transitions afterwards)
+    pub(crate) fn cancel_state_request(&self, id: u32) {
+        if let Some(state_machine) = self.state_machines.lock().unwrap().get_mut(&(id as u16)) {
+            if state_machine.request.is_some() {
+                log::debug!("Slave {} | state request cancelled", id);
+            }
+            state_machine.fail_request("Cancelled by a direct controlword");
+        }
+    }
+
+    // the last CiA402 state transitions of the slave (oldest first)
+    pub fn get_state_transitions(&self, id: u32) -> Vec<StateTransition> {
+        self.state_machines
+            .lock()
+            .unwrap()
+            .get(&(id as u16))
+            .map(|s| s.transitions.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
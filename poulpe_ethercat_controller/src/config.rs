@@ -0,0 +1,64 @@
+use std::{error::Error, fs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cia402::Cia402Config;
+use crate::limits::LimitsConfig;
+use crate::pid::PidConfig;
+use crate::thermal::ThermalConfig;
+
+// configuration of the poulpe controller
+// read from the `poulpe` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoulpeConfig {
+    // safety policies (`safety` entry)
+    pub safety: PoulpeSafetyConfig,
+    // PID gains objects and limits (`pid` entry, see pid)
+    pub pid: PidConfig,
+    // joint limits of the actuators (`limits` entry, see limits)
+    pub limits: LimitsConfig,
+    // thermal protection thresholds of the actuators (`thermal` entry, see thermal)
+    pub thermal: ThermalConfig,
+    // CiA402 state machines (`cia402` entry, see cia402)
+    pub cia402: Cia402Config,
+}
+
+// safety policies of the poulpe controller
+// read from the `safety` entry of the `poulpe` section of the configuration file
+// the defaults are set by the cargo features of the same name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoulpeSafetyConfig {
+    // set the target position to the current position before turning on the slave
+    pub safe_turn_on: bool,
+    // do not fail the setup and the torque on/off if a slave is in fault state
+    // (all the slaves are stopped instead)
+    pub allow_fault_on_slave: bool,
+    // turn off the slaves that are in OperationEnabled state during the setup
+    pub turn_off_slaves_setup: bool,
+}
+
+impl Default for PoulpeSafetyConfig {
+    fn default() -> Self {
+        PoulpeSafetyConfig {
+            safe_turn_on: cfg!(feature = "safe_turn_on"),
+            allow_fault_on_slave: cfg!(feature = "allow_fault_on_slave"),
+            turn_off_slaves_setup: cfg!(feature = "turn_off_slaves_setup"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PoulpeConfigSection {
+    #[serde(default)]
+    poulpe: PoulpeConfig,
+}
+
+impl PoulpeConfig {
+    pub fn from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let yaml = fs::read_to_string(path)?;
+        let section: PoulpeConfigSection = serde_yaml::from_str(&yaml)?;
+        Ok(section.poulpe)
+    }
+}
@@ -0,0 +1,143 @@
+// Torque on/off of a group of actuators with all-or-nothing semantics
+//
+// `set_torque_group(ids, true)` turns on the torque of all the actuators of the group or of none:
+// - the state of every member is verified first, nothing is done if one of them cannot be enabled
+//   (fault state, or SwitchedOnDisabled without the switchon_on_turnon feature)
+// - with safe_turn_on, the current position is copied to the target position of every member
+//   (a member whose target position cannot be set is a failed member, nothing is enabled)
+// - the CiA402 requests of all the members are issued together, their controlwords are sent in
+//   the same cycle by the driver (see cia402)
+// - if a member does not reach OperationEnabled (fault or timeout) DisableOperation is written to
+//   all the members whatever their state and they are waited for in SwitchedOn (rollback), and an
+//   error is returned
+// Contrary to set_torque, a member in the fault state is an error even with allow_fault_on_slave.
+//
+// `set_torque_group(ids, false)` disables all the enabled members together, the members that fail
+// to be disabled are reported in the error.
+
+use std::error::Error;
+
+use crate::cia402::{Cia402Target, StateRequest};
+use crate::state_machine::{ControlWord, StatusBit};
+use crate::PoulpeController;
+
+// wait for all the requests, returns the ids of the members that failed
+fn wait_all(requests: &[StateRequest]) -> Vec<u32> {
+    requests
+        .iter()
+        .filter(|r| r.wait().is_err())
+        .map(|r| r.id())
+        .collect()
+}
+
+impl PoulpeController {
+    // turn the torque of all the actuators on or off
+    pub fn set_torque_group(&self, ids: &[u32], on: bool) -> Result<(), Box<dyn Error>> {
+        if !on {
+            return self.disable_group(ids);
+        }
+
+        // verify the state of every member first
+        for &id in ids {
+            if !self.poulpe_config.contains_key(&(id as u16)) {
+                log::error!("Slave {} is not a poulpe board!", id);
+                return Err("Slave is not a poulpe board!".into());
+            }
+            let status_bits = self.get_status_bits(id as u16)?;
+            if status_bits.contains(&StatusBit::Fault) {
+                log::error!("Slave {} in fault state, group not enabled", id);
+                return Err("Slave in fault state status, group not enabled".into());
+            }
+            #[cfg(not(feature = "switchon_on_turnon"))]
+            if status_bits.contains(&StatusBit::SwitchedOnDisabled) {
+                log::error!(
+                    "Slave {} in SwitchedOnDisabled state, group not enabled",
+                    id
+                );
+                return Err("Slave in SwitchedOnDisabled state status, group not enabled".into());
+            }
+        }
+
+        let to_enable = ids
+            .iter()
+            .copied()
+            .filter(|&id| !matches!(self.is_torque_on(id), Ok(Some(true))))
+            .collect::<Vec<_>>();
+        if to_enable.is_empty() {
+            return Ok(());
+        }
+        log::debug!("Enabling the group {:?}", to_enable);
+
+        // a member whose target position cannot be set is a failed member
+        let mut failed = vec![];
+        if self.config.safety.safe_turn_on {
+            for &id in &to_enable {
+                if let Err(e) = self.copy_position_to_target(id) {
+                    log::error!("Slave {} | {}", id, e);
+                    failed.push(id);
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            let requests = self.request_states(&to_enable, Cia402Target::OperationEnabled)?;
+            failed = wait_all(&requests);
+            if failed.is_empty() {
+                return Ok(());
+            }
+        }
+
+        // rollback: disable all the members
+        log::error!(
+            "Slaves {:?} not enabled, disabling the group {:?}",
+            failed,
+            ids
+        );
+        if let Err(e) = self.rollback_group(ids) {
+            log::error!("Rollback of the group {:?} failed: {}", ids, e);
+        }
+        Err(format!("Slaves {:?} not enabled, group disabled", failed).into())
+    }
+
+    // disable all the members whatever their state: a member whose enable request timed out
+    // still has EnableOperation in its controlword and could reach OperationEnabled afterwards
+    fn rollback_group(&self, ids: &[u32]) -> Result<(), Box<dyn Error>> {
+        for &id in ids {
+            if let Err(e) = self.set_controlword(id as u16, ControlWord::DisableOperation.to_u16())
+            {
+                log::error!("Slave {} | failed to send the controlword: {}", id, e);
+            }
+        }
+
+        let requests = self.request_states(ids, Cia402Target::SwitchedOn)?;
+        let failed = wait_all(&requests);
+        if !failed.is_empty() {
+            log::error!("Slaves {:?} not disabled", failed);
+            return Err(format!("Slaves {:?} not disabled", failed).into());
+        }
+        Ok(())
+    }
+
+    fn disable_group(&self, ids: &[u32]) -> Result<(), Box<dyn Error>> {
+        let to_disable = ids
+            .iter()
+            .copied()
+            .filter(|&id| matches!(self.is_torque_on(id), Ok(Some(true))))
+            .collect::<Vec<_>>();
+        if to_disable.is_empty() {
+            return Ok(());
+        }
+        log::debug!("Disabling the group {:?}", to_disable);
+        for &id in &to_disable {
+            self.forget_target_position(id);
+        }
+
+        let requests = self.request_states(&to_disable, Cia402Target::SwitchedOn)?;
+        let failed = wait_all(&requests);
+        if !failed.is_empty() {
+            log::error!("Slaves {:?} not disabled", failed);
+            return Err(format!("Slaves {:?} not disabled", failed).into());
+        }
+        Ok(())
+    }
+}
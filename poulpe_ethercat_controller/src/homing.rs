@@ -0,0 +1,168 @@
+// CiA402 homing mode
+//
+// The homing method is written with an SDO (0x6098), the slave is switched to the homing
+// mode of operation and enabled, and the homing is started with the rising edge of the
+// operation mode specific bit 4 of the controlword.
+// The homing is followed with the statusword bits:
+// - bit 10: target reached
+// - bit 12: homing attained
+// - bit 13: homing error
+// Once finished the homing is stopped (bit 4 cleared), the torque is turned off and the
+// previous mode of operation is restored, on every exit path (error, timeout or fault). In the fault
+// state the mode is restored in the process image and applies once the fault is cleared.
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use crate::state_machine::{
+    parse_homing_error_flags, CiA402ModeOfOperation, ControlWord, HomingOutcome, HomingStatus,
+    StatusBit, HOMING_ATTAINED, HOMING_ERROR, HOMING_TARGET_REACHED,
+};
+use crate::PoulpeController;
+
+// homing method object (INTEGER8)
+const HOMING_METHOD_OBJECT: u16 = 0x6098;
+// time for the slave to switch to the homing mode of operation
+const MODE_SWITCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl PoulpeController {
+    // home the slave with the homing method (see the firmware for the supported methods)
+    // - the torque of the slave must be off
+    // - returns the outcome of the homing with the homing error flags of the board
+    pub fn home(
+        &self,
+        id: u32,
+        method: i8,
+        timeout: Duration,
+    ) -> Result<HomingOutcome, Box<dyn Error>> {
+        let slave_id = id as u16;
+        if !self.poulpe_config.contains_key(&slave_id) {
+            log::error!("Slave {} is not a poulpe board!", id);
+            return Err("Slave is not a poulpe board!".into());
+        }
+        if self.get_status_bits(slave_id)?.contains(&StatusBit::Fault) {
+            log::error!("Slave {} | Cannot start the homing in fault state!", id);
+            return Err("Cannot start the homing in fault state!".into());
+        }
+
+        // homing method (the SDO is written in PREOP with the torque off)
+        self.with_slave_in_preop(id, || {
+            Ok(self
+                .inner
+                .sdo_download(slave_id, HOMING_METHOD_OBJECT, 0, &method)?)
+        })?;
+
+        let previous_mode = self.get_mode_of_operation_display(slave_id)?;
+        let result = self.run_homing(id, method, timeout);
+        // stop the homing and restore the previous mode of operation whatever the result
+        let stopped = self.stop_homing(id, previous_mode);
+        let (status, duration) = result?;
+        stopped?;
+
+        let error_codes = self.get_error_codes(id)?;
+        let homing_error_flags = error_codes
+            .first()
+            .map(|e| parse_homing_error_flags((*e as u16).to_le_bytes()))
+            .unwrap_or_default();
+
+        match status {
+            HomingStatus::Attained => {
+                log::info!("Slave {} | homing attained in {:?}", id, duration)
+            }
+            _ => log::error!(
+                "Slave {} | homing failed: {:?}, homing error flags: {:?}",
+                id,
+                status,
+                homing_error_flags
+            ),
+        }
+
+        Ok(HomingOutcome {
+            status,
+            homing_error_flags,
+            duration,
+        })
+    }
+
+    // switch to the homing mode, enable the slave and run the homing until it finishes
+    fn run_homing(
+        &self,
+        id: u32,
+        method: i8,
+        timeout: Duration,
+    ) -> Result<(HomingStatus, Duration), Box<dyn Error>> {
+        let slave_id = id as u16;
+        // switch to the homing mode
+        let homing_mode = CiA402ModeOfOperation::HomingMode as u8;
+        self.set_mode_of_operation(slave_id, homing_mode)?;
+        let start = Instant::now();
+        while self.get_mode_of_operation_display(slave_id)? != homing_mode {
+            if start.elapsed() > MODE_SWITCH_TIMEOUT {
+                log::error!("Slave {} | did not switch to the homing mode!", id);
+                return Err("Timeout switching to the homing mode".into());
+            }
+            self.inner.wait_for_next_cycle();
+        }
+
+        self.set_torque(id, true)?;
+
+        // start the homing (rising edge of the bit 4)
+        log::info!("Slave {} | homing with method {}", id, method);
+        let start = Instant::now();
+        self.set_controlword(slave_id, ControlWord::StartHoming.to_u16())?;
+        let status = loop {
+            let status_bits = self.get_status_bits(slave_id)?;
+            if status_bits.contains(&StatusBit::Fault) {
+                break HomingStatus::Fault;
+            }
+            if status_bits.contains(&HOMING_ERROR) {
+                break HomingStatus::Error;
+            }
+            if status_bits.contains(&HOMING_ATTAINED)
+                && status_bits.contains(&HOMING_TARGET_REACHED)
+            {
+                break HomingStatus::Attained;
+            }
+            if start.elapsed() > timeout {
+                break HomingStatus::TimedOut;
+            }
+            self.inner.wait_for_next_cycle();
+        };
+        Ok((status, start.elapsed()))
+    }
+
+    // clear the bit 4, turn the torque off and restore the mode of operation
+    // every step is tried, the first error is returned
+    fn stop_homing(&self, id: u32, previous_mode: u8) -> Result<(), Box<dyn Error>> {
+        let slave_id = id as u16;
+        let mut result = Ok(());
+        let mut keep_first_error = |step: Result<(), Box<dyn Error>>| {
+            if let Err(e) = step {
+                log::error!("Slave {} | failed stopping the homing: {}", id, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        };
+
+        match self.get_status_bits(slave_id) {
+            // (the fault reset starts from a cleared controlword)
+            Ok(status_bits) if status_bits.contains(&StatusBit::Fault) => keep_first_error(
+                self.set_controlword(slave_id, ControlWord::DisableVoltage.to_u16()),
+            ),
+            Ok(status_bits) => {
+                if status_bits.contains(&StatusBit::OperationEnabled) {
+                    keep_first_error(
+                        self.set_controlword(slave_id, ControlWord::EnableOperation.to_u16()),
+                    );
+                }
+                keep_first_error(self.set_torque(id, false));
+            }
+            Err(e) => keep_first_error(Err(e)),
+        }
+        keep_first_error(self.set_mode_of_operation(slave_id, previous_mode));
+        result
+    }
+}
@@ -2,14 +2,15 @@ use std::{
     collections::HashMap,
     error::Error,
     f32::consts::{E, PI},
-    sync::RwLock,
+    sync::{atomic::AtomicBool, Mutex, RwLock},
     time::Duration,
 };
 
 pub mod state_machine;
 use state_machine::{
     parse_homing_error_flags, parse_motor_error_flags, parse_state_from_status_bits,
-    parse_status_word, CiA402State, ControlWord, ErrorFlags, StatusBit,
+    parse_status_word, CiA402State, ControlWord, ErrorFlags, FaultRecovery, FaultRecoveryOutcome,
+    StatusBit,
 };
 
 extern crate num;
@@ -20,32 +21,79 @@ use bitvec::{prelude::*, ptr::read};
 
 use ethercat_controller::{
     config::{PoulpeKind, SlaveConfig},
-    Config, EtherCatController,
+    Config, EtherCatController, PdoValue, SlaveKey,
 };
 
 pub mod register;
 use register::PdoRegister;
 
+pub mod config;
+use config::{PoulpeConfig, PoulpeSafetyConfig};
+
+pub mod actuator;
+pub mod cia402;
+use cia402::{Cia402Target, StateRequest};
+pub mod group;
+pub mod homing;
+pub mod limits;
+use limits::{ActuatorLimits, CommandedTarget, LimitViolations, Setpoint};
+pub mod motion;
+pub mod thermal;
+use thermal::{ThermalLimits, ThermalState};
+pub mod pid;
+pub mod trajectory;
+pub use actuator::{Actuator, Orbita2dHandle, Orbita3dHandle, OrbitaHandle};
+
+// number of cycles the controlword bit 7 is cleared before the fault reset
+const FAULT_RESET_EDGE_CYCLES: usize = 3;
+// time for the slave to leave the fault state after the fault reset
+const FAULT_RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub struct PoulpeController {
     pub inner: EtherCatController,
     pub poulpe_config: HashMap<u16, PoulpeKind>,
+    // configuration in effect (safety policies, PID, limits, thermal and CiA402 settings)
+    config: PoulpeConfig,
+    // joint limits of the actuators (see limits)
+    joint_limits: HashMap<u16, ActuatorLimits>,
+    limit_violations: Mutex<HashMap<u16, LimitViolations>>,
+    // target positions commanded by the host by cycle (the reference of the max_step limit)
+    commanded_targets: Mutex<HashMap<u16, CommandedTarget>>,
+    // thermal protection of the actuators (see thermal)
+    thermal_limits: HashMap<u16, ThermalLimits>,
+    thermal_state: Mutex<HashMap<u16, ThermalState>>,
+    thermal_protection_started: AtomicBool,
+    // torque limit requested by the user (before the thermal derating)
+    torque_limit_request: Mutex<HashMap<u16, Vec<f32>>>,
+    // CiA402 state machines of the slaves driven by the EtherCAT cycle (see cia402)
+    state_machines: cia402::StateMachines,
 }
 
 impl PoulpeController {
     pub fn connect(filename: &str) -> Result<Self, Box<dyn Error>> {
         let config = Config::from_yaml(filename)?;
-
-        let controller = EtherCatController::open(
-            config.ethercat.master_id,
-            Duration::from_micros(config.ethercat.cycle_time_us as u64),
-            config.ethercat.command_drop_time_us,
-            config.ethercat.watchdog_timeout_ms,
-            config.ethercat.mailbox_wait_time_ms,
-        )?
+        let controller_config = PoulpeConfig::from_yaml(filename)?;
+        log::info!("Poulpe controller configuration: {:?}", controller_config);
+
+        let controller = match &config.ethercat.replay {
+            // replay the recorded process image instead of connecting to the robot
+            Some(replay) => EtherCatController::open_replay(
+                &replay.path,
+                replay.speed,
+                config.ethercat.command_drop_time_us,
+            )?,
+            None => {
+                let controller = EtherCatController::open_from_config(&config.ethercat)?;
+                if let Some(path) = &config.ethercat.process_image_log {
+                    controller.start_recording(path)?;
+                }
+                controller
+            }
+        }
         .wait_for_ready();
 
-        let mut poulpe_config = HashMap::new();
+        let mut poulpe_config: HashMap<u16, PoulpeKind> = HashMap::new();
 
         // get the list of connected slaves
         let slaves = controller.get_slave_ids_and_names();
@@ -64,17 +112,21 @@ impl PoulpeController {
             // from this point on we are sure we are dealing with a poulpe boards
 
             // check if slave with the same name already exists in the slave list
-            // if it does, throw an error and return
-            if poulpe_config
-                .values()
-                .any(|p: &PoulpeKind| p.name == slave_name)
-            {
+            // if it does and the slaves cannot be told apart by their alias, serial number or DXL_ID
+            // throw an error and return
+            let identity = controller.get_slave_identity(slave_id);
+            if let Some((other_id, _)) = poulpe_config.iter().find(|(id, p)| {
+                p.name == slave_name
+                    && !identity
+                        .zip(controller.get_slave_identity(**id))
+                        .is_some_and(|(a, b)| a.is_distinguishable_from(b))
+            }) {
                 log::error!(
                     "Slave {} with name {:?} already connected, possible duplicate names at ids: {} and {}",
                     slave_id,
                     slave_name,
                     slave_id,
-                    poulpe_config.iter().find(|(_, v)| v.name == slave_name).unwrap().0
+                    other_id
                 );
                 return Err("Duplicate slave!".into());
             }
@@ -102,12 +154,41 @@ impl PoulpeController {
             }
         }
 
+        let joint_limits =
+            limits::resolve_joint_limits(&controller_config.limits.joints, &poulpe_config)?;
+        let thermal_limits =
+            thermal::resolve_thermal_limits(&controller_config.thermal, &poulpe_config)?;
+
+        let state_machines = cia402::new_state_machines(poulpe_config.keys().copied());
+        cia402::spawn_driver(
+            controller.clone(),
+            std::sync::Arc::downgrade(&state_machines),
+            controller_config.cia402.timeouts,
+        );
+
         Ok(Self {
             inner: controller,
             poulpe_config,
+            config: controller_config,
+            joint_limits,
+            limit_violations: Mutex::new(HashMap::new()),
+            thermal_limits,
+            thermal_state: Mutex::new(HashMap::new()),
+            thermal_protection_started: AtomicBool::new(false),
+            torque_limit_request: Mutex::new(HashMap::new()),
+            commanded_targets: Mutex::new(HashMap::new()),
+            state_machines,
         })
     }
 
+    pub fn get_safety_config(&self) -> &PoulpeSafetyConfig {
+        &self.config.safety
+    }
+
+    pub fn get_config(&self) -> &PoulpeConfig {
+        &self.config
+    }
+
     // function that checks if the time is longer that dropping time
     // returns true if its longer and false if not
     pub fn check_if_too_old(&self, message_ellased_time: Duration) -> bool {
@@ -126,13 +207,18 @@ impl PoulpeController {
         self.poulpe_config.get(&slave_id).map(|x| x.name.clone())
     }
 
+    // the slave can be identified by its name or by a key (ex. "dxl:3", "alias:12", see SlaveKey)
+    // None if no poulpe or more than one poulpe match
     pub fn get_slave_id(&self, name: &str) -> Option<u32> {
-        for (id, poulpe) in &self.poulpe_config {
-            if poulpe.name == name {
-                return Some(*id as u32);
+        let key = name.parse::<SlaveKey>().ok()?;
+        match self.inner.find_slave(&key) {
+            Ok(id) if self.poulpe_config.contains_key(&id) => Some(id as u32),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Slave {:?} | {}", name, e);
+                None
             }
         }
-        None
     }
 
     pub fn get_slave_names(&self) -> Vec<String> {
@@ -155,41 +241,123 @@ impl PoulpeController {
     }
 
     fn get_status_bits(&self, slave_id: u16) -> Result<Vec<StatusBit>, Box<dyn Error>> {
-        let status_word = self.get_pdo_register(slave_id, PdoRegister::StatusWord, 0)?;
-        let bits = u16::from_le_bytes(status_word.try_into().unwrap());
+        let bits = self.get_pdo_value::<u16>(slave_id, PdoRegister::StatusWord, 0)?;
         Ok(parse_status_word(bits))
     }
 
     pub fn get_mode_of_operation(&self, slave_id: u16) -> Result<u8, Box<dyn Error>> {
-        let mode_of_opearation = self.get_pdo_register(slave_id, PdoRegister::ModeOfOperation, 0);
+        let mode_of_opearation =
+            self.get_pdo_value::<u8>(slave_id, PdoRegister::ModeOfOperation, 0);
         match mode_of_opearation {
-            Ok(b) => Ok(b[0]),
+            Ok(b) => Ok(b),
             Err(_) => Err("Error reading mode of operation".into()),
         }
     }
     pub fn get_mode_of_operation_display(&self, slave_id: u16) -> Result<u8, Box<dyn Error>> {
         let mode_of_operation_display =
-            self.get_pdo_register(slave_id, PdoRegister::ModeOfOperationDisplay, 0);
+            self.get_pdo_value::<u8>(slave_id, PdoRegister::ModeOfOperationDisplay, 0);
         match mode_of_operation_display {
-            Ok(b) => Ok(b[0]),
+            Ok(b) => Ok(b),
             Err(_) => Err("Error reading mode of operation display".into()),
         }
     }
 
-    fn clear_fault(&self, slave_id: u16) -> Result<(), Box<dyn Error>> {
-        Ok(())
+    // clear the fault of the slave and set it up again (up to the SwitchedOn state)
+    // - the error flags are read and reported
+    // - the fault reset is sent as a 0 -> 1 edge of the controlword bit 7
+    //   and the slave should go to the SwitchOnDisabled state
+    // - if a non recoverable fault is still present the slave is not set up again
+    pub fn clear_fault(&self, id: u32) -> Result<FaultRecovery, Box<dyn Error>> {
+        let slave_id = id as u16;
+
+        let error_flags = self.get_error_flags(slave_id)?;
+        let state = self.get_status(id)?;
+        if !self.get_status_bits(slave_id)?.contains(&StatusBit::Fault) {
+            log::info!("Slave {} | not in fault state ({:?})", slave_id, state);
+            return Ok(FaultRecovery {
+                outcome: FaultRecoveryOutcome::NotInFault,
+                remaining_error_flags: error_flags.clone(),
+                error_flags,
+                state,
+            });
+        }
+        log::warn!(
+            "Slave {} | clearing the fault, error flags: {:#x?}",
+            slave_id,
+            error_flags
+        );
+
+        // the fault reset is done on the rising edge of the bit 7
+        // the bit is cleared for a few cycles so that the slave sees the edge
+        self.set_controlword(slave_id, ControlWord::DisableVoltage.to_u16())?;
+        for _ in 0..FAULT_RESET_EDGE_CYCLES {
+            self.inner.wait_for_next_cycle();
+        }
+        self.set_controlword(slave_id, ControlWord::FaultReset.to_u16())?;
+
+        // wait for the slave to leave the fault state
+        let start = std::time::Instant::now();
+        let cleared = loop {
+            let status_bits = self.get_status_bits(slave_id)?;
+            if !status_bits.contains(&StatusBit::Fault)
+                && status_bits.contains(&StatusBit::SwitchedOnDisabled)
+            {
+                break true;
+            }
+            if start.elapsed() > FAULT_RESET_TIMEOUT {
+                break false;
+            }
+            self.inner.wait_for_next_cycle();
+        };
+        self.set_controlword(slave_id, ControlWord::DisableVoltage.to_u16())?;
+
+        let remaining_error_flags = self.get_error_flags(slave_id)?;
+        let non_recoverable = remaining_error_flags.non_recoverable();
+        let outcome = if !cleared {
+            log::error!(
+                "Slave {} | still in fault state after the fault reset, error flags: {:#x?}",
+                slave_id,
+                remaining_error_flags
+            );
+            FaultRecoveryOutcome::StillInFault
+        } else if !non_recoverable.is_empty() {
+            log::error!(
+                "Slave {} | non recoverable fault, the board needs a restart: {:#x?}",
+                slave_id,
+                non_recoverable
+            );
+            FaultRecoveryOutcome::NonRecoverable
+        } else {
+            // set up the slave again (up to the SwitchedOn state)
+            self.set_poulpe_setup(id, false);
+            self.setup(id)?;
+            log::info!("Slave {} | fault cleared", slave_id);
+            FaultRecoveryOutcome::Recovered
+        };
+
+        Ok(FaultRecovery {
+            outcome,
+            error_flags,
+            remaining_error_flags,
+            state: self.get_status(id)?,
+        })
     }
 
+    // (cancels the active CiA402 state request of the slave, see cia402)
     fn set_controlword(&self, slave_id: u16, value: u16) -> Result<(), Box<dyn Error>> {
-        self.set_pdo_register(slave_id, PdoRegister::ControlWord, 0, &value.to_le_bytes())
+        self.cancel_state_request(slave_id as u32);
+        self.set_pdo_value::<u16>(slave_id, PdoRegister::ControlWord, 0, value)
     }
 
     pub fn get_error_flags(&self, slave_id: u16) -> Result<ErrorFlags, Box<dyn Error>> {
-        let error_codes = self.get_pdo_registers(slave_id, PdoRegister::ErrorCode)?;
-        let homing_error_flags = parse_homing_error_flags(error_codes[0][0..2].try_into().unwrap());
+        let error_codes = self.get_pdo_values::<u16>(slave_id, PdoRegister::ErrorCode)?;
+        if error_codes.is_empty() {
+            return Err("No error codes".into());
+        }
+        let homing_error_flags = parse_homing_error_flags(error_codes[0].to_le_bytes());
         let mut motor_error_flags = vec![Vec::new(); error_codes.len() - 1];
         for (i, e) in error_codes.iter().skip(1).enumerate() {
-            motor_error_flags[i] = parse_motor_error_flags(e[0..2].try_into().unwrap());
+            motor_error_flags[i] = parse_motor_error_flags(e.to_le_bytes());
         }
 
         Ok(ErrorFlags {
@@ -198,83 +366,38 @@ impl PoulpeController {
         })
     }
 
-    fn wait_for_status_bit(
-        &self,
-        slave_id: u16,
-        bit: StatusBit,
-        timeout: Duration,
-    ) -> Result<(), Box<dyn Error>> {
-        let status_bits = self.get_status_bits(slave_id);
-        log::debug!("Waiting for {:?} ({:?})", bit, status_bits);
-
-        let start = std::time::Instant::now();
-        loop {
-            let status_bits = self.get_status_bits(slave_id)?;
-
-            if status_bits.contains(&StatusBit::Fault) {
-                // log::error!(
-                //     "Slave {} in Fault state \n {:#x?}",
-                //     slave_id,
-                //     self.get_error_flags(slave_id)?,
-                // );
-                return Err("Fault status".into());
-            }
-
-            if status_bits.contains(&bit) {
-                // bit is set
-                break;
-            }
-            if start.elapsed() > timeout {
-                log::error!("Timeout waiting for {:?} on slave {:?}", bit, slave_id);
-                return Err("Timeout waiting for bit".into());
-            }
-            self.inner.wait_for_next_cycle();
-        }
-        Ok(())
-    }
-
-    fn get_pdo_register(
+    fn get_pdo_value<T: PdoValue>(
         &self,
         slave_id: u16,
         reg: PdoRegister,
         index: usize,
-    ) -> Result<Vec<u8>, Box<dyn Error>> {
-        Ok(self
-            .inner
-            .get_pdo_register(slave_id, &reg.name().to_string(), index)
-            .unwrap())
+    ) -> Result<T, Box<dyn Error>> {
+        Ok(self.inner.get::<T>(slave_id, reg.name(), index)?)
     }
-    fn set_pdo_register(
+    fn set_pdo_value<T: PdoValue>(
         &self,
         slave_id: u16,
         reg: PdoRegister,
         index: usize,
-        value: &[u8],
+        value: T,
     ) -> Result<(), Box<dyn Error>> {
-        self.inner
-            .set_pdo_register(slave_id, &reg.name().to_string(), index, value.to_vec());
-        Ok(())
+        Ok(self.inner.set::<T>(slave_id, reg.name(), index, value)?)
     }
 
-    fn get_pdo_registers(
+    fn get_pdo_values<T: PdoValue>(
         &self,
         slave_id: u16,
         reg: PdoRegister,
-    ) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
-        Ok(self
-            .inner
-            .get_pdo_registers(slave_id, &reg.name().to_string())
-            .unwrap())
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        Ok(self.inner.get_all::<T>(slave_id, reg.name())?)
     }
-    fn set_pdo_registers(
+    fn set_pdo_values<T: PdoValue>(
         &self,
         slave_id: u16,
         reg: PdoRegister,
-        values: Vec<Vec<u8>>,
+        values: &[T],
     ) -> Result<(), Box<dyn Error>> {
-        self.inner
-            .set_pdo_registers(slave_id, &reg.name().to_string(), values);
-        Ok(())
+        Ok(self.inner.set_all::<T>(slave_id, reg.name(), values)?)
     }
 }
 
@@ -366,7 +489,7 @@ impl PoulpeController {
         log::info!("Slave {}, inital state: {:?}", slave_id, state);
 
         // get staus bits
-        let mut status_bits = self.get_status_bits(slave_id)?;
+        let status_bits = self.get_status_bits(slave_id)?;
 
         if status_bits.contains(&StatusBit::Warning) {
             log::warn!(
@@ -384,14 +507,12 @@ impl PoulpeController {
                 self.get_error_flags(slave_id)?,
             );
 
-            #[cfg(not(feature = "allow_fault_on_slave"))]
-            return Err("Fault status".into());
-            #[cfg(feature = "allow_fault_on_slave")]
-            {
-                // turn off all the slaves if one of them is in fault state
-                self.emergency_stop_all(slave_id)?;
-                return Ok(());
+            if !self.config.safety.allow_fault_on_slave {
+                return Err("Fault status".into());
             }
+            // turn off all the slaves if one of them is in fault state
+            self.emergency_stop_all(slave_id)?;
+            return Ok(());
         }
 
         if state == CiA402State::NotReadyToSwitchOn {
@@ -399,59 +520,24 @@ impl PoulpeController {
                 "Slave {} in NotReadyToSwitchOn state, waiting to be rady for SwitchOn",
                 slave_id
             );
-            // wait 1s
-            std::thread::sleep(std::time::Duration::from_secs(1));
-            self.wait_for_status_bit(
-                slave_id,
-                StatusBit::SwitchedOnDisabled,
-                Duration::from_secs(100),
-            )?;
-
-            // get staus bits
-            status_bits = self.get_status_bits(slave_id)?;
         }
 
         // if enabled (should not be possible in normal operation)
         if status_bits.contains(&StatusBit::OperationEnabled) {
-            #[cfg(feature = "turn_off_slaves_setup")]
-            {
+            if self.config.safety.turn_off_slaves_setup {
                 // if the operation is enabled, we need
-                // to disable it before we can set the controlword
-                self.emergency_stop(id)?;
+                // to disable it before we can set the controlword (quick stop)
                 log::warn!("Slave {} in OperationEnabled state, turning off", slave_id);
-                self.wait_for_status_bit(
-                    slave_id,
-                    StatusBit::SwitchedOnDisabled,
-                    Duration::from_secs(20),
-                )?; // wait 20s (quick stop can take up to 10s)
-                    // get staus bits
-                status_bits = self.get_status_bits(slave_id)?;
-            }
-            #[cfg(not(feature = "turn_off_slaves_setup"))]
-            {
+                self.request_state(id, Cia402Target::SwitchOnDisabled)?
+                    .wait()?;
+            } else {
                 log::info!("Slave {}, setup done! Current state: {:?}", slave_id, state);
                 return Ok(());
             }
         }
 
-        // if switch on disabled, we need to switch on
-        if status_bits.contains(&StatusBit::SwitchedOnDisabled) {
-            // go to the ready to switch on state
-            self.set_controlword(slave_id, ControlWord::Shutdown.to_u16())?;
-        }
-
-        // here the slave shoulde be in ready to switch on state)
-        self.wait_for_status_bit(slave_id, StatusBit::ReadyToSwitchOn, Duration::from_secs(1))?;
-        status_bits = self.get_status_bits(slave_id)?;
-
-        // if ready to switch on, we need to switch on
-        if status_bits.contains(&StatusBit::ReadyToSwitchOn) {
-            // go to the switched on state
-            self.set_controlword(slave_id, ControlWord::SwitchOn.to_u16())?;
-            self.wait_for_status_bit(slave_id, StatusBit::SwitchedOn, Duration::from_secs(1))?;
-        }
-
-        let state: CiA402State = self.get_status(slave_id as u32)?;
+        // go to the switched on state (once the slave is ready)
+        let state = self.request_state(id, Cia402Target::SwitchedOn)?.wait()?;
         log::info!("Slave {}, setup done! Current state: {:?}", slave_id, state);
 
         // set the slave as setup
@@ -471,7 +557,23 @@ impl PoulpeController {
         id: u32,
         requested_torque: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(request) = self.request_torque(id, requested_torque)? {
+            request.wait()?;
+        }
+        Ok(())
+    }
+
+    // same as set_torque without waiting for the transition
+    // returns the state request (None if there is nothing to do or if the same request is running)
+    pub fn request_torque(
+        &self,
+        id: u32,
+        requested_torque: bool,
+    ) -> Result<Option<StateRequest>, Box<dyn std::error::Error>> {
         let slave_id = id as u16;
+        if !requested_torque {
+            self.forget_target_position(id);
+        }
         match self.is_torque_on(id) {
             Ok(Some(actual_torque)) => {
                 log::debug!(
@@ -482,9 +584,10 @@ impl PoulpeController {
                 );
                 let status_bits = self.get_status_bits(slave_id)?;
                 if status_bits.contains(&StatusBit::Fault) {
-                    #[cfg(feature = "allow_fault_on_slave")]
                     // return ok if the slave is in the fault state - dont try to set the torque
-                    return Ok(());
+                    if self.config.safety.allow_fault_on_slave {
+                        return Ok(None);
+                    }
 
                     // return error if the slave is in fault state - dont try to set the torque
                     log::error!("Slave {} in fault state", slave_id);
@@ -492,10 +595,11 @@ impl PoulpeController {
                 }
                 #[cfg(not(feature = "switchon_on_turnon"))]
                 if status_bits.contains(&StatusBit::SwitchedOnDisabled) && requested_torque {
-                    #[cfg(feature = "allow_fault_on_slave")]
                     // return ok if the slave is in switch on disabled state
                     // the board is probably been turned off by a quick stop
-                    return Ok(());
+                    if self.config.safety.allow_fault_on_slave {
+                        return Ok(None);
+                    }
 
                     // return error if the slave is in fault state - dont try to set the torque
                     log::error!(
@@ -507,84 +611,61 @@ impl PoulpeController {
                     );
                 }
 
-                if actual_torque == requested_torque {
-                    return Ok(());
-                } else {
-                    // if turn on is requested, set the target position to the current position - safety feature
-                    if requested_torque {
-                        #[cfg(feature = "safe_turn_on")]
-                        {
-                            // set the target position to the current position
-                            let current_position = self.get_current_position(id).unwrap().unwrap();
-                            self.set_target_position(id, current_position.clone())
-                                .unwrap();
-
-                            // verify that the target position is set correctly and try 5 times
-                            let mut target_position =
-                                self.get_current_target_position(id).unwrap().unwrap();
-                            let mut tries = 0;
-                            // check if the target position is set correctly (small error margin)
-                            while tries < 5
-                                && (current_position
-                                    .iter()
-                                    .zip(target_position.iter())
-                                    .all(|(a, b)| (a - b).abs() > 0.001))
-                            {
-                                self.set_target_position(id, current_position.clone())
-                                    .unwrap();
-                                std::thread::sleep(std::time::Duration::from_millis(2));
-                                target_position =
-                                    self.get_current_target_position(id).unwrap().unwrap();
-                                tries += 1;
-                            }
-                            // throw error if the target position is not set correctly
-                            if tries == 5 {
-                                log::error!("Error setting target position!");
-                                return Err("Error setting target position!".into());
-                            }
-                        }
-
-                        #[cfg(feature = "switchon_on_turnon")]
-                        {
-                            // Switch on
-                            self.set_controlword(slave_id, ControlWord::SwitchOn.to_u16())?;
-                            self.wait_for_status_bit(
-                                slave_id,
-                                StatusBit::SwitchedOn,
-                                Duration::from_secs(1),
-                            )?;
-                        }
-
-                        // Enable
-                        self.set_controlword(slave_id, ControlWord::EnableOperation.to_u16())?;
-                        self.wait_for_status_bit(
-                            slave_id,
-                            StatusBit::OperationEnabled,
-                            Duration::from_millis(20),
-                        )?;
-                    } else {
-                        // Shutdown
-                        self.set_controlword(slave_id, ControlWord::DisableOperation.to_u16())?;
-                        self.wait_for_status_bit(
-                            slave_id,
-                            StatusBit::SwitchedOn,
-                            Duration::from_millis(20),
-                        )?;
-                    }
+                let target = match requested_torque {
+                    true => Cia402Target::OperationEnabled,
+                    false => Cia402Target::SwitchedOn,
+                };
+                if actual_torque == requested_torque || self.is_state_requested(id, target) {
+                    return Ok(None);
+                }
+                // if turn on is requested, set the target position to the current position - safety feature
+                if requested_torque && self.config.safety.safe_turn_on {
+                    self.copy_position_to_target(id)?;
                 }
+                // Enable (switched on first if needed, see switchon_on_turnon) or Shutdown
+                Ok(Some(self.request_state(id, target)?))
             }
             _ => {
                 log::error!("Error getting torque state!");
-                return Err("Error getting torque state!".into());
+                Err("Error getting torque state!".into())
             }
         }
-        Ok(())
     }
 
-    fn get_pid(&self, _id: u32) -> Result<Option<(f32, f32, f32)>, Box<dyn std::error::Error>> {
-        Ok(None)
-    }
-    fn set_pid(&self, _id: u32, _pid: (f32, f32, f32)) -> Result<(), Box<dyn std::error::Error>> {
+    // set the target position to the current position (safe turn on)
+    // (not verified against the joint limits, the target must not move)
+    fn copy_position_to_target(&self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let current_position = self
+            .get_current_position(id)?
+            .ok_or("Error reading the current position!")?;
+        self.set_register_values(id, PdoRegister::TargetPosition, current_position.clone())?;
+
+        // check if the target position is set correctly (small error margin on every axis)
+        let is_set = |target_position: &[f32]| {
+            !current_position
+                .iter()
+                .zip(target_position.iter())
+                .any(|(a, b)| (a - b).abs() > 0.001)
+        };
+        // verify that the target position is set correctly and try 5 times
+        let mut target_position = self
+            .get_current_target_position(id)?
+            .ok_or("Error reading the target position!")?;
+        let mut tries = 0;
+        while tries < 5 && !is_set(&target_position) {
+            self.set_register_values(id, PdoRegister::TargetPosition, current_position.clone())?;
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            target_position = self
+                .get_current_target_position(id)?
+                .ok_or("Error reading the target position!")?;
+            tries += 1;
+        }
+        // throw error if the target position is not set correctly
+        if !is_set(&target_position) {
+            log::error!("Error setting target position!");
+            return Err("Error setting target position!".into());
+        }
+        self.record_target_position(id, current_position);
         Ok(())
     }
 
@@ -594,8 +675,8 @@ impl PoulpeController {
     }
 
     pub fn get_type(&self, slave_id: u32) -> u8 {
-        let byte = match self.get_pdo_register(slave_id as u16, PdoRegister::ActuatorType, 0) {
-            Ok(b) => b[0],
+        let byte = match self.get_pdo_value::<u8>(slave_id as u16, PdoRegister::ActuatorType, 0) {
+            Ok(b) => b,
             Err(_) => 255,
         };
         byte
@@ -621,7 +702,7 @@ impl PoulpeController {
                     );
                     return Err("Cannot change mode of operation when torque is on!".into());
                 }
-                false => self.set_pdo_register(slave_id, PdoRegister::ModeOfOperation, 0, &[value]),
+                false => self.set_pdo_value::<u8>(slave_id, PdoRegister::ModeOfOperation, 0, value),
             },
             _ => {
                 log::error!("Slave {} | Error getting torque state!", slave_id);
@@ -636,14 +717,8 @@ impl PoulpeController {
         register: PdoRegister,
     ) -> Result<Option<Vec<f32>>, Box<dyn std::error::Error>> {
         let slave_id = id as u16;
-        match self.get_pdo_registers(slave_id, register) {
-            Ok(bytes) => {
-                let values = bytes
-                    .iter()
-                    .map(|x| f32::from_le_bytes(x[0..4].try_into().unwrap()))
-                    .collect::<Vec<f32>>();
-                Ok(Some(values))
-            }
+        match self.get_pdo_values::<f32>(slave_id, register) {
+            Ok(values) => Ok(Some(values)),
             Err(_) => Err("Error reading register!".into()),
         }
     }
@@ -742,8 +817,7 @@ impl PoulpeController {
         values: Vec<f32>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let slave_id = id as u16;
-        let values_bytes: Vec<Vec<u8>> = values.iter().map(|&x| x.to_le_bytes().to_vec()).collect();
-        self.set_pdo_registers(slave_id, register, values_bytes)
+        self.set_pdo_values::<f32>(slave_id, register, &values)
     }
 
     pub fn set_target_position(
@@ -751,7 +825,10 @@ impl PoulpeController {
         id: u32,
         target_position: Vec<f32>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.set_register_values(id, PdoRegister::TargetPosition, target_position)
+        let target_position = self.enforce_limits(id, Setpoint::Position, target_position)?;
+        self.set_register_values(id, PdoRegister::TargetPosition, target_position.clone())?;
+        self.record_target_position(id, target_position);
+        Ok(())
     }
 
     pub fn set_velocity_limit(
@@ -767,7 +844,8 @@ impl PoulpeController {
         id: u32,
         torque_limit: Vec<f32>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.set_register_values(id, PdoRegister::TorqueLimit, torque_limit)
+        // derated by the thermal protection
+        self.write_torque_limit(id, torque_limit)
     }
 
     pub fn set_target_velocity(
@@ -775,6 +853,7 @@ impl PoulpeController {
         id: u32,
         target_velocity: Vec<f32>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let target_velocity = self.enforce_limits(id, Setpoint::Velocity, target_velocity)?;
         self.set_register_values(id, PdoRegister::TargetVelocity, target_velocity)
     }
 
@@ -783,25 +862,21 @@ impl PoulpeController {
         id: u32,
         target_torque: Vec<f32>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let target_torque = self.enforce_limits(id, Setpoint::Torque, target_torque)?;
         self.set_register_values(id, PdoRegister::TargetTorque, target_torque)
     }
 
     pub fn get_error_codes(&self, id: u32) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
         let slave_id = id as u16;
-        match self.get_pdo_registers(slave_id, PdoRegister::ErrorCode) {
-            Ok(bytes) => {
-                let mut error_codes = Vec::new();
-                for e in bytes.iter() {
-                    error_codes.push(u16::from_le_bytes(e[0..2].try_into().unwrap()) as u32);
-                }
-                Ok(error_codes)
-            }
+        match self.get_pdo_values::<u16>(slave_id, PdoRegister::ErrorCode) {
+            Ok(error_codes) => Ok(error_codes.iter().map(|&e| e as u32).collect()),
             Err(_) => Err("Error reading error codes!".into()),
         }
     }
 
     pub fn emergency_stop(&self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
         let slave_id = id as u16;
+        self.forget_target_position(id);
         self.set_controlword(slave_id, ControlWord::QuickStop.to_u16())
     }
 
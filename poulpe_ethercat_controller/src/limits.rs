@@ -0,0 +1,636 @@
+// Software joint limits of the actuators
+//
+// The setpoints are verified before being written to the PDOs:
+// - the values that are not finite (NaN, inf) are always rejected
+// - position range of each axis (set_target_position)
+// - max absolute target velocity (set_target_velocity) and target torque (set_target_torque)
+// - max step of the target position per EtherCAT cycle: the new target position is compared to
+//   the last one commanded in an earlier cycle (the one sent to the board), and the step allowed
+//   is max_step times the number of cycles since then (so the limit does not depend on the rate
+//   of the commands, several commands in the same cycle share one step)
+//   before the first command (and after the torque is turned off) the target position echoed by
+//   the board is the reference, with one cycle
+//
+// The limits are read per actuator (slave name) and per axis from the `joints` of the `limits` entry
+// of the `poulpe` section of the configuration file, the actuators without limits are only verified
+// for non finite values.
+// On a violation the reaction of the actuator (or the `reaction` of the `limits` entry) is applied:
+// - clamp: the values are clamped to the limits and written
+// - reject: nothing is written and an error is returned
+// - disable: nothing is written, the torque of the actuator is turned off and an error is returned
+// The non finite values cannot be clamped, they are rejected (or the actuator disabled).
+//
+// The violations are counted per actuator (see PoulpeController::get_limit_violations).
+
+use std::{collections::HashMap, error::Error};
+
+use ethercat_controller::config::PoulpeKind;
+use serde::{Deserialize, Serialize};
+
+use crate::PoulpeController;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitReaction {
+    Clamp,
+    #[default]
+    Reject,
+    Disable,
+}
+
+// read from the `limits` entry of the `poulpe` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    // reaction to the violations of the actuators without their own
+    pub reaction: LimitReaction,
+    // limits of the actuators by slave name
+    pub joints: HashMap<String, ActuatorLimits>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisLimits {
+    // [min, max] target position (rad)
+    pub position: Option<[f32; 2]>,
+    // max absolute target velocity
+    pub max_velocity: Option<f32>,
+    // max absolute target torque
+    pub max_torque: Option<f32>,
+    // max step of the target position per EtherCAT cycle (rad)
+    pub max_step: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActuatorLimits {
+    // reaction to the violations (the `reaction` of the `limits` entry if not set)
+    pub reaction: Option<LimitReaction>,
+    // limits of each axis
+    pub axes: Vec<AxisLimits>,
+}
+
+impl ActuatorLimits {
+    fn validate(&self, name: &str, axes: usize) -> Result<(), String> {
+        if self.axes.len() != axes {
+            return Err(format!(
+                "{:?} has {} axes, {} axis limits given",
+                name,
+                axes,
+                self.axes.len()
+            ));
+        }
+        for (axis, l) in self.axes.iter().enumerate() {
+            let maxima = [l.max_velocity, l.max_torque, l.max_step];
+            if maxima.iter().flatten().any(|m| !m.is_finite() || *m < 0.0)
+                || l.position
+                    .is_some_and(|[min, max]| !min.is_finite() || !max.is_finite() || min > max)
+            {
+                return Err(format!(
+                    "Invalid limits {:?} of {:?} axis {}",
+                    l, name, axis
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// number of violations of each kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LimitViolations {
+    pub non_finite: u64,
+    pub position: u64,
+    pub velocity: u64,
+    pub torque: u64,
+    pub step: u64,
+}
+
+impl LimitViolations {
+    pub fn total(&self) -> u64 {
+        self.non_finite + self.position + self.velocity + self.torque + self.step
+    }
+
+    fn add(&mut self, other: &LimitViolations) {
+        self.non_finite += other.non_finite;
+        self.position += other.position;
+        self.velocity += other.velocity;
+        self.torque += other.torque;
+        self.step += other.step;
+    }
+}
+
+// target positions commanded by the host for an actuator
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct CommandedTarget {
+    // last target position commanded and its cycle
+    cycle: u64,
+    position: Vec<f32>,
+    // last target position commanded in an earlier cycle (sent to the board) and its cycle
+    previous: Option<(u64, Vec<f32>)>,
+}
+
+impl CommandedTarget {
+    fn new(cycle: u64, position: Vec<f32>) -> Self {
+        CommandedTarget {
+            cycle,
+            position,
+            previous: None,
+        }
+    }
+
+    // target position sent to the board before the cycle and the number of cycles since then
+    fn reference(&self, cycle: u64) -> Option<(&[f32], u64)> {
+        let (sent_cycle, position) = match cycle == self.cycle {
+            true => self.previous.as_ref().map(|(c, p)| (*c, p.as_slice()))?,
+            false => (self.cycle, self.position.as_slice()),
+        };
+        Some((position, cycle.wrapping_sub(sent_cycle).max(1)))
+    }
+
+    fn record(&mut self, cycle: u64, position: Vec<f32>) {
+        if cycle != self.cycle {
+            let sent = std::mem::take(&mut self.position);
+            self.previous = Some((self.cycle, sent));
+            self.cycle = cycle;
+        }
+        self.position = position;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Setpoint {
+    Position,
+    Velocity,
+    Torque,
+}
+
+// limits of the actuators on the bus by slave id
+pub(crate) fn resolve_joint_limits(
+    joint_limits: &HashMap<String, ActuatorLimits>,
+    poulpe_config: &HashMap<u16, PoulpeKind>,
+) -> Result<HashMap<u16, ActuatorLimits>, Box<dyn Error>> {
+    let mut limits = HashMap::new();
+    for (name, l) in joint_limits {
+        match poulpe_config.values().find(|p| &p.name == name) {
+            Some(poulpe) => {
+                l.validate(name, poulpe.orbita_type as usize)?;
+                limits.insert(poulpe.id, l.clone());
+            }
+            None => log::warn!("Joint limits of {:?} but no such actuator on the bus", name),
+        }
+    }
+    Ok(limits)
+}
+
+// what is done with a setpoint
+#[derive(Debug, Clone, PartialEq)]
+enum LimitOutcome {
+    // write the values (clamped if the reaction is clamp)
+    Write(Vec<f32>),
+    Reject,
+    // reject the values and turn the torque of the actuator off
+    Disable,
+}
+
+// clamp the values to the limits of the axes and count the violations
+// (the non finite values are left as they are, see check_limits)
+// step_reference: target position the step is measured from and the number of cycles since it
+fn apply_limits(
+    setpoint: Setpoint,
+    axes: &[AxisLimits],
+    values: &[f32],
+    step_reference: Option<(&[f32], u64)>,
+) -> (Vec<f32>, LimitViolations) {
+    let mut violations = LimitViolations::default();
+    let clamped = values
+        .iter()
+        .enumerate()
+        .map(|(axis, &value)| {
+            let Some(l) = axes.get(axis).filter(|_| value.is_finite()) else {
+                return value;
+            };
+            let mut value = value;
+            let mut clamp = |min: f32, max: f32, count: &mut u64| {
+                if value < min || value > max {
+                    *count += 1;
+                    value = value.clamp(min, max);
+                }
+            };
+            match setpoint {
+                Setpoint::Position => {
+                    if let (Some(step), Some((reference, cycles))) = (l.max_step, step_reference) {
+                        if let Some(reference) = reference.get(axis) {
+                            let step = step * cycles as f32;
+                            clamp(reference - step, reference + step, &mut violations.step);
+                        }
+                    }
+                    if let Some([min, max]) = l.position {
+                        clamp(min, max, &mut violations.position);
+                    }
+                }
+                Setpoint::Velocity => {
+                    if let Some(max) = l.max_velocity {
+                        clamp(-max, max, &mut violations.velocity);
+                    }
+                }
+                Setpoint::Torque => {
+                    if let Some(max) = l.max_torque {
+                        clamp(-max, max, &mut violations.torque);
+                    }
+                }
+            }
+            value
+        })
+        .collect();
+    (clamped, violations)
+}
+
+// verify the values against the limits of the actuator (only for non finite values without limits)
+// the non finite values are never written, whatever the reaction
+fn check_limits(
+    setpoint: Setpoint,
+    limits: Option<&ActuatorLimits>,
+    default_reaction: LimitReaction,
+    values: Vec<f32>,
+    step_reference: Option<(&[f32], u64)>,
+) -> (LimitOutcome, LimitViolations) {
+    let reaction = limits.and_then(|l| l.reaction).unwrap_or(default_reaction);
+    let (clamped, mut violations) = match limits {
+        Some(l) => apply_limits(setpoint, &l.axes, &values, step_reference),
+        None => (values.clone(), LimitViolations::default()),
+    };
+    let non_finite = values.iter().any(|v| !v.is_finite());
+    if non_finite {
+        violations.non_finite += 1;
+    }
+    let outcome = match reaction {
+        _ if violations.total() == 0 => LimitOutcome::Write(values),
+        LimitReaction::Clamp if !non_finite => LimitOutcome::Write(clamped),
+        LimitReaction::Disable => LimitOutcome::Disable,
+        _ => LimitOutcome::Reject,
+    };
+    (outcome, violations)
+}
+
+impl PoulpeController {
+    // verify the setpoint against the limits of the actuator
+    // returns the values to write (clamped if the reaction is clamp)
+    pub(crate) fn enforce_limits(
+        &self,
+        id: u32,
+        setpoint: Setpoint,
+        values: Vec<f32>,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        let slave_id = id as u16;
+        let limits = self.joint_limits.get(&slave_id);
+
+        // the step is measured from the target position commanded by the host in an earlier
+        // cycle (the target position echoed by the slave lags by a few cycles) or from the echoed
+        // one before the first command
+        let max_step = setpoint == Setpoint::Position
+            && limits.is_some_and(|l| l.axes.iter().any(|a| a.max_step.is_some()));
+        let reference = match max_step {
+            true => {
+                let cycle = self.inner.get_cycle();
+                let commanded = self
+                    .commanded_targets
+                    .lock()
+                    .unwrap()
+                    .get(&slave_id)
+                    .and_then(|c| c.reference(cycle))
+                    .map(|(position, cycles)| (position.to_vec(), cycles));
+                match commanded {
+                    Some(commanded) => Some(commanded),
+                    None => self.get_current_target_position(id)?.map(|p| (p, 1)),
+                }
+            }
+            false => None,
+        };
+        let (outcome, violations) = check_limits(
+            setpoint,
+            limits,
+            self.config.limits.reaction,
+            values.clone(),
+            reference.as_ref().map(|(p, c)| (p.as_slice(), *c)),
+        );
+        if violations.total() == 0 {
+            return Ok(values);
+        }
+
+        self.limit_violations
+            .lock()
+            .unwrap()
+            .entry(slave_id)
+            .or_default()
+            .add(&violations);
+
+        match outcome {
+            LimitOutcome::Write(clamped) => {
+                log::debug!(
+                    "Slave {} | {:?} {:?} clamped to {:?}",
+                    id,
+                    setpoint,
+                    values,
+                    clamped
+                );
+                Ok(clamped)
+            }
+            LimitOutcome::Disable => {
+                log::error!(
+                    "Slave {} | {:?} {:?} out of the limits, disabling the actuator!",
+                    id,
+                    setpoint,
+                    values
+                );
+                // (not waiting for the transition, the setpoint path must not block)
+                self.request_torque(id, false)?;
+                Err("Setpoint out of the limits, actuator disabled".into())
+            }
+            LimitOutcome::Reject => {
+                log::warn!(
+                    "Slave {} | {:?} {:?} out of the limits, rejected",
+                    id,
+                    setpoint,
+                    values
+                );
+                Err("Setpoint out of the limits".into())
+            }
+        }
+    }
+
+    // record the target position written to the board (reference of the max_step limit)
+    pub(crate) fn record_target_position(&self, id: u32, position: Vec<f32>) {
+        let cycle = self.inner.get_cycle();
+        let mut commanded_targets = self.commanded_targets.lock().unwrap();
+        match commanded_targets.get_mut(&(id as u16)) {
+            Some(commanded) => commanded.record(cycle, position),
+            None => {
+                commanded_targets.insert(id as u16, CommandedTarget::new(cycle, position));
+            }
+        }
+    }
+
+    // forget the commanded target positions (on torque off), the next step is measured from the
+    // target position echoed by the board
+    pub(crate) fn forget_target_position(&self, id: u32) {
+        self.commanded_targets.lock().unwrap().remove(&(id as u16));
+    }
+
+    // number of limit violations of the actuator since the start
+    pub fn get_limit_violations(&self, id: u32) -> LimitViolations {
+        self.limit_violations
+            .lock()
+            .unwrap()
+            .get(&(id as u16))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn get_joint_limits(&self, id: u32) -> Option<&ActuatorLimits> {
+        self.joint_limits.get(&(id as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REACTIONS: [LimitReaction; 3] = [
+        LimitReaction::Clamp,
+        LimitReaction::Reject,
+        LimitReaction::Disable,
+    ];
+
+    fn actuator(axes: Vec<AxisLimits>) -> ActuatorLimits {
+        ActuatorLimits {
+            reaction: None,
+            axes,
+        }
+    }
+
+    fn position_limits(min: f32, max: f32) -> AxisLimits {
+        AxisLimits {
+            position: Some([min, max]),
+            ..Default::default()
+        }
+    }
+
+    fn step_limits(max_step: f32) -> AxisLimits {
+        AxisLimits {
+            max_step: Some(max_step),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn clamp_reject_disable() {
+        let limits = actuator(vec![position_limits(-1.0, 1.0), position_limits(-1.0, 1.0)]);
+        let expected = [
+            LimitOutcome::Write(vec![1.0, 0.5]),
+            LimitOutcome::Reject,
+            LimitOutcome::Disable,
+        ];
+        for (reaction, expected) in REACTIONS.into_iter().zip(expected) {
+            let (outcome, violations) = check_limits(
+                Setpoint::Position,
+                Some(&limits),
+                reaction,
+                vec![2.0, 0.5],
+                None,
+            );
+            assert_eq!(outcome, expected, "{:?}", reaction);
+            assert_eq!(violations.position, 1, "{:?}", reaction);
+            assert_eq!(violations.total(), 1, "{:?}", reaction);
+
+            // written as they are within the limits
+            let (outcome, violations) = check_limits(
+                Setpoint::Position,
+                Some(&limits),
+                reaction,
+                vec![-1.0, 0.5],
+                None,
+            );
+            assert_eq!(outcome, LimitOutcome::Write(vec![-1.0, 0.5]));
+            assert_eq!(violations, LimitViolations::default());
+        }
+
+        // the reaction of the actuator overrides the default one
+        let limits = ActuatorLimits {
+            reaction: Some(LimitReaction::Disable),
+            ..limits
+        };
+        let (outcome, _) = check_limits(
+            Setpoint::Position,
+            Some(&limits),
+            LimitReaction::Clamp,
+            vec![2.0, 0.5],
+            None,
+        );
+        assert_eq!(outcome, LimitOutcome::Disable);
+    }
+
+    #[test]
+    fn non_finite_values_never_written() {
+        let limits = actuator(vec![
+            AxisLimits {
+                position: Some([-1.0, 1.0]),
+                max_velocity: Some(1.0),
+                max_torque: Some(1.0),
+                max_step: Some(0.1),
+            };
+            2
+        ]);
+        let reference: &[f32] = &[0.0, 0.0];
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            for setpoint in [Setpoint::Position, Setpoint::Velocity, Setpoint::Torque] {
+                for reaction in REACTIONS {
+                    for limits in [Some(&limits), None] {
+                        let (outcome, violations) = check_limits(
+                            setpoint,
+                            limits,
+                            reaction,
+                            vec![value, 0.0],
+                            Some((reference, 1)),
+                        );
+                        let expected = match reaction {
+                            LimitReaction::Disable => LimitOutcome::Disable,
+                            _ => LimitOutcome::Reject,
+                        };
+                        assert_eq!(outcome, expected, "{} {:?} {:?}", value, setpoint, reaction);
+                        // only counted as non finite
+                        assert_eq!(violations.non_finite, 1);
+                        assert_eq!(violations.total(), 1);
+                    }
+                }
+            }
+        }
+
+        // not clamped (NaN is neither below nor above the limits)
+        let (values, violations) =
+            apply_limits(Setpoint::Position, &limits.axes, &[f32::NAN, 2.0], None);
+        assert!(values[0].is_nan());
+        assert_eq!(values[1], 1.0);
+        assert_eq!(violations.position, 1);
+    }
+
+    #[test]
+    fn step_clamping() {
+        let axes = [step_limits(0.1), step_limits(0.1)];
+        let reference: &[f32] = &[0.0, 1.0];
+
+        // one cycle since the reference
+        let (values, violations) = apply_limits(
+            Setpoint::Position,
+            &axes,
+            &[0.5, 0.95],
+            Some((reference, 1)),
+        );
+        assert_eq!(values, vec![0.1, 0.95]);
+        assert_eq!(violations.step, 1);
+
+        // the step allowed grows with the cycles since the reference
+        let (values, violations) =
+            apply_limits(Setpoint::Position, &axes, &[0.5, 0.0], Some((reference, 3)));
+        assert!((values[0] - 0.3).abs() < 1e-6, "{:?}", values);
+        assert!((values[1] - 0.7).abs() < 1e-6, "{:?}", values);
+        assert_eq!(violations.step, 2);
+
+        // no step verification without reference
+        let (values, violations) = apply_limits(Setpoint::Position, &axes, &[0.5, 0.0], None);
+        assert_eq!(values, vec![0.5, 0.0]);
+        assert_eq!(violations, LimitViolations::default());
+
+        // the step then the position range
+        let axes = [AxisLimits {
+            position: Some([-1.0, 0.2]),
+            max_step: Some(0.1),
+            ..Default::default()
+        }];
+        let reference: &[f32] = &[0.15];
+        let (values, violations) =
+            apply_limits(Setpoint::Position, &axes, &[0.5], Some((reference, 1)));
+        assert_eq!(values, vec![0.2]);
+        assert_eq!(violations.step, 1);
+        assert_eq!(violations.position, 1);
+    }
+
+    #[test]
+    fn limits_of_the_setpoint() {
+        let axes = [AxisLimits {
+            position: Some([-1.0, 1.0]),
+            max_velocity: Some(2.0),
+            max_torque: Some(0.5),
+            max_step: None,
+        }];
+        let (values, violations) = apply_limits(Setpoint::Velocity, &axes, &[-3.0], None);
+        assert_eq!(values, vec![-2.0]);
+        assert_eq!(violations.velocity, 1);
+        assert_eq!(violations.total(), 1);
+
+        let (values, violations) = apply_limits(Setpoint::Torque, &axes, &[3.0], None);
+        assert_eq!(values, vec![0.5]);
+        assert_eq!(violations.torque, 1);
+        assert_eq!(violations.total(), 1);
+
+        // the axes without limits are not verified
+        let (values, violations) = apply_limits(Setpoint::Position, &axes, &[0.5, 10.0], None);
+        assert_eq!(values, vec![0.5, 10.0]);
+        assert_eq!(violations, LimitViolations::default());
+    }
+
+    #[test]
+    fn violation_counters() {
+        let mut counters = LimitViolations::default();
+        let limits = actuator(vec![AxisLimits {
+            position: Some([-1.0, 1.0]),
+            max_velocity: Some(1.0),
+            max_torque: Some(1.0),
+            max_step: Some(0.1),
+        }]);
+        let reference: &[f32] = &[0.95];
+        let setpoints = [
+            (Setpoint::Position, 2.0),
+            (Setpoint::Position, 0.98),
+            (Setpoint::Velocity, 2.0),
+            (Setpoint::Torque, -2.0),
+            (Setpoint::Torque, f32::NAN),
+        ];
+        for (setpoint, value) in setpoints {
+            let (_, violations) = check_limits(
+                setpoint,
+                Some(&limits),
+                LimitReaction::Clamp,
+                vec![value],
+                Some((reference, 1)),
+            );
+            counters.add(&violations);
+        }
+        assert_eq!(
+            counters,
+            LimitViolations {
+                non_finite: 1,
+                // 2.0: clamped to 1.05 by the max step, then to 1.0 by the position range
+                position: 1,
+                velocity: 1,
+                torque: 1,
+                step: 1,
+            }
+        );
+        assert_eq!(counters.total(), 5);
+    }
+
+    #[test]
+    fn commanded_target_reference() {
+        let mut commanded = CommandedTarget::new(10, vec![0.1]);
+        // nothing sent before the first cycle
+        assert_eq!(commanded.reference(10), None);
+        assert_eq!(commanded.reference(12), Some((&[0.1][..], 2)));
+
+        // several commands in the same cycle share the same reference
+        commanded.record(12, vec![0.2]);
+        commanded.record(12, vec![0.3]);
+        assert_eq!(commanded.reference(12), Some((&[0.1][..], 2)));
+        assert_eq!(commanded.reference(13), Some((&[0.3][..], 1)));
+    }
+}
@@ -0,0 +1,388 @@
+// Host-side profile position moves
+//
+// The poulpe boards follow the target position sent every cycle, so a large step of the target
+// is followed at the velocity and torque limits of the board.
+// `move_to` plans a trapezoidal velocity profile for each axis (constant acceleration, constant
+// velocity, constant deceleration) and sends the planned position as target position every cycle
+// of the EtherCAT loop until the end of the move.
+//
+// The axes are synchronised: the duration of the move is the one of the slowest axis (the time
+// optimal profile with the max velocity and acceleration) and the peak velocity of the other axes
+// is lowered so that they finish at the same time.
+//
+// The following error (planned position - actual position) of each axis is followed during the
+// move and reported at the end.
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use crate::state_machine::StatusBit;
+use crate::PoulpeController;
+
+// trapezoidal velocity profile of one axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrapezoidalProfile {
+    start: f32,
+    distance: f32,
+    // direction of the move (1.0 or -1.0)
+    direction: f32,
+    velocity: f32,
+    acceleration: f32,
+    duration: f32,
+}
+
+impl TrapezoidalProfile {
+    // time optimal profile from start to target
+    pub fn new(start: f32, target: f32, max_vel: f32, max_acc: f32) -> Self {
+        let distance = (target - start).abs();
+        // the max velocity is not reached (triangular profile)
+        let velocity = max_vel.min((distance * max_acc).sqrt());
+        let duration = match velocity > 0.0 {
+            true => velocity / max_acc + distance / velocity,
+            false => 0.0,
+        };
+        TrapezoidalProfile {
+            start,
+            distance,
+            direction: if target >= start { 1.0 } else { -1.0 },
+            velocity,
+            acceleration: max_acc,
+            duration,
+        }
+    }
+
+    // same move lasting the duration (longer than the time optimal one)
+    // the acceleration is kept and the peak velocity lowered
+    fn stretch(&self, duration: f32) -> Self {
+        if duration <= self.duration || self.distance == 0.0 {
+            return *self;
+        }
+        // distance = v * (duration - v / acc)
+        let a = self.acceleration;
+        let discriminant = (a * a * duration * duration - 4.0 * a * self.distance).max(0.0);
+        TrapezoidalProfile {
+            velocity: (a * duration - discriminant.sqrt()) / 2.0,
+            duration,
+            ..*self
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn target(&self) -> f32 {
+        self.start + self.direction * self.distance
+    }
+
+    // planned position at the time t (s) since the start of the move
+    pub fn position(&self, t: f32) -> f32 {
+        if self.distance == 0.0 || t >= self.duration {
+            return self.target();
+        }
+        let t = t.max(0.0);
+        let a = self.acceleration;
+        let acceleration_time = self.velocity / a;
+        let travelled = if t < acceleration_time {
+            0.5 * a * t * t
+        } else if t < self.duration - acceleration_time {
+            0.5 * a * acceleration_time * acceleration_time
+                + self.velocity * (t - acceleration_time)
+        } else {
+            let remaining = self.duration - t;
+            self.distance - 0.5 * a * remaining * remaining
+        };
+        self.start + self.direction * travelled
+    }
+}
+
+// synchronised profiles of the axes of a slave
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionProfile {
+    axes: Vec<TrapezoidalProfile>,
+    duration: f32,
+}
+
+impl MotionProfile {
+    pub fn new(
+        start: &[f32],
+        target: &[f32],
+        max_vel: f32,
+        max_acc: f32,
+    ) -> Result<Self, Box<dyn Error>> {
+        if start.len() != target.len() {
+            return Err("Unexpected number of target positions".into());
+        }
+        if !(max_vel.is_finite() && max_vel > 0.0 && max_acc.is_finite() && max_acc > 0.0) {
+            return Err("The max velocity and acceleration must be finite and positive".into());
+        }
+        if start.iter().chain(target).any(|p| !p.is_finite()) {
+            return Err("Invalid position".into());
+        }
+
+        let axes: Vec<_> = start
+            .iter()
+            .zip(target)
+            .map(|(s, t)| TrapezoidalProfile::new(*s, *t, max_vel, max_acc))
+            .collect();
+        let duration = axes.iter().map(|p| p.duration()).fold(0.0, f32::max);
+        Ok(MotionProfile {
+            axes: axes.iter().map(|p| p.stretch(duration)).collect(),
+            duration,
+        })
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.duration)
+    }
+
+    pub fn position(&self, t: Duration) -> Vec<f32> {
+        let t = t.as_secs_f32();
+        self.axes.iter().map(|p| p.position(t)).collect()
+    }
+
+    pub fn target(&self) -> Vec<f32> {
+        self.axes.iter().map(|p| p.target()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MotionStatus {
+    // the whole profile has been sent
+    Completed,
+    // the torque has been turned off during the move (emergency stop for example)
+    TorqueOff,
+    // the slave went to the fault state during the move
+    Fault,
+}
+
+// result of a move (see PoulpeController::move_to)
+#[derive(Debug, Clone)]
+pub struct MotionOutcome {
+    pub status: MotionStatus,
+    // planned duration of the move
+    pub duration: Duration,
+    // max absolute following error of each axis during the move
+    pub max_following_error: Vec<f32>,
+    // following error of each axis at the end of the move
+    pub final_following_error: Vec<f32>,
+}
+
+impl PoulpeController {
+    // move the axes of the slave to the target positions
+    // - trapezoidal profile with the max velocity (rad/s) and acceleration (rad/s^2) on each axis
+    // - the torque of the slave must be on
+    // - blocks until the end of the move, the target position is updated every cycle
+    pub fn move_to(
+        &self,
+        id: u32,
+        target: &[f32],
+        max_vel: f32,
+        max_acc: f32,
+    ) -> Result<MotionOutcome, Box<dyn Error>> {
+        let axes = self.axes(id)?;
+        if target.len() != axes {
+            log::error!(
+                "Slave {} | expected {} target positions, got {}",
+                id,
+                axes,
+                target.len()
+            );
+            return Err("Unexpected number of target positions".into());
+        }
+        if self.is_torque_on(id)? != Some(true) {
+            log::error!("Slave {} | Cannot move with the torque off!", id);
+            return Err("Cannot move with the torque off!".into());
+        }
+
+        let start_position = self
+            .get_current_position(id)?
+            .ok_or("Error reading the current position!")?;
+        let profile = MotionProfile::new(&start_position, target, max_vel, max_acc)?;
+        log::debug!(
+            "Slave {} | move from {:?} to {:?} in {:?}",
+            id,
+            start_position,
+            target,
+            profile.duration()
+        );
+
+        let slave_id = id as u16;
+        let mut max_following_error = vec![0.0f32; axes];
+        let mut following_error = vec![0.0f32; axes];
+        let start = Instant::now();
+        let status = loop {
+            let t = start.elapsed();
+            let planned = profile.position(t);
+            self.set_target_position(id, planned.clone())?;
+
+            let status_bits = self.get_status_bits(slave_id)?;
+            if status_bits.contains(&StatusBit::Fault) {
+                break MotionStatus::Fault;
+            }
+            if !status_bits.contains(&StatusBit::OperationEnabled) {
+                break MotionStatus::TorqueOff;
+            }
+
+            if let Some(position) = self.get_current_position(id)? {
+                following_error = planned.iter().zip(&position).map(|(p, a)| p - a).collect();
+                for (max, e) in max_following_error.iter_mut().zip(&following_error) {
+                    *max = max.max(e.abs());
+                }
+            }
+
+            if t >= profile.duration() {
+                break MotionStatus::Completed;
+            }
+            self.inner.wait_for_next_cycle();
+        };
+
+        match status {
+            MotionStatus::Completed => log::debug!(
+                "Slave {} | move done, max following error {:?}",
+                id,
+                max_following_error
+            ),
+            _ => log::error!("Slave {} | move interrupted: {:?}", id, status),
+        }
+
+        Ok(MotionOutcome {
+            status,
+            duration: profile.duration(),
+            max_following_error,
+            final_following_error: following_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32, what: &str) {
+        assert!((a - b).abs() < 1e-3, "{}: {} != {}", what, a, b);
+    }
+
+    // max absolute velocity of the axes sampled every ms
+    fn max_velocities(profile: &MotionProfile) -> Vec<f32> {
+        let dt = 0.001;
+        let steps = (profile.duration / dt).ceil() as u32 + 1;
+        let mut max = vec![0.0f32; profile.axes.len()];
+        let mut previous = profile.position(Duration::ZERO);
+        for k in 1..=steps {
+            let position = profile.position(Duration::from_secs_f32(k as f32 * dt));
+            for (m, (p, q)) in max.iter_mut().zip(position.iter().zip(&previous)) {
+                *m = m.max((p - q).abs() / dt);
+            }
+            previous = position;
+        }
+        max
+    }
+
+    #[test]
+    fn triangular_profile() {
+        // the max velocity is not reached
+        let p = TrapezoidalProfile::new(0.0, 1.0, 2.0, 1.0);
+        assert_close(p.velocity, 1.0, "peak velocity");
+        assert_close(p.duration(), 2.0, "duration");
+        assert_close(p.position(0.5), 0.125, "position while accelerating");
+        assert_close(p.position(1.0), 0.5, "position at the peak velocity");
+        assert_close(p.position(1.5), 0.875, "position while decelerating");
+        assert_close(p.position(2.0), 1.0, "position at the end");
+    }
+
+    #[test]
+    fn trapezoidal_profile() {
+        // 1s of acceleration, 3s at the max velocity and 1s of deceleration
+        let p = TrapezoidalProfile::new(0.0, 4.0, 1.0, 1.0);
+        assert_close(p.velocity, 1.0, "peak velocity");
+        assert_close(p.duration(), 5.0, "duration");
+        assert_close(
+            p.position(1.0),
+            0.5,
+            "position at the end of the acceleration",
+        );
+        assert_close(p.position(2.5), 2.0, "position at the max velocity");
+        assert_close(p.position(4.5), 3.875, "position while decelerating");
+        assert_close(p.position(6.0), 4.0, "position after the end");
+
+        // same profile backwards
+        let p = TrapezoidalProfile::new(1.0, -3.0, 1.0, 1.0);
+        assert_close(p.duration(), 5.0, "duration backwards");
+        assert_close(p.position(2.5), -1.0, "position backwards");
+        assert_close(p.target(), -3.0, "target backwards");
+    }
+
+    #[test]
+    fn synchronised_finish_time() {
+        let profile = MotionProfile::new(&[0.0, 0.0, -1.0], &[4.0, 1.0, 1.0], 1.0, 1.0).unwrap();
+        // the duration of the slowest axis
+        assert_close(profile.duration, 5.0, "duration");
+        assert_eq!(profile.axes.len(), 3);
+        for (i, p) in profile.axes.iter().enumerate() {
+            assert_close(p.duration(), 5.0, &format!("duration of the axis {}", i));
+        }
+
+        // none of the axes reaches its target before the end
+        let before_end = profile.position(Duration::from_secs_f32(4.9));
+        for (i, (p, t)) in before_end.iter().zip(profile.target()).enumerate() {
+            assert!((p - t).abs() > 1e-3, "axis {} already at its target", i);
+        }
+        let end = profile.position(profile.duration());
+        for (i, (p, t)) in end.iter().zip(profile.target()).enumerate() {
+            assert_close(*p, t, &format!("position of the axis {} at the end", i));
+        }
+
+        // the peak velocity of the faster axes is lowered: 1 = v * (5 - v)
+        let v = (5.0 - 21.0f32.sqrt()) / 2.0;
+        assert_close(profile.axes[1].velocity, v, "stretched peak velocity");
+        let max = max_velocities(&profile);
+        assert!(max[0] <= 1.0 + 1e-2, "velocity of the axis 0: {}", max[0]);
+        assert!(max[1] <= v + 1e-2, "velocity of the axis 1: {}", max[1]);
+        assert!(max[2] <= 1.0 + 1e-2, "velocity of the axis 2: {}", max[2]);
+    }
+
+    #[test]
+    fn zero_distance_axes() {
+        let p = TrapezoidalProfile::new(0.3, 0.3, 1.0, 1.0);
+        assert_eq!(p.duration(), 0.0);
+        assert_eq!(p.position(0.0), 0.3);
+        assert_eq!(p.stretch(2.0), p);
+
+        // the axis stays in place while the others move
+        let profile = MotionProfile::new(&[0.0, 0.3], &[1.0, 0.3], 1.0, 1.0).unwrap();
+        for t in [0.0, 0.5, 1.0, 1.5, 2.0] {
+            let position = profile.position(Duration::from_secs_f32(t));
+            assert_eq!(position[1], 0.3, "position of the static axis at {}", t);
+        }
+
+        // no move at all
+        let profile = MotionProfile::new(&[0.1, 0.2], &[0.1, 0.2], 1.0, 1.0).unwrap();
+        assert_eq!(profile.duration(), Duration::ZERO);
+        assert_eq!(profile.position(Duration::ZERO), vec![0.1, 0.2]);
+    }
+
+    #[test]
+    fn invalid_limits_rejected() {
+        let start = [0.0, 0.0];
+        let target = [1.0, 1.0];
+        for max_vel in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(
+                MotionProfile::new(&start, &target, max_vel, 1.0).is_err(),
+                "max velocity {}",
+                max_vel
+            );
+        }
+        for max_acc in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(
+                MotionProfile::new(&start, &target, 1.0, max_acc).is_err(),
+                "max acceleration {}",
+                max_acc
+            );
+        }
+        assert!(MotionProfile::new(&start, &[1.0, f32::NAN], 1.0, 1.0).is_err());
+        assert!(MotionProfile::new(&start, &[1.0], 1.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+// PID gains of the control loops of the poulpe boards
+//
+// The gains are in the object dictionary of the board, one object per control loop and axis
+// (sub index 1: P, 2: I, 3: D, f32), and are accessed with SDOs.
+// The objects depend on the firmware of the board and are not in the ESI files, so they are read
+// from the `objects` of the `pid` entry of the `poulpe` section of the configuration file (per
+// actuator or default), taken from the object dictionary of the firmware. Without them the gains cannot be
+// accessed (nothing is ever written to an object that is not configured).
+// The board only answers the SDO requests in PREOP, so the slave is taken to PREOP for the
+// access and back to OP afterwards (the torque must be off). Each call of the getters and setters
+// is one PREOP/OP round trip (up to a few hundred ms), get_pid_loops reads several loops at once
+// and set_axes_pid writes the gains of several axes and saves them at once.
+// The gains written are used right away and lost on restart unless they are saved to the flash
+// of the board (CiA 301 store parameters object, 0x1010 sub index 1).
+
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use ethercat_controller::AlState;
+use serde::{Deserialize, Serialize};
+
+use crate::PoulpeController;
+
+// CiA 301 store parameters object (sub index 1: all parameters) and its "save" signature
+const STORE_PARAMETERS_OBJECT: u16 = 0x1010;
+const STORE_PARAMETERS_SIGNATURE: u32 = 0x65766173;
+
+// time for the slave to go to PREOP and back to OP
+const STATE_CHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlLoop {
+    Position,
+    Velocity,
+    Current,
+}
+
+// objects of the gains in the object dictionary of the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidObjects {
+    // object of the gains of the first axis of each control loop (+ axis index)
+    pub position: Option<u16>,
+    pub velocity: Option<u16>,
+    pub current: Option<u16>,
+    // store parameters object (None if the firmware cannot save the gains)
+    pub store_parameters: Option<u16>,
+}
+
+impl Default for PidObjects {
+    fn default() -> Self {
+        PidObjects {
+            position: None,
+            velocity: None,
+            current: None,
+            store_parameters: Some(STORE_PARAMETERS_OBJECT),
+        }
+    }
+}
+
+impl PidObjects {
+    fn object(&self, control_loop: ControlLoop, axis: usize) -> Option<u16> {
+        let base = match control_loop {
+            ControlLoop::Position => self.position,
+            ControlLoop::Velocity => self.velocity,
+            ControlLoop::Current => self.current,
+        };
+        base.map(|b| b + axis as u16)
+    }
+}
+
+// read from the `pid` entry of the `poulpe` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidConfig {
+    // max PID gains accepted by PoulpeController::set_pid
+    pub limits: PidLimits,
+    // objects of the PID gains in the object dictionary of the firmware
+    pub objects: PidObjectsConfig,
+}
+
+// read from the `objects` of the `pid` entry of the `poulpe` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidObjectsConfig {
+    // objects of the actuators that are not listed
+    pub default: Option<PidObjects>,
+    // objects by slave name (boards with another firmware)
+    pub actuators: HashMap<String, PidObjects>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PidGains {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+}
+
+impl PidGains {
+    fn to_array(self) -> [f32; 3] {
+        [self.p, self.i, self.d]
+    }
+}
+
+// max gains of each control loop
+// read from the `limits` of the `pid` entry of the `poulpe` section of the configuration file
+// the gains are always verified to be finite and positive, the max gains only if they are set
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidLimits {
+    pub position: Option<PidGains>,
+    pub velocity: Option<PidGains>,
+    pub current: Option<PidGains>,
+}
+
+impl PidLimits {
+    pub fn validate(&self, control_loop: ControlLoop, gains: &PidGains) -> Result<(), String> {
+        if gains.to_array().iter().any(|g| !g.is_finite() || *g < 0.0) {
+            return Err(format!(
+                "Invalid {:?} loop gains {:?}, the gains must be finite and positive",
+                control_loop, gains
+            ));
+        }
+        let max = match control_loop {
+            ControlLoop::Position => self.position,
+            ControlLoop::Velocity => self.velocity,
+            ControlLoop::Current => self.current,
+        };
+        if let Some(max) = max {
+            if gains
+                .to_array()
+                .iter()
+                .zip(max.to_array())
+                .any(|(g, m)| *g > m)
+            {
+                return Err(format!(
+                    "{:?} loop gains {:?} above the limits {:?}",
+                    control_loop, gains, max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PoulpeController {
+    // run the SDO requests with the slave in PREOP and bring it back to OP
+    pub(crate) fn with_slave_in_preop<T>(
+        &self,
+        id: u32,
+        f: impl FnOnce() -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let slave_id = id as u16;
+        if self.is_torque_on(id)? == Some(true) {
+            log::error!(
+                "Slave {} | Cannot access the SDOs when the torque is on!",
+                id
+            );
+            return Err("Cannot access the SDOs when the torque is on!".into());
+        }
+
+        self.inner
+            .request_slave_state(slave_id, AlState::PreOp, STATE_CHANGE_TIMEOUT)?;
+        let result = f();
+        // back to OP even if the requests failed
+        self.inner
+            .request_slave_state(slave_id, AlState::Op, STATE_CHANGE_TIMEOUT)?;
+        result
+    }
+
+    // objects of the gains of the slave (from the configuration)
+    fn pid_objects(&self, id: u32) -> Result<PidObjects, Box<dyn Error>> {
+        let name = &self
+            .poulpe_config
+            .get(&(id as u16))
+            .ok_or("Slave is not a poulpe board!")?
+            .name;
+        let config = &self.config.pid.objects;
+        match config.actuators.get(name).or(config.default.as_ref()) {
+            Some(objects) => Ok(*objects),
+            None => {
+                log::error!(
+                    "Slave {} | no PID objects configured for {:?} (pid objects)",
+                    id,
+                    name
+                );
+                Err("PID objects not configured".into())
+            }
+        }
+    }
+
+    fn pid_object(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        axis: usize,
+    ) -> Result<u16, Box<dyn Error>> {
+        match self.pid_objects(id)?.object(control_loop, axis) {
+            Some(object) => Ok(object),
+            None => {
+                log::error!(
+                    "Slave {} | no PID object configured for the {:?} loop",
+                    id,
+                    control_loop
+                );
+                Err("PID object not configured".into())
+            }
+        }
+    }
+
+    fn read_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        axis: usize,
+    ) -> Result<PidGains, Box<dyn Error>> {
+        let object = self.pid_object(id, control_loop, axis)?;
+        let mut gains = [0.0; 3];
+        for (i, gain) in gains.iter_mut().enumerate() {
+            let mut data = [0u8; 4];
+            self.inner
+                .sdo_upload(id as u16, object, i as u8 + 1, &mut data)?;
+            *gain = f32::from_le_bytes(data);
+        }
+        Ok(PidGains {
+            p: gains[0],
+            i: gains[1],
+            d: gains[2],
+        })
+    }
+
+    fn write_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        axis: usize,
+        gains: &PidGains,
+    ) -> Result<(), Box<dyn Error>> {
+        let object = self.pid_object(id, control_loop, axis)?;
+        for (i, gain) in gains.to_array().iter().enumerate() {
+            self.inner
+                .sdo_download(id as u16, object, i as u8 + 1, gain)?;
+        }
+        Ok(())
+    }
+
+    // number of axes of the slave
+    pub(crate) fn axes(&self, id: u32) -> Result<usize, Box<dyn Error>> {
+        match self.poulpe_config.get(&(id as u16)) {
+            Some(poulpe) => Ok(poulpe.orbita_type as usize),
+            None => {
+                log::error!("Slave {} is not a poulpe board!", id);
+                Err("Slave is not a poulpe board!".into())
+            }
+        }
+    }
+
+    fn check_axis(&self, id: u32, axis: usize) -> Result<(), Box<dyn Error>> {
+        let axes = self.axes(id)?;
+        if axis >= axes {
+            log::error!("Slave {} | no axis {} (only {} axes)", id, axis, axes);
+            return Err("Invalid axis".into());
+        }
+        Ok(())
+    }
+
+    // gains of the control loop of each axis of the slave
+    // (one PREOP/OP round trip per call, see get_pid_loops to read several loops)
+    pub fn get_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+    ) -> Result<Vec<PidGains>, Box<dyn Error>> {
+        let mut gains = self.get_pid_loops(id, &[control_loop])?;
+        Ok(gains.remove(0))
+    }
+
+    // gains of each axis of several control loops in one PREOP/OP round trip
+    pub fn get_pid_loops(
+        &self,
+        id: u32,
+        control_loops: &[ControlLoop],
+    ) -> Result<Vec<Vec<PidGains>>, Box<dyn Error>> {
+        let axes = self.axes(id)?;
+        // fail before leaving OP if an object is not configured
+        for &control_loop in control_loops {
+            self.pid_object(id, control_loop, 0)?;
+        }
+        self.with_slave_in_preop(id, || {
+            control_loops
+                .iter()
+                .map(|&control_loop| {
+                    (0..axes)
+                        .map(|axis| self.read_pid(id, control_loop, axis))
+                        .collect()
+                })
+                .collect()
+        })
+    }
+
+    // (one PREOP/OP round trip per call)
+    pub fn get_axis_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        axis: usize,
+    ) -> Result<PidGains, Box<dyn Error>> {
+        self.check_axis(id, axis)?;
+        self.pid_object(id, control_loop, axis)?;
+        self.with_slave_in_preop(id, || self.read_pid(id, control_loop, axis))
+    }
+
+    // set the gains of the control loop of each axis of the slave
+    // the gains are verified against the limits of the configuration before writing any of them
+    pub fn set_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        gains: &[PidGains],
+    ) -> Result<(), Box<dyn Error>> {
+        let axes = self.axes(id)?;
+        if gains.len() != axes {
+            log::error!(
+                "Slave {} | expected {} gains, got {}",
+                id,
+                axes,
+                gains.len()
+            );
+            return Err("Unexpected number of gains".into());
+        }
+        for g in gains {
+            self.config.pid.limits.validate(control_loop, g)?;
+        }
+        self.pid_object(id, control_loop, 0)?;
+        self.with_slave_in_preop(id, || {
+            for (axis, g) in gains.iter().enumerate() {
+                self.write_pid(id, control_loop, axis, g)?;
+            }
+            log::info!(
+                "Slave {} | {:?} loop gains set to {:?}",
+                id,
+                control_loop,
+                gains
+            );
+            Ok(())
+        })
+    }
+
+    // set the gains of the control loop of some axes of the slave (and save them to the flash of
+    // the board if persist) in one PREOP/OP round trip
+    // the axes and the gains are all verified before leaving OP, so nothing is written if one of
+    // them is invalid
+    pub fn set_axes_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        gains: &[(usize, PidGains)],
+        persist: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        for (k, (axis, g)) in gains.iter().enumerate() {
+            self.check_axis(id, *axis)?;
+            if gains[..k].iter().any(|(a, _)| a == axis) {
+                log::error!("Slave {} | gains of axis {} given twice", id, axis);
+                return Err("Duplicate axis".into());
+            }
+            self.config.pid.limits.validate(control_loop, g)?;
+            self.pid_object(id, control_loop, *axis)?;
+        }
+        let store_parameters = match persist {
+            true => Some(self.store_parameters_object(id)?),
+            false => None,
+        };
+        self.with_slave_in_preop(id, || {
+            for (axis, g) in gains {
+                self.write_pid(id, control_loop, *axis, g)?;
+            }
+            log::info!(
+                "Slave {} | {:?} loop gains set to {:?} (axis, gains)",
+                id,
+                control_loop,
+                gains
+            );
+            if let Some(object) = store_parameters {
+                self.store_parameters(id, object)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn set_axis_pid(
+        &self,
+        id: u32,
+        control_loop: ControlLoop,
+        axis: usize,
+        gains: PidGains,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_axis(id, axis)?;
+        self.config.pid.limits.validate(control_loop, &gains)?;
+        self.pid_object(id, control_loop, axis)?;
+        self.with_slave_in_preop(id, || {
+            self.write_pid(id, control_loop, axis, &gains)?;
+            log::info!(
+                "Slave {} | {:?} loop gains of axis {} set to {:?}",
+                id,
+                control_loop,
+                axis,
+                gains
+            );
+            Ok(())
+        })
+    }
+
+    // save the gains to the flash of the board (kept after a restart)
+    // (one PREOP/OP round trip per call, see set_axes_pid to set and save the gains at once)
+    pub fn save_pid(&self, id: u32) -> Result<(), Box<dyn Error>> {
+        let object = self.store_parameters_object(id)?;
+        self.with_slave_in_preop(id, || self.store_parameters(id, object))
+    }
+
+    fn store_parameters_object(&self, id: u32) -> Result<u16, Box<dyn Error>> {
+        match self.pid_objects(id)?.store_parameters {
+            Some(object) => Ok(object),
+            None => {
+                log::error!("Slave {} | no store parameters object configured", id);
+                Err("Store parameters object not configured".into())
+            }
+        }
+    }
+
+    // (the slave must be in PREOP)
+    fn store_parameters(&self, id: u32, object: u16) -> Result<(), Box<dyn Error>> {
+        self.inner
+            .sdo_download(id as u16, object, 1, &STORE_PARAMETERS_SIGNATURE)?;
+        log::info!("Slave {} | PID gains saved to flash", id);
+        Ok(())
+    }
+}
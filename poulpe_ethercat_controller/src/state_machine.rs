@@ -27,6 +27,7 @@ pub enum ControlWord {
     DisableOperation,
     QuickStop,
     FaultReset,
+    StartHoming, // enable operation + operation mode specific bit 4 (homing mode)
     Unknown,
 }
 
@@ -40,6 +41,7 @@ impl ControlWord {
             ControlWord::DisableOperation => 0b0111,
             ControlWord::QuickStop => 0b0010,
             ControlWord::FaultReset => 0b10000000,
+            ControlWord::StartHoming => 0b11111,
             ControlWord::Unknown => 0b0,
         }
     }
@@ -82,6 +84,19 @@ pub enum MotorErrorFlag {
     TemperatureSensorMalfunctionWarning = 8,
 }
 
+impl MotorErrorFlag {
+    // the fault can be cleared with a fault reset
+    // the configuration, alignment and driver faults need a restart of the board
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(
+            self,
+            MotorErrorFlag::ConfigFail
+                | MotorErrorFlag::MotorAlignFail
+                | MotorErrorFlag::DriverFault
+        )
+    }
+}
+
 // Error codes for the homing procedure
 // - None - no error
 // - AxisSensorReadFail - error during the reading of the axis sensor
@@ -101,6 +116,13 @@ pub enum HomingErrorFlag {
     LowLevelCommunicaiton = 5,
 }
 
+impl HomingErrorFlag {
+    // the homing is done once at the startup of the board, a homing fault needs a restart of the board
+    pub fn is_recoverable(&self) -> bool {
+        false
+    }
+}
+
 #[derive(FromPrimitive, PartialEq, Clone, Copy, Debug)]
 #[repr(u16)]
 pub enum CiA402State {
@@ -115,12 +137,91 @@ pub enum CiA402State {
     Fault = 0b00001000,           // fault state
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct ErrorFlags {
     pub motor_error_flags: Vec<Vec<MotorErrorFlag>>,
     pub homing_error_flags: Vec<HomingErrorFlag>,
 }
 
+impl ErrorFlags {
+    pub fn is_empty(&self) -> bool {
+        self.homing_error_flags.is_empty() && self.motor_error_flags.iter().all(|f| f.is_empty())
+    }
+
+    // the flags that cannot be cleared with a fault reset
+    pub fn non_recoverable(&self) -> ErrorFlags {
+        ErrorFlags {
+            motor_error_flags: self
+                .motor_error_flags
+                .iter()
+                .map(|flags| {
+                    flags
+                        .iter()
+                        .filter(|f| !f.is_recoverable())
+                        .copied()
+                        .collect()
+                })
+                .collect(),
+            homing_error_flags: self
+                .homing_error_flags
+                .iter()
+                .filter(|f| !f.is_recoverable())
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+// homing status bits (in the homing mode of operation)
+pub const HOMING_TARGET_REACHED: StatusBit = StatusBit::OperatingModeSpecific10;
+pub const HOMING_ATTAINED: StatusBit = StatusBit::OperatingModeSpecific12;
+pub const HOMING_ERROR: StatusBit = StatusBit::OperatingModeSpecific13;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HomingStatus {
+    // homing attained and target reached
+    Attained,
+    // the homing error bit is set
+    Error,
+    // the homing did not finish in time, it has been stopped
+    TimedOut,
+    // the slave went to the fault state during the homing
+    Fault,
+}
+
+// result of a homing (see PoulpeController::home)
+#[derive(Debug, Clone)]
+pub struct HomingOutcome {
+    pub status: HomingStatus,
+    // homing error flags of the board at the end of the homing
+    pub homing_error_flags: Vec<HomingErrorFlag>,
+    pub duration: std::time::Duration,
+}
+
+// result of a fault recovery (see PoulpeController::clear_fault)
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultRecoveryOutcome {
+    // the slave was not in the fault state, nothing was done
+    NotInFault,
+    // the fault is cleared and the slave is back in the SwitchedOn state
+    Recovered,
+    // the fault reset was sent but the slave is still in the fault state
+    StillInFault,
+    // a fault that cannot be cleared with a fault reset is present, the slave is not set up again
+    NonRecoverable,
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultRecovery {
+    pub outcome: FaultRecoveryOutcome,
+    // error flags read before the fault reset
+    pub error_flags: ErrorFlags,
+    // error flags still set after the fault reset
+    pub remaining_error_flags: ErrorFlags,
+    // state of the slave at the end of the recovery
+    pub state: CiA402State,
+}
+
 pub fn parse_status_word(status: u16) -> Vec<StatusBit> {
     let mut status_bits = Vec::new();
     for i in 0..16 {
@@ -174,6 +275,10 @@ pub fn parse_state_from_status_bits(
     // remove the warning bit
     state = state & 0b1111111101111111;
 
+    // remove the operation mode specific bits (10, 12 and 13, ex. homing attained and homing error)
+    // and the internal limit active bit (11)
+    state &= 0b1100001111111111;
+
     match num::FromPrimitive::from_u16(state) {
         Some(s) => Ok(s),
         None => Err("Invalid state".into()),
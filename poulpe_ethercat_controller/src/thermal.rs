@@ -0,0 +1,401 @@
+// Host-side thermal protection of the actuators
+//
+// The firmware only sets the HighTemperatureWarning flag and goes to the fault state once the
+// motor or the board is too hot (OverTemperatureMotor, OverTemperatureBoard). The thermal protection
+// follows the motor and board temperatures of each axis and acts before the firmware trips:
+// - warning: an event is emitted (also if the firmware sets the HighTemperatureWarning flag)
+// - derating: the torque limit of the axis is lowered linearly from 100% at `derating_start` to
+//   `min_torque_ratio` at `derating_end`
+// - compliant: (optional) the torque of the actuator is turned off
+//
+// The thresholds (°C) are read from the `thermal` entry of the `poulpe` section of the configuration
+// file, per actuator (slave name) or for all the actuators. A level is left once the temperature is
+// `hysteresis` below its threshold.
+//
+// The torque limit set with set_torque_limit is kept as the requested torque limit, the torque
+// limit written to the board is the requested one scaled by the derating ratio of the axis.
+//
+// The protection runs in its own thread (see PoulpeController::start_thermal_protection) and
+// emits an event on each change of the thermal level of an actuator.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{atomic::Ordering, mpsc, Arc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use ethercat_controller::config::PoulpeKind;
+use serde::{Deserialize, Serialize};
+
+use crate::register::PdoRegister;
+use crate::state_machine::MotorErrorFlag;
+use crate::PoulpeController;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureThresholds {
+    pub warning: f32,
+    pub derating_start: f32,
+    pub derating_end: f32,
+    // turn off the torque above this temperature (optional)
+    pub compliant: Option<f32>,
+}
+
+impl TemperatureThresholds {
+    fn validate(&self) -> Result<(), String> {
+        let valid = [self.warning, self.derating_start, self.derating_end]
+            .iter()
+            .chain(self.compliant.iter())
+            .all(|t| t.is_finite())
+            && self.warning <= self.derating_start
+            && self.derating_start < self.derating_end
+            && self.compliant.is_none_or(|c| self.derating_start <= c);
+        match valid {
+            true => Ok(()),
+            false => Err(format!("Invalid temperature thresholds {:?}", self)),
+        }
+    }
+
+    // thermal level of the temperature, the levels are left `hysteresis` below their threshold
+    fn level(&self, temperature: f32, previous: ThermalLevel, hysteresis: f32) -> ThermalLevel {
+        let above = |threshold: f32, level: ThermalLevel| {
+            temperature >= threshold || (previous >= level && temperature > threshold - hysteresis)
+        };
+        if self
+            .compliant
+            .is_some_and(|c| above(c, ThermalLevel::Compliant))
+        {
+            ThermalLevel::Compliant
+        } else if above(self.derating_start, ThermalLevel::Derating) {
+            ThermalLevel::Derating
+        } else if above(self.warning, ThermalLevel::Warning) {
+            ThermalLevel::Warning
+        } else {
+            ThermalLevel::Normal
+        }
+    }
+
+    // torque ratio of the temperature (1.0 below derating_start)
+    fn torque_ratio(&self, temperature: f32, min_torque_ratio: f32) -> f32 {
+        let derating =
+            (temperature - self.derating_start) / (self.derating_end - self.derating_start);
+        (1.0 - derating.clamp(0.0, 1.0) * (1.0 - min_torque_ratio)).clamp(min_torque_ratio, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThermalLimits {
+    #[serde(default)]
+    pub motor: Option<TemperatureThresholds>,
+    #[serde(default)]
+    pub board: Option<TemperatureThresholds>,
+    // torque ratio at derating_end (0.0 - 1.0)
+    #[serde(default)]
+    pub min_torque_ratio: f32,
+}
+
+impl ThermalLimits {
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.min_torque_ratio) {
+            return Err(format!(
+                "Invalid min torque ratio {}, must be between 0 and 1",
+                self.min_torque_ratio
+            ));
+        }
+        for thresholds in self.motor.iter().chain(self.board.iter()) {
+            thresholds.validate()?;
+        }
+        Ok(())
+    }
+}
+
+// read from the `thermal` entry of the `poulpe` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    // period of the temperature checks (ms)
+    pub period_ms: u64,
+    // °C below the thresholds to leave a level
+    pub hysteresis: f32,
+    // limits of the actuators that are not listed
+    pub default: Option<ThermalLimits>,
+    // limits by slave name
+    pub actuators: HashMap<String, ThermalLimits>,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            period_ms: 100,
+            hysteresis: 2.0,
+            default: None,
+            actuators: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThermalLevel {
+    #[default]
+    Normal,
+    Warning,
+    Derating,
+    Compliant,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThermalState {
+    // highest level of the axes
+    pub level: ThermalLevel,
+    // level of each axis (highest of the motor and the board)
+    pub axis_levels: Vec<ThermalLevel>,
+    // ratio applied to the requested torque limit of each axis
+    pub torque_ratio: Vec<f32>,
+    pub motor_temperatures: Vec<f32>,
+    pub board_temperatures: Vec<f32>,
+}
+
+// change of the thermal level of an actuator
+#[derive(Debug, Clone)]
+pub struct ThermalEvent {
+    pub id: u32,
+    pub name: String,
+    pub previous_level: ThermalLevel,
+    pub state: ThermalState,
+    // the torque of the actuator has been turned off
+    pub made_compliant: bool,
+    pub time: SystemTime,
+}
+
+// limits of the actuators on the bus by slave id
+pub(crate) fn resolve_thermal_limits(
+    config: &ThermalConfig,
+    poulpe_config: &HashMap<u16, PoulpeKind>,
+) -> Result<HashMap<u16, ThermalLimits>, Box<dyn Error>> {
+    if config.period_ms == 0 || !config.hysteresis.is_finite() || config.hysteresis < 0.0 {
+        return Err("Invalid thermal protection period or hysteresis".into());
+    }
+    for (name, l) in &config.actuators {
+        l.validate()?;
+        if !poulpe_config.values().any(|p| &p.name == name) {
+            log::warn!(
+                "Thermal limits of {:?} but no such actuator on the bus",
+                name
+            );
+        }
+    }
+    if let Some(l) = &config.default {
+        l.validate()?;
+    }
+    Ok(poulpe_config
+        .values()
+        .filter_map(|p| {
+            config
+                .actuators
+                .get(&p.name)
+                .or(config.default.as_ref())
+                .map(|l| (p.id, *l))
+        })
+        .collect())
+}
+
+impl PoulpeController {
+    // start the thermal protection of the actuators with thermal limits
+    // returns the receiver of the thermal events (the protection keeps running if it is dropped)
+    pub fn start_thermal_protection(
+        self: &Arc<Self>,
+    ) -> Result<mpsc::Receiver<ThermalEvent>, Box<dyn Error>> {
+        if self.thermal_protection_started.swap(true, Ordering::SeqCst) {
+            return Err("Thermal protection already started".into());
+        }
+        let (tx, rx) = mpsc::channel();
+        if self.thermal_limits.is_empty() {
+            // (the receiver is disconnected right away)
+            log::info!("No thermal limits configured, the thermal protection is not started");
+            return Ok(rx);
+        }
+        let controller = Arc::clone(self);
+        let period = Duration::from_millis(self.config.thermal.period_ms);
+        thread::spawn(move || loop {
+            let mut ids: Vec<_> = controller.thermal_limits.keys().copied().collect();
+            ids.sort();
+            for id in ids {
+                match controller.update_thermal_state(id as u32) {
+                    // the receiver may have been dropped
+                    Ok(Some(event)) => _ = tx.send(event),
+                    Ok(None) => {}
+                    Err(e) => log::debug!("Slave {} | thermal protection: {}", id, e),
+                }
+            }
+            thread::sleep(period);
+        });
+        log::info!(
+            "Thermal protection started on slaves {:?}",
+            self.thermal_limits.keys().collect::<Vec<_>>()
+        );
+        Ok(rx)
+    }
+
+    fn update_thermal_state(&self, id: u32) -> Result<Option<ThermalEvent>, Box<dyn Error>> {
+        let slave_id = id as u16;
+        let Some(limits) = self.thermal_limits.get(&slave_id) else {
+            return Ok(None);
+        };
+        if !self.is_slave_ready(slave_id) {
+            return Ok(None);
+        }
+        let hysteresis = self.config.thermal.hysteresis;
+        let (motor_temperatures, board_temperatures) = self
+            .get_temperatures(id)?
+            .ok_or("Error reading temperatures!")?;
+        let high_temperature_warning = self.get_error_flags(slave_id).map(|flags| {
+            flags
+                .motor_error_flags
+                .iter()
+                .map(|f| f.contains(&MotorErrorFlag::HighTemperatureWarning))
+                .collect::<Vec<_>>()
+        })?;
+
+        let previous_state = self.get_thermal_state(id);
+        let previous = previous_state.clone().unwrap_or_default();
+        let mut state = ThermalState {
+            motor_temperatures,
+            board_temperatures,
+            ..Default::default()
+        };
+        for axis in 0..state.motor_temperatures.len() {
+            let previous_level = previous.axis_levels.get(axis).copied().unwrap_or_default();
+            let mut level = ThermalLevel::Normal;
+            let mut ratio = 1.0f32;
+            for (thresholds, temperature) in [
+                (limits.motor, state.motor_temperatures.get(axis)),
+                (limits.board, state.board_temperatures.get(axis)),
+            ] {
+                if let (Some(thresholds), Some(&temperature)) = (thresholds, temperature) {
+                    level = level.max(thresholds.level(temperature, previous_level, hysteresis));
+                    ratio =
+                        ratio.min(thresholds.torque_ratio(temperature, limits.min_torque_ratio));
+                }
+            }
+            if high_temperature_warning.get(axis) == Some(&true) {
+                level = level.max(ThermalLevel::Warning);
+            }
+            state.axis_levels.push(level);
+            state.torque_ratio.push(ratio);
+        }
+        state.level = state.axis_levels.iter().copied().max().unwrap_or_default();
+
+        let requested_torque_limit = match state.torque_ratio != previous.torque_ratio {
+            true => Some(self.get_requested_torque_limit(id)?),
+            false => None,
+        };
+        self.thermal_state
+            .lock()
+            .unwrap()
+            .insert(slave_id, state.clone());
+        // on failure the previous state is restored, so that the next update applies the
+        // derating and the torque off again and emits the event
+        let made_compliant = match self.apply_thermal_state(id, &state, requested_torque_limit) {
+            Ok(made_compliant) => made_compliant,
+            Err(e) => {
+                let mut thermal_state = self.thermal_state.lock().unwrap();
+                match previous_state {
+                    Some(previous) => thermal_state.insert(slave_id, previous),
+                    None => thermal_state.remove(&slave_id),
+                };
+                return Err(e);
+            }
+        };
+
+        if state.level == previous.level && !made_compliant {
+            return Ok(None);
+        }
+        match state.level {
+            ThermalLevel::Normal => log::info!("Slave {} | temperatures back to normal", id),
+            level => log::warn!(
+                "Slave {} | thermal level {:?} (motors {:?}, boards {:?}, torque ratio {:?})",
+                id,
+                level,
+                state.motor_temperatures,
+                state.board_temperatures,
+                state.torque_ratio
+            ),
+        }
+        Ok(Some(ThermalEvent {
+            id,
+            name: self.get_slave_name(slave_id).unwrap_or_default(),
+            previous_level: previous.level,
+            state,
+            made_compliant,
+            time: SystemTime::now(),
+        }))
+    }
+
+    // derate the torque limit (if the ratio changed) and turn off the torque if too hot
+    // (also if it has been turned on again while too hot), returns true if the torque off is requested
+    // the torque off is requested without waiting, the other actuators are not held up
+    fn apply_thermal_state(
+        &self,
+        id: u32,
+        state: &ThermalState,
+        requested_torque_limit: Option<Vec<f32>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        if let Some(requested) = requested_torque_limit {
+            self.write_torque_limit(id, requested)?;
+        }
+        if state.level != ThermalLevel::Compliant || self.is_torque_on(id)? != Some(true) {
+            return Ok(false);
+        }
+        // (None if the torque off is already requested, while the transition is running)
+        let made_compliant = self.request_torque(id, false)?.is_some();
+        if made_compliant {
+            log::error!(
+                "Slave {} | too hot (motors {:?}, boards {:?}), turning off the torque!",
+                id,
+                state.motor_temperatures,
+                state.board_temperatures
+            );
+        }
+        Ok(made_compliant)
+    }
+
+    // thermal state of the actuator (None if the thermal protection does not follow it)
+    pub fn get_thermal_state(&self, id: u32) -> Option<ThermalState> {
+        self.thermal_state
+            .lock()
+            .unwrap()
+            .get(&(id as u16))
+            .cloned()
+    }
+
+    // torque limit requested with set_torque_limit (the current one if never set)
+    fn get_requested_torque_limit(&self, id: u32) -> Result<Vec<f32>, Box<dyn Error>> {
+        if let Some(requested) = self.torque_limit_request.lock().unwrap().get(&(id as u16)) {
+            return Ok(requested.clone());
+        }
+        self.get_current_torque_limit(id)?
+            .ok_or_else(|| "Error reading the torque limit!".into())
+    }
+
+    // write the requested torque limit scaled by the derating ratio of each axis
+    pub(crate) fn write_torque_limit(
+        &self,
+        id: u32,
+        torque_limit: Vec<f32>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.torque_limit_request
+            .lock()
+            .unwrap()
+            .insert(id as u16, torque_limit.clone());
+        let derated = match self.get_thermal_state(id) {
+            Some(state) => torque_limit
+                .iter()
+                .enumerate()
+                .map(|(axis, l)| l * state.torque_ratio.get(axis).copied().unwrap_or(1.0))
+                .collect(),
+            None => torque_limit,
+        };
+        self.set_register_values(id, PdoRegister::TorqueLimit, derated)
+    }
+}
@@ -0,0 +1,561 @@
+// Waypoint trajectories interpolated next to the bus
+//
+// A trajectory is a timed list of waypoints (positions and optional velocities of each axis) for
+// one or several actuators. The waypoints are interpolated with splines (cubic or quintic) and the
+// interpolated positions are written as target position at each cycle of the EtherCAT loop by an
+// executor thread, so the clients do not have to stream the target positions.
+//
+// - cubic: piecewise cubic Hermite spline, continuous position and velocity
+// - quintic: piecewise quintic Hermite spline, continuous position, velocity and acceleration
+//
+// The velocities that are not given are estimated from the neighbouring waypoints (the mean of the
+// slopes before and after the waypoint, 0 at the extremities and at the waypoints where the motion
+// changes direction, so that the spline does not overshoot them).
+// The accelerations of the quintic spline are estimated in the same way from the velocities.
+//
+// The time of the waypoints is relative to the start of the trajectory. If the first waypoint is
+// not at 0, the trajectory starts from the current position of the actuators.
+//
+// The execution can be paused (the target positions are held), resumed, aborted and slowed down or
+// sped up with a time scale (the time of the trajectory advances by time_scale * elapsed time).
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use crate::state_machine::StatusBit;
+use crate::PoulpeController;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Cubic,
+    Quintic,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waypoint {
+    // time since the start of the trajectory (s)
+    pub time: f32,
+    // position of each axis (rad)
+    pub positions: Vec<f32>,
+    // velocity of each axis (rad/s), estimated if not given
+    pub velocities: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActuatorTrajectory {
+    pub id: u32,
+    pub waypoints: Vec<Waypoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Trajectory {
+    pub actuators: Vec<ActuatorTrajectory>,
+    pub interpolation: Interpolation,
+}
+
+// piecewise polynomial of one axis
+// segment i: p(t) = sum(c[k] * (t - times[i])^k) for times[i] <= t < times[i + 1]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spline {
+    times: Vec<f32>,
+    coefficients: Vec<[f32; 6]>,
+    end: f32,
+}
+
+// velocity (or acceleration) at the waypoints from the slopes between them
+fn estimate_derivatives(times: &[f32], values: &[f32]) -> Vec<f32> {
+    let n = times.len();
+    let slope = |i: usize| (values[i + 1] - values[i]) / (times[i + 1] - times[i]);
+    (0..n)
+        .map(|i| {
+            if i == 0 || i == n - 1 {
+                return 0.0;
+            }
+            let (before, after) = (slope(i - 1), slope(i));
+            match before * after > 0.0 {
+                true => (before + after) / 2.0,
+                false => 0.0,
+            }
+        })
+        .collect()
+}
+
+impl Spline {
+    // the times must be strictly increasing
+    pub fn new(
+        times: &[f32],
+        positions: &[f32],
+        velocities: Option<&[f32]>,
+        interpolation: Interpolation,
+    ) -> Self {
+        let velocities = match velocities {
+            Some(v) => v.to_vec(),
+            None => estimate_derivatives(times, positions),
+        };
+        let accelerations = match interpolation {
+            Interpolation::Cubic => vec![0.0; times.len()],
+            Interpolation::Quintic => estimate_derivatives(times, &velocities),
+        };
+
+        let coefficients = times
+            .windows(2)
+            .enumerate()
+            .map(|(i, t)| {
+                let h = t[1] - t[0];
+                let (p0, p1) = (positions[i], positions[i + 1]);
+                let (v0, v1) = (velocities[i], velocities[i + 1]);
+                let (a0, a1) = (accelerations[i], accelerations[i + 1]);
+                let dp = p1 - p0;
+                match interpolation {
+                    Interpolation::Cubic => [
+                        p0,
+                        v0,
+                        (3.0 * dp / h - 2.0 * v0 - v1) / h,
+                        (-2.0 * dp / h + v0 + v1) / (h * h),
+                        0.0,
+                        0.0,
+                    ],
+                    Interpolation::Quintic => [
+                        p0,
+                        v0,
+                        a0 / 2.0,
+                        (20.0 * dp - (8.0 * v1 + 12.0 * v0) * h - (3.0 * a0 - a1) * h * h)
+                            / (2.0 * h.powi(3)),
+                        (-30.0 * dp + (14.0 * v1 + 16.0 * v0) * h + (3.0 * a0 - 2.0 * a1) * h * h)
+                            / (2.0 * h.powi(4)),
+                        (12.0 * dp - 6.0 * (v1 + v0) * h - (a0 - a1) * h * h) / (2.0 * h.powi(5)),
+                    ],
+                }
+            })
+            .collect();
+
+        Spline {
+            times: times.to_vec(),
+            coefficients,
+            end: *positions.last().unwrap_or(&0.0),
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        *self.times.last().unwrap_or(&0.0)
+    }
+
+    // position at the time t (s), held before the first and after the last waypoint
+    pub fn position(&self, t: f32) -> f32 {
+        if t >= self.duration() || self.coefficients.is_empty() {
+            return self.end;
+        }
+        let i = self.times.partition_point(|x| *x <= t).saturating_sub(1);
+        let dt = (t - self.times[i]).max(0.0);
+        self.coefficients[i]
+            .iter()
+            .rev()
+            .fold(0.0, |acc, c| acc * dt + c)
+    }
+}
+
+// splines of the axes of an actuator
+#[derive(Debug, Clone)]
+struct ActuatorSplines {
+    id: u32,
+    axes: Vec<Spline>,
+}
+
+impl ActuatorSplines {
+    fn position(&self, t: f32) -> Vec<f32> {
+        self.axes.iter().map(|s| s.position(t)).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrajectoryStatus {
+    Running,
+    Paused,
+    // the end of the trajectory has been reached
+    Completed,
+    Aborted,
+    // the execution stopped on an error (torque off, fault, ...)
+    Failed(String),
+}
+
+impl TrajectoryStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TrajectoryStatus::Running | TrajectoryStatus::Paused)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryState {
+    pub status: TrajectoryStatus,
+    // time of the trajectory reached (s)
+    pub time: f32,
+    pub duration: f32,
+    pub time_scale: f32,
+}
+
+#[derive(Debug)]
+struct SharedState {
+    state: TrajectoryState,
+    pause_requested: bool,
+    abort_requested: bool,
+}
+
+// handle of a running trajectory (see PoulpeController::execute_trajectory)
+// the trajectory keeps running if the handle is dropped
+#[derive(Debug)]
+pub struct TrajectoryHandle {
+    shared: Arc<Mutex<SharedState>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TrajectoryHandle {
+    pub fn state(&self) -> TrajectoryState {
+        self.shared.lock().unwrap().state.clone()
+    }
+
+    // hold the current target positions
+    pub fn pause(&self) {
+        self.shared.lock().unwrap().pause_requested = true;
+    }
+
+    pub fn resume(&self) {
+        self.shared.lock().unwrap().pause_requested = false;
+    }
+
+    // stop the trajectory, the last target positions are held
+    pub fn abort(&self) {
+        self.shared.lock().unwrap().abort_requested = true;
+    }
+
+    // speed of the trajectory time (1.0: real time, 0.5: twice slower)
+    pub fn set_time_scale(&self, time_scale: f32) -> Result<(), Box<dyn Error>> {
+        if !time_scale.is_finite() || time_scale <= 0.0 {
+            return Err("The time scale must be finite and positive".into());
+        }
+        self.shared.lock().unwrap().state.time_scale = time_scale;
+        Ok(())
+    }
+
+    // wait for the end of the trajectory
+    pub fn wait(mut self) -> TrajectoryState {
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Trajectory executor thread panicked!");
+            }
+        }
+        self.state()
+    }
+}
+
+impl PoulpeController {
+    fn build_splines(
+        &self,
+        trajectory: &ActuatorTrajectory,
+        interpolation: Interpolation,
+    ) -> Result<ActuatorSplines, Box<dyn Error>> {
+        let id = trajectory.id;
+        let axes = self.axes(id)?;
+        let waypoints = &trajectory.waypoints;
+        if waypoints.is_empty() {
+            log::error!("Slave {} | empty trajectory", id);
+            return Err("Empty trajectory".into());
+        }
+        for (i, w) in waypoints.iter().enumerate() {
+            let previous_time = match i {
+                0 => 0.0,
+                _ => waypoints[i - 1].time,
+            };
+            if !w.time.is_finite() || w.time < previous_time || (i > 0 && w.time == previous_time) {
+                log::error!("Slave {} | waypoint {} at invalid time {}", id, i, w.time);
+                return Err("The waypoint times must be positive and increasing".into());
+            }
+            let velocities = w.velocities.as_deref().unwrap_or(&[]);
+            if w.positions.len() != axes || (w.velocities.is_some() && velocities.len() != axes) {
+                log::error!("Slave {} | waypoint {} does not have {} axes", id, i, axes);
+                return Err("Unexpected number of axes in the waypoint".into());
+            }
+            if w.positions.iter().chain(velocities).any(|v| !v.is_finite()) {
+                log::error!("Slave {} | invalid values in waypoint {}", id, i);
+                return Err("Invalid waypoint".into());
+            }
+        }
+
+        // start from the current position
+        let mut waypoints = waypoints.clone();
+        if waypoints[0].time > 0.0 {
+            let position = self
+                .get_current_position(id)?
+                .ok_or("Error reading the current position!")?;
+            waypoints.insert(
+                0,
+                Waypoint {
+                    time: 0.0,
+                    positions: position,
+                    velocities: Some(vec![0.0; axes]),
+                },
+            );
+        }
+
+        let times: Vec<_> = waypoints.iter().map(|w| w.time).collect();
+        let estimate_velocities = waypoints.iter().any(|w| w.velocities.is_none());
+        let axes = (0..axes)
+            .map(|axis| {
+                let positions: Vec<_> = waypoints.iter().map(|w| w.positions[axis]).collect();
+                // the velocities are only used if they are given for all the waypoints
+                let velocities: Option<Vec<_>> = match estimate_velocities {
+                    true => None,
+                    false => waypoints
+                        .iter()
+                        .map(|w| w.velocities.as_ref().map(|v| v[axis]))
+                        .collect(),
+                };
+                Spline::new(&times, &positions, velocities.as_deref(), interpolation)
+            })
+            .collect();
+        Ok(ActuatorSplines { id, axes })
+    }
+
+    // start the execution of the trajectory
+    // - the torque of the actuators must be on
+    // - the target positions are written by an executor thread at each cycle
+    pub fn execute_trajectory(
+        self: &Arc<Self>,
+        trajectory: &Trajectory,
+    ) -> Result<TrajectoryHandle, Box<dyn Error>> {
+        let mut ids = HashSet::new();
+        for t in &trajectory.actuators {
+            if !ids.insert(t.id) {
+                log::error!("Slave {} | several trajectories for the actuator", t.id);
+                return Err("Several trajectories for the same actuator".into());
+            }
+            if self.is_torque_on(t.id)? != Some(true) {
+                log::error!("Slave {} | Cannot move with the torque off!", t.id);
+                return Err("Cannot move with the torque off!".into());
+            }
+        }
+        let splines = trajectory
+            .actuators
+            .iter()
+            .map(|t| self.build_splines(t, trajectory.interpolation))
+            .collect::<Result<Vec<_>, _>>()?;
+        let duration = splines
+            .iter()
+            .flat_map(|s| s.axes.iter().map(|a| a.duration()))
+            .fold(0.0, f32::max);
+
+        let shared = Arc::new(Mutex::new(SharedState {
+            state: TrajectoryState {
+                status: TrajectoryStatus::Running,
+                time: 0.0,
+                duration,
+                time_scale: 1.0,
+            },
+            pause_requested: false,
+            abort_requested: false,
+        }));
+        log::info!(
+            "Executing a {:?} trajectory of {:.3}s on slaves {:?}",
+            trajectory.interpolation,
+            duration,
+            ids
+        );
+
+        let controller = Arc::clone(self);
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || {
+            let status = controller.run_trajectory(&splines, &thread_shared);
+            match &status {
+                TrajectoryStatus::Failed(e) => log::error!("Trajectory failed: {}", e),
+                status => log::info!("Trajectory {:?}", status),
+            }
+            thread_shared.lock().unwrap().state.status = status;
+        });
+
+        Ok(TrajectoryHandle {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    fn run_trajectory(
+        &self,
+        splines: &[ActuatorSplines],
+        shared: &Mutex<SharedState>,
+    ) -> TrajectoryStatus {
+        let mut last_cycle = Instant::now();
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(last_cycle).as_secs_f32();
+            last_cycle = now;
+
+            let (t, duration) = {
+                let mut shared = shared.lock().unwrap();
+                if shared.abort_requested {
+                    return TrajectoryStatus::Aborted;
+                }
+                let state = &mut shared.state;
+                if state.status == TrajectoryStatus::Running {
+                    state.time = (state.time + elapsed * state.time_scale).min(state.duration);
+                }
+                let t = state.time;
+                let duration = state.duration;
+                shared.state.status = match shared.pause_requested {
+                    true => TrajectoryStatus::Paused,
+                    false => TrajectoryStatus::Running,
+                };
+                (t, duration)
+            };
+
+            for s in splines {
+                let status_bits = match self.get_status_bits(s.id as u16) {
+                    Ok(status_bits) => status_bits,
+                    Err(e) => return TrajectoryStatus::Failed(e.to_string()),
+                };
+                if status_bits.contains(&StatusBit::Fault) {
+                    return TrajectoryStatus::Failed(format!("slave {} in fault state", s.id));
+                }
+                if !status_bits.contains(&StatusBit::OperationEnabled) {
+                    return TrajectoryStatus::Failed(format!("slave {} torque off", s.id));
+                }
+                if let Err(e) = self.set_target_position(s.id, s.position(t)) {
+                    return TrajectoryStatus::Failed(e.to_string());
+                }
+            }
+
+            if t >= duration {
+                return TrajectoryStatus::Completed;
+            }
+            self.inner.wait_for_next_cycle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMES: [f32; 5] = [0.0, 0.5, 1.2, 2.0, 3.0];
+    const POSITIONS: [f32; 5] = [0.0, 0.4, 1.0, 0.2, -0.5];
+
+    fn assert_close(a: f32, b: f32, what: &str) {
+        assert!((a - b).abs() < 1e-3, "{}: {} != {}", what, a, b);
+    }
+
+    // position, velocity and acceleration of the segment i at dt from its start
+    fn derivatives(spline: &Spline, i: usize, dt: f32) -> [f32; 3] {
+        let c = &spline.coefficients[i];
+        let p = c.iter().rev().fold(0.0, |acc, c| acc * dt + c);
+        let v = (1..6).rev().fold(0.0, |acc, k| acc * dt + k as f32 * c[k]);
+        let a = (2..6)
+            .rev()
+            .fold(0.0, |acc, k| acc * dt + (k * (k - 1)) as f32 * c[k]);
+        [p, v, a]
+    }
+
+    // the derivatives at the end of each segment match the ones at the start of the next one
+    fn assert_continuous(spline: &Spline, orders: usize) {
+        for i in 1..spline.coefficients.len() {
+            let h = spline.times[i] - spline.times[i - 1];
+            let end = derivatives(spline, i - 1, h);
+            let start = derivatives(spline, i, 0.0);
+            for k in 0..orders {
+                assert_close(
+                    end[k],
+                    start[k],
+                    &format!("derivative {} at waypoint {}", k, i),
+                );
+            }
+        }
+    }
+
+    fn assert_through_waypoints(spline: &Spline, times: &[f32], positions: &[f32]) {
+        for (t, p) in times.iter().zip(positions) {
+            assert_close(spline.position(*t), *p, &format!("position at {}", t));
+        }
+    }
+
+    #[test]
+    fn estimated_derivatives() {
+        let v = estimate_derivatives(&TIMES, &POSITIONS);
+        // 0 at the extremities
+        assert_eq!(v[0], 0.0);
+        assert_eq!(v[4], 0.0);
+        // mean of the slopes before and after
+        assert_close(
+            v[1],
+            (0.4 / 0.5 + 0.6 / 0.7) / 2.0,
+            "velocity at waypoint 1",
+        );
+        // 0 where the motion changes direction
+        assert_eq!(v[2], 0.0);
+        assert_close(
+            v[3],
+            (-0.8 / 0.8 + -0.7 / 1.0) / 2.0,
+            "velocity at waypoint 3",
+        );
+    }
+
+    #[test]
+    fn cubic_spline() {
+        let spline = Spline::new(&TIMES, &POSITIONS, None, Interpolation::Cubic);
+        assert_through_waypoints(&spline, &TIMES, &POSITIONS);
+        // continuous position and velocity
+        assert_continuous(&spline, 2);
+        // the velocities at the waypoints are the estimated ones
+        let velocities = estimate_derivatives(&TIMES, &POSITIONS);
+        for i in 0..spline.coefficients.len() {
+            assert_close(derivatives(&spline, i, 0.0)[1], velocities[i], "velocity");
+        }
+    }
+
+    #[test]
+    fn quintic_spline() {
+        let spline = Spline::new(&TIMES, &POSITIONS, None, Interpolation::Quintic);
+        assert_through_waypoints(&spline, &TIMES, &POSITIONS);
+        // continuous position, velocity and acceleration
+        assert_continuous(&spline, 3);
+        // at rest at the extremities
+        let last = spline.coefficients.len() - 1;
+        let h = TIMES[4] - TIMES[3];
+        assert_eq!(derivatives(&spline, 0, 0.0)[1..], [0.0, 0.0]);
+        assert_close(derivatives(&spline, last, h)[1], 0.0, "final velocity");
+        assert_close(derivatives(&spline, last, h)[2], 0.0, "final acceleration");
+    }
+
+    #[test]
+    fn given_velocities() {
+        let velocities = [0.2, 1.0, -0.5, 0.0, 0.3];
+        for interpolation in [Interpolation::Cubic, Interpolation::Quintic] {
+            let spline = Spline::new(&TIMES, &POSITIONS, Some(&velocities), interpolation);
+            assert_through_waypoints(&spline, &TIMES, &POSITIONS);
+            assert_continuous(&spline, 2);
+            for i in 1..TIMES.len() {
+                let h = TIMES[i] - TIMES[i - 1];
+                assert_close(
+                    derivatives(&spline, i - 1, h)[1],
+                    velocities[i],
+                    &format!("{:?} velocity at waypoint {}", interpolation, i),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn held_outside_the_waypoints() {
+        let spline = Spline::new(&TIMES, &POSITIONS, None, Interpolation::Cubic);
+        assert_eq!(spline.duration(), 3.0);
+        assert_eq!(spline.position(-1.0), POSITIONS[0]);
+        assert_eq!(spline.position(10.0), POSITIONS[4]);
+
+        // single waypoint
+        let spline = Spline::new(&[0.0], &[0.7], None, Interpolation::Quintic);
+        assert_eq!(spline.position(0.0), 0.7);
+        assert_eq!(spline.position(1.0), 0.7);
+    }
+}
@@ -1,8 +1,9 @@
 use std::{any::Any, collections::HashMap, f32::consts::E, sync::Arc, time::Duration};
 
 use super::pb::{
-    poulpe_multiplexer_client::PoulpeMultiplexerClient, PoulpeCommand, PoulpeCommands, PoulpeState,
-    StateStreamRequest,
+    poulpe_multiplexer_client::PoulpeMultiplexerClient, ControlLoop, Pid, PidGains, PidRequest,
+    PoulpeCommand, PoulpeCommands, PoulpeState, SafetyConfig, StateStreamRequest, Trajectory,
+    TrajectoryCommand, TrajectoryCommandType, TrajectoryState,
 };
 use prost_types::Timestamp;
 use tokio::{
@@ -212,6 +213,87 @@ impl PoulpeRemoteClient {
         })
     }
 
+    // safety policies in effect on the server
+    pub fn get_safety_config_sync(&self) -> Result<SafetyConfig, Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let mut client = PoulpeMultiplexerClient::connect(self.addr.to_string()).await?;
+            let response = client.get_safety_config(Request::new(())).await?;
+            Ok(response.into_inner())
+        })
+    }
+
+    // PID gains of the control loop of each axis (the torque of the slave must be off)
+    pub fn get_pid_sync(
+        &self,
+        slave_id: u16,
+        control_loop: ControlLoop,
+    ) -> Result<Vec<PidGains>, Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let mut client = PoulpeMultiplexerClient::connect(self.addr.to_string()).await?;
+            let response = client
+                .get_pid(Request::new(PidRequest {
+                    id: slave_id as i32,
+                    control_loop: control_loop as i32,
+                }))
+                .await?;
+            Ok(response.into_inner().gains)
+        })
+    }
+
+    // set the PID gains of the listed axes, and save them to the flash of the board if persist
+    pub fn set_pid_sync(
+        &self,
+        slave_id: u16,
+        control_loop: ControlLoop,
+        gains: Vec<PidGains>,
+        persist: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let mut client = PoulpeMultiplexerClient::connect(self.addr.to_string()).await?;
+            client
+                .set_pid(Request::new(Pid {
+                    id: slave_id as i32,
+                    control_loop: control_loop as i32,
+                    gains,
+                    persist,
+                }))
+                .await?;
+            Ok(())
+        })
+    }
+
+    // execute a waypoint trajectory on the server, returns the id of the trajectory
+    pub fn execute_trajectory_sync(
+        &self,
+        trajectory: Trajectory,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let mut client = PoulpeMultiplexerClient::connect(self.addr.to_string()).await?;
+            let response = client.execute_trajectory(Request::new(trajectory)).await?;
+            Ok(response.into_inner().id)
+        })
+    }
+
+    // pause, resume, abort or scale the time of the trajectory, returns its state
+    pub fn control_trajectory_sync(
+        &self,
+        trajectory_id: u32,
+        command: TrajectoryCommandType,
+        time_scale: f32,
+    ) -> Result<TrajectoryState, Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let mut client = PoulpeMultiplexerClient::connect(self.addr.to_string()).await?;
+            let response = client
+                .control_trajectory(Request::new(TrajectoryCommand {
+                    id: trajectory_id,
+                    command: command as i32,
+                    time_scale,
+                }))
+                .await?;
+            Ok(response.into_inner())
+        })
+    }
+
     pub fn get_poulpe_ids(&self) -> Vec<u16> {
         self.rt
             .block_on(self.state.read())
@@ -0,0 +1,46 @@
+use std::{error::Error, fs};
+
+use serde::{Deserialize, Serialize};
+
+// configuration of the grpc server
+// read from the `grpc` section of the configuration file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GrpcConfig {
+    #[serde(flatten)]
+    pub safety: GrpcSafetyConfig,
+    // address of the metrics HTTP endpoint (ex. "127.0.0.1:9100")
+    // the endpoint is disabled if not set
+    #[serde(default)]
+    pub metrics_address: Option<String>,
+}
+
+// safety policies of the grpc server
+// the defaults are set by the cargo features of the same name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcSafetyConfig {
+    // send the emergency stop to all the slaves if one of them is in fault state
+    pub quick_stop_on_slave_fault: bool,
+}
+
+impl Default for GrpcSafetyConfig {
+    fn default() -> Self {
+        GrpcSafetyConfig {
+            quick_stop_on_slave_fault: cfg!(feature = "qucik_stop_on_slave_fault"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GrpcConfigSection {
+    #[serde(default)]
+    grpc: GrpcConfig,
+}
+
+impl GrpcConfig {
+    pub fn from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let yaml = fs::read_to_string(path)?;
+        let section: GrpcConfigSection = serde_yaml::from_str(&yaml)?;
+        Ok(section.grpc)
+    }
+}
@@ -3,4 +3,6 @@ pub mod pb {
 }
 
 pub mod client;
+pub mod config;
+pub mod metrics;
 pub use client::PoulpeRemoteClient;
@@ -0,0 +1,554 @@
+// Prometheus/OpenMetrics exporter
+//
+// Serves the bus metrics of the EtherCAT master and the actuator metrics of the poulpe boards
+// on a local HTTP endpoint (GET /metrics) in the Prometheus text format.
+
+use std::{collections::HashMap, fmt::Write, sync::Arc, sync::Mutex};
+
+use poulpe_ethercat_controller::PoulpeController;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+// commands counters of one slave
+#[derive(Debug, Default, Clone)]
+pub struct CommandCounters {
+    pub received: u64,
+    // dropped because the slave is not ready or in fault state
+    pub dropped: u64,
+    // dropped because older than the command drop time
+    pub too_old: u64,
+}
+
+// commands counters of all the slaves, updated by the grpc server
+#[derive(Debug, Default)]
+pub struct CommandMetrics {
+    counters: Mutex<HashMap<u32, CommandCounters>>,
+}
+
+impl CommandMetrics {
+    fn update(&self, slave_id: u32, f: impl FnOnce(&mut CommandCounters)) {
+        if let Ok(mut counters) = self.counters.lock() {
+            f(counters.entry(slave_id).or_default());
+        }
+    }
+
+    pub fn received(&self, slave_id: u32) {
+        self.update(slave_id, |c| c.received += 1);
+    }
+
+    pub fn dropped(&self, slave_id: u32) {
+        self.update(slave_id, |c| c.dropped += 1);
+    }
+
+    pub fn too_old(&self, slave_id: u32) {
+        self.update(slave_id, |c| c.too_old += 1);
+    }
+
+    pub fn get(&self) -> HashMap<u32, CommandCounters> {
+        self.counters.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+}
+
+// escape the label value (backslash, double quote and line feed)
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// write the HELP and TYPE lines of a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// render all the metrics in the Prometheus text format
+pub fn render(controller: &PoulpeController, commands: &CommandMetrics) -> String {
+    let mut out = String::new();
+    let bus = controller.inner.get_bus_metrics();
+
+    // bus metrics
+    for (name, kind, help, value) in [
+        (
+            "ethercat_loop_frequency_hz",
+            "gauge",
+            "EtherCAT loop frequency",
+            bus.loop_frequency_hz as f64,
+        ),
+        (
+            "ethercat_loop_jitter_mean_us",
+            "gauge",
+            "Mean deviation of the loop period from the cycle time",
+            bus.jitter_mean_us as f64,
+        ),
+        (
+            "ethercat_loop_jitter_max_us",
+            "gauge",
+            "Max deviation of the loop period from the cycle time",
+            bus.jitter_max_us as f64,
+        ),
+        (
+            "ethercat_loop_cycles_total",
+            "counter",
+            "Number of EtherCAT cycles",
+            bus.cycles as f64,
+        ),
+        (
+            "ethercat_loop_overruns_total",
+            "counter",
+            "Number of cycles longer than the cycle time",
+            bus.overruns as f64,
+        ),
+        (
+            "ethercat_wkc_errors_total",
+            "counter",
+            "Number of cycles with an incomplete working counter",
+            bus.wkc_errors as f64,
+        ),
+        (
+            "ethercat_working_counter",
+            "gauge",
+            "Last working counter of the domain",
+            bus.working_counter as f64,
+        ),
+        (
+            "ethercat_link_up",
+            "gauge",
+            "Link state of the master",
+            bus.link_up as u8 as f64,
+        ),
+        (
+            "ethercat_operational",
+            "gauge",
+            "Master and all slaves operational",
+            bus.operational as u8 as f64,
+        ),
+    ] {
+        header(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    // per slave bus metrics
+    header(
+        &mut out,
+        "ethercat_slave_al_state",
+        "gauge",
+        "AL state of the slave (1 init, 2 preop, 4 safeop, 8 op, 0 unknown)",
+    );
+    for s in &bus.slaves {
+        let _ = writeln!(
+            out,
+            "ethercat_slave_al_state{{slave=\"{}\",name=\"{}\"}} {}",
+            s.position,
+            escape(&s.name),
+            s.al_state
+        );
+    }
+    header(
+        &mut out,
+        "ethercat_slave_al_status_code",
+        "gauge",
+        "AL status code of the slaves that refused the last state change",
+    );
+    for s in &bus.slaves {
+        if let Some(code) = s.al_status_code {
+            let _ = writeln!(
+                out,
+                "ethercat_slave_al_status_code{{slave=\"{}\",name=\"{}\",description=\"{}\"}} {}",
+                s.position,
+                escape(&s.name),
+                code.description(),
+                code.code()
+            );
+        }
+    }
+    header(
+        &mut out,
+        "ethercat_slave_watchdog_age_ms",
+        "gauge",
+        "Time since the last watchdog update of the slave",
+    );
+    for s in &bus.slaves {
+        if let Some(age) = s.watchdog_age_ms {
+            let _ = writeln!(
+                out,
+                "ethercat_slave_watchdog_age_ms{{slave=\"{}\",name=\"{}\"}} {}",
+                s.position,
+                escape(&s.name),
+                age
+            );
+        }
+    }
+    header(
+        &mut out,
+        "ethercat_slave_mailbox_age_ms",
+        "gauge",
+        "Time since the last mailbox PDO update of the slave",
+    );
+    for s in &bus.slaves {
+        if let Some(age) = s.mailbox_age_ms {
+            let _ = writeln!(
+                out,
+                "ethercat_slave_mailbox_age_ms{{slave=\"{}\",name=\"{}\"}} {}",
+                s.position,
+                escape(&s.name),
+                age
+            );
+        }
+    }
+
+    // ESC error counters of each port of the slaves (errors counted since the start of the master)
+    let esc_diagnostics = bus
+        .slaves
+        .iter()
+        .filter_map(|s| {
+            controller
+                .inner
+                .get_esc_diagnostics(s.position)
+                .map(|d| (s, d))
+        })
+        .collect::<Vec<_>>();
+    for (i, (name, kind, help)) in [
+        (
+            "ethercat_slave_port_link",
+            "gauge",
+            "Physical link detected on the port of the slave",
+        ),
+        (
+            "ethercat_slave_port_invalid_frames_total",
+            "counter",
+            "Frames received with a CRC error on the port of the slave",
+        ),
+        (
+            "ethercat_slave_port_rx_errors_total",
+            "counter",
+            "Physical layer errors on the port of the slave",
+        ),
+        (
+            "ethercat_slave_port_forwarded_errors_total",
+            "counter",
+            "Frames received on the port of the slave with an error detected by a previous slave",
+        ),
+        (
+            "ethercat_slave_port_lost_links_total",
+            "counter",
+            "Lost links on the port of the slave",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        header(&mut out, name, kind, help);
+        for (s, d) in &esc_diagnostics {
+            for (port, p) in d.ports.iter().enumerate() {
+                let values = [
+                    p.link as u64,
+                    p.total.invalid_frames,
+                    p.total.rx_errors,
+                    p.total.forwarded_errors,
+                    p.total.lost_links,
+                ];
+                let _ = writeln!(
+                    out,
+                    "{}{{slave=\"{}\",name=\"{}\",port=\"{}\"}} {}",
+                    name,
+                    s.position,
+                    escape(&s.name),
+                    port,
+                    values[i]
+                );
+            }
+        }
+    }
+
+    // network devices of the master (main and backup)
+    for (i, (name, kind, help)) in [
+        (
+            "ethercat_link_device_up",
+            "gauge",
+            "Link state of the network device",
+        ),
+        (
+            "ethercat_link_slaves_responding",
+            "gauge",
+            "Number of slaves responding on the network device",
+        ),
+        (
+            "ethercat_link_tx_frames_total",
+            "counter",
+            "Frames sent by the network device",
+        ),
+        (
+            "ethercat_link_rx_frames_total",
+            "counter",
+            "Frames received by the network device",
+        ),
+        (
+            "ethercat_link_tx_errors_total",
+            "counter",
+            "Transmission errors of the network device",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        header(&mut out, name, kind, help);
+        for d in &bus.link.devices {
+            let values = [
+                d.link_up as u64,
+                d.slaves_responding as u64,
+                d.tx_frames,
+                d.rx_frames,
+                d.tx_errors,
+            ];
+            let _ = writeln!(
+                out,
+                "{}{{device=\"{}\",address=\"{}\"}} {}",
+                name, d.index, d.address, values[i]
+            );
+        }
+    }
+    for (name, kind, help, value) in [
+        (
+            "ethercat_lost_frames_total",
+            "counter",
+            "Frames sent by the master and never received back",
+            bus.link.lost_frames,
+        ),
+        (
+            "ethercat_redundancy_degraded",
+            "gauge",
+            "Ring redundancy degraded (a link is down or the ring is open)",
+            bus.link.redundancy_degraded as u64,
+        ),
+    ] {
+        header(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    // actuator metrics
+    let mut ids = controller.get_slave_ids();
+    ids.sort();
+    let labels = |id: u32| {
+        format!(
+            "slave=\"{}\",name=\"{}\"",
+            id,
+            escape(&controller.get_slave_name(id as u16).unwrap_or_default())
+        )
+    };
+
+    header(
+        &mut out,
+        "poulpe_motor_temperature_celsius",
+        "gauge",
+        "Motor temperatures",
+    );
+    for &id in &ids {
+        if let Ok(Some(temperatures)) = controller.get_motor_temperatures(id) {
+            for (axis, t) in temperatures.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "poulpe_motor_temperature_celsius{{{},axis=\"{}\"}} {}",
+                    labels(id),
+                    axis,
+                    t
+                );
+            }
+        }
+    }
+    header(
+        &mut out,
+        "poulpe_board_temperature_celsius",
+        "gauge",
+        "Board temperatures",
+    );
+    for &id in &ids {
+        if let Ok(Some(temperatures)) = controller.get_board_temperatures(id) {
+            for (axis, t) in temperatures.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "poulpe_board_temperature_celsius{{{},axis=\"{}\"}} {}",
+                    labels(id),
+                    axis,
+                    t
+                );
+            }
+        }
+    }
+    header(
+        &mut out,
+        "poulpe_cia402_state",
+        "gauge",
+        "CiA402 state of the board",
+    );
+    for &id in &ids {
+        if let Ok(state) = controller.get_status(id) {
+            let _ = writeln!(
+                out,
+                "poulpe_cia402_state{{{},state=\"{:?}\"}} {}",
+                labels(id),
+                state,
+                state as u32
+            );
+        }
+    }
+    header(
+        &mut out,
+        "poulpe_error_flags",
+        "gauge",
+        "Raw error flags of the board (source homing or motor index)",
+    );
+    for &id in &ids {
+        if let Ok(error_codes) = controller.get_error_codes(id) {
+            for (i, e) in error_codes.iter().enumerate() {
+                let source = match i {
+                    0 => "homing".to_string(),
+                    _ => format!("motor{}", i - 1),
+                };
+                let _ = writeln!(
+                    out,
+                    "poulpe_error_flags{{{},source=\"{}\"}} {}",
+                    labels(id),
+                    source,
+                    e
+                );
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "poulpe_thermal_level",
+        "gauge",
+        "Thermal level of the actuator (0 normal, 1 warning, 2 derating, 3 compliant)",
+    );
+    for &id in &ids {
+        if let Some(state) = controller.get_thermal_state(id) {
+            let _ = writeln!(
+                out,
+                "poulpe_thermal_level{{{}}} {}",
+                labels(id),
+                state.level as u32
+            );
+        }
+    }
+    header(
+        &mut out,
+        "poulpe_torque_derating_ratio",
+        "gauge",
+        "Ratio applied to the torque limit by the thermal protection",
+    );
+    for &id in &ids {
+        if let Some(state) = controller.get_thermal_state(id) {
+            for (axis, ratio) in state.torque_ratio.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "poulpe_torque_derating_ratio{{{},axis=\"{}\"}} {}",
+                    labels(id),
+                    axis,
+                    ratio
+                );
+            }
+        }
+    }
+    header(
+        &mut out,
+        "poulpe_limit_violations_total",
+        "counter",
+        "Setpoints out of the joint limits (see the limits configuration)",
+    );
+    for &id in &ids {
+        let v = controller.get_limit_violations(id);
+        for (kind, count) in [
+            ("non_finite", v.non_finite),
+            ("position", v.position),
+            ("velocity", v.velocity),
+            ("torque", v.torque),
+            ("step", v.step),
+        ] {
+            let _ = writeln!(
+                out,
+                "poulpe_limit_violations_total{{{},kind=\"{}\"}} {}",
+                labels(id),
+                kind,
+                count
+            );
+        }
+    }
+
+    // commands received by the grpc server
+    let counters = commands.get();
+    for (name, help, value) in [
+        (
+            "poulpe_commands_received_total",
+            "Commands received by the grpc server",
+            (|c: &CommandCounters| c.received) as fn(&CommandCounters) -> u64,
+        ),
+        (
+            "poulpe_commands_dropped_total",
+            "Commands dropped (slave not ready or in fault state)",
+            |c: &CommandCounters| c.dropped,
+        ),
+        (
+            "poulpe_commands_too_old_total",
+            "Commands dropped because older than the command drop time",
+            |c: &CommandCounters| c.too_old,
+        ),
+    ] {
+        header(&mut out, name, "counter", help);
+        for &id in &ids {
+            let c = counters.get(&id).cloned().unwrap_or_default();
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels(id), value(&c));
+        }
+    }
+
+    out
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    controller: &PoulpeController,
+    commands: &CommandMetrics,
+) -> std::io::Result<()> {
+    // only the request line is needed
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let response = if request.starts_with("GET ") && (path == "/metrics" || path == "/") {
+        let body = render(controller, commands);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// serve the metrics on the address until the process stops
+pub async fn serve(
+    address: String,
+    controller: Arc<PoulpeController>,
+    commands: Arc<CommandMetrics>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    log::info!("Metrics available at http://{}/metrics", address);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let controller = Arc::clone(&controller);
+        let commands = Arc::clone(&commands);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &controller, &commands).await {
+                log::warn!("Metrics request failed: {}", e);
+            }
+        });
+    }
+}
@@ -1,29 +1,55 @@
 use std::{
+    collections::HashMap,
     env,
     f32::consts::E,
     mem::take,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
     task::Context,
     time::{Duration, SystemTime},
 };
 
-use poulpe_ethercat_controller::{state_machine::CiA402State, PoulpeController};
+use poulpe_ethercat_controller::{
+    pid::{ControlLoop, PidGains},
+    state_machine::CiA402State,
+    thermal::{ThermalEvent, ThermalLevel},
+    trajectory::{
+        ActuatorTrajectory, Interpolation, Trajectory, TrajectoryHandle, TrajectoryState,
+        TrajectoryStatus, Waypoint,
+    },
+    PoulpeController,
+};
 use tokio::{
-    sync::mpsc,
+    sync::{broadcast, mpsc},
     time::{error::Elapsed, sleep},
 };
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tonic::{transport::Server, Request, Response, Status, Streaming};
 
+use poulpe_ethercat_grpc::config::{GrpcConfig, GrpcSafetyConfig};
+use poulpe_ethercat_grpc::metrics::{self, CommandMetrics};
 use poulpe_ethercat_grpc::pb::{
     poulpe_multiplexer_server::{PoulpeMultiplexer, PoulpeMultiplexerServer},
-    PoulpeCommands, PoulpeIds, PoulpeState, PoulpeStates, StateStreamRequest,
+    ControlLoop as PbControlLoop, Interpolation as PbInterpolation, Pid, PidGains as PbPidGains,
+    PidRequest, PoulpeCommands, PoulpeIds, PoulpeState, PoulpeStates, SafetyConfig,
+    StateStreamRequest, ThermalEvent as PbThermalEvent, ThermalLevel as PbThermalLevel,
+    Trajectory as PbTrajectory, TrajectoryCommand, TrajectoryCommandType, TrajectoryId,
+    TrajectoryState as PbTrajectoryState, TrajectoryStatus as PbTrajectoryStatus,
 };
 
 use prost_types::Timestamp;
 #[derive(Debug)]
 struct PoulpeMultiplexerService {
     controller: Arc<PoulpeController>,
+    safety: GrpcSafetyConfig,
+    commands: Arc<CommandMetrics>,
+    // trajectories executed by the controller (the finished ones are removed on the next execution)
+    trajectories: Mutex<HashMap<u32, TrajectoryHandle>>,
+    next_trajectory_id: AtomicU32,
+    // events of the thermal protection, forwarded to the clients
+    thermal_events: broadcast::Sender<PbThermalEvent>,
 }
 
 fn get_state_for_id(
@@ -291,6 +317,7 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
             if self.controller.is_slave_ready(slave_id as u16) == false {
                 log::error!("Slave (id: {}) not ready!", slave_id);
                 nb_dropped += 1;
+                self.commands.dropped(slave_id);
                 continue;
             }
 
@@ -308,8 +335,7 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
                             self.controller.get_error_flags(slave_id as u16).unwrap()
                         );
 
-                        #[cfg(feature = "qucik_stop_on_slave_fault")]
-                        {
+                        if self.safety.quick_stop_on_slave_fault {
                             log::warn!("Sending emergency stop on all slaves!");
                             // quick stop on all slaves
                             self.controller
@@ -327,6 +353,7 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
                         // display the error every 5s
                         nb_errors_max = 5000;
                     }
+                    self.commands.dropped(slave_id);
                     continue;
                 }
                 _ => {} // do nothing
@@ -335,6 +362,7 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
             log::debug!("Got commands {:?}", req);
             for cmd in req.commands {
                 slave_id = cmd.id as u32;
+                self.commands.received(slave_id);
 
                 // emergency stop first
                 match cmd.emergency_stop {
@@ -369,6 +397,7 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
                             .check_if_too_old(published_time.elapsed().unwrap())
                         {
                             nb_dropped += 1;
+                            self.commands.too_old(slave_id);
                             continue;
                         }
                     }
@@ -478,20 +507,30 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
                 }
 
                 // check if the slave is compliant
-                match set_compliant {
-                    Some(true) => self
-                        .controller
-                        .set_torque(slave_id, false)
-                        .unwrap_or_else(|e| {
-                            log::error!("Failed to set torque off for slave {}: {}", slave_id, e)
-                        }),
-                    Some(false) => self
-                        .controller
-                        .set_torque(slave_id, true)
-                        .unwrap_or_else(|e| {
-                            log::error!("Failed to set torque on for slave {}: {}", slave_id, e)
-                        }),
-                    None => (),
+                // (the transition is not waited for in the command loop)
+                if let Some(compliant) = set_compliant {
+                    let on_off = if compliant { "off" } else { "on" };
+                    match self.controller.request_torque(slave_id, !compliant) {
+                        Ok(Some(request)) => {
+                            tokio::task::spawn_blocking(move || {
+                                if let Err(e) = request.wait() {
+                                    log::error!(
+                                        "Failed to set torque {} for slave {}: {}",
+                                        on_off,
+                                        slave_id,
+                                        e
+                                    );
+                                }
+                            });
+                        }
+                        Ok(None) => (),
+                        Err(e) => log::error!(
+                            "Failed to set torque {} for slave {}: {}",
+                            on_off,
+                            slave_id,
+                            e
+                        ),
+                    }
                 }
 
                 // DEBUGGING
@@ -535,6 +574,261 @@ impl PoulpeMultiplexer for PoulpeMultiplexerService {
 
         Ok(Response::new(()))
     }
+
+    async fn get_safety_config(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<SafetyConfig>, Status> {
+        let ethercat = self.controller.inner.get_safety_config();
+        let poulpe = self.controller.get_safety_config();
+        let reply = SafetyConfig {
+            enable_watchdog: ethercat.enable_watchdog,
+            verify_mailbox_pdos: ethercat.verify_mailbox_pdos,
+            stop_operation_on_error: ethercat.stop_operation_on_error,
+            safe_turn_on: poulpe.safe_turn_on,
+            allow_fault_on_slave: poulpe.allow_fault_on_slave,
+            turn_off_slaves_setup: poulpe.turn_off_slaves_setup,
+            quick_stop_on_slave_fault: self.safety.quick_stop_on_slave_fault,
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_pid(&self, request: Request<PidRequest>) -> Result<Response<Pid>, Status> {
+        let request = request.into_inner();
+        let control_loop = to_control_loop(request.control_loop).ok_or_else(|| {
+            Status::invalid_argument(format!("Unknown control loop {}", request.control_loop))
+        })?;
+        let controller = Arc::clone(&self.controller);
+        let id = request.id as u32;
+        // the slave is taken to PREOP for the SDO requests, it can take some time
+        let gains = tokio::task::spawn_blocking(move || {
+            controller
+                .get_pid(id, control_loop)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| {
+            log::error!("Failed to get the PID gains of slave {}: {}", id, e);
+            Status::failed_precondition(e)
+        })?;
+
+        Ok(Response::new(Pid {
+            id: request.id,
+            control_loop: request.control_loop,
+            gains: gains
+                .iter()
+                .enumerate()
+                .map(|(axis, g)| PbPidGains {
+                    axis: axis as u32,
+                    p: g.p,
+                    i: g.i,
+                    d: g.d,
+                })
+                .collect(),
+            persist: false,
+        }))
+    }
+
+    async fn set_pid(&self, request: Request<Pid>) -> Result<Response<()>, Status> {
+        let request = request.into_inner();
+        let control_loop = to_control_loop(request.control_loop).ok_or_else(|| {
+            Status::invalid_argument(format!("Unknown control loop {}", request.control_loop))
+        })?;
+        let controller = Arc::clone(&self.controller);
+        let id = request.id as u32;
+        let gains = request
+            .gains
+            .iter()
+            .map(|g| {
+                (
+                    g.axis as usize,
+                    PidGains {
+                        p: g.p,
+                        i: g.i,
+                        d: g.d,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        // all the gains are verified first and written (and saved) in one PREOP/OP round trip
+        tokio::task::spawn_blocking(move || {
+            controller
+                .set_axes_pid(id, control_loop, &gains, request.persist)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| {
+            log::error!("Failed to set the PID gains of slave {}: {}", id, e);
+            Status::failed_precondition(e)
+        })?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn execute_trajectory(
+        &self,
+        request: Request<PbTrajectory>,
+    ) -> Result<Response<TrajectoryId>, Status> {
+        let request = request.into_inner();
+        let interpolation = match PbInterpolation::try_from(request.interpolation) {
+            Ok(PbInterpolation::Cubic) => Interpolation::Cubic,
+            Ok(PbInterpolation::Quintic) => Interpolation::Quintic,
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown interpolation {}",
+                    request.interpolation
+                )))
+            }
+        };
+        let trajectory = Trajectory {
+            actuators: request
+                .actuators
+                .into_iter()
+                .map(|a| ActuatorTrajectory {
+                    id: a.id as u32,
+                    waypoints: a
+                        .waypoints
+                        .into_iter()
+                        .map(|w| Waypoint {
+                            time: w.time,
+                            positions: w.positions,
+                            velocities: match w.velocities.is_empty() {
+                                true => None,
+                                false => Some(w.velocities),
+                            },
+                        })
+                        .collect(),
+                })
+                .collect(),
+            interpolation,
+        };
+
+        let handle = self
+            .controller
+            .execute_trajectory(&trajectory)
+            .map_err(|e| {
+                log::error!("Failed to execute the trajectory: {}", e);
+                Status::failed_precondition(e.to_string())
+            })?;
+        let id = self.next_trajectory_id.fetch_add(1, Ordering::Relaxed);
+        let mut trajectories = self.trajectories.lock().unwrap();
+        trajectories.retain(|_, t| !t.state().status.is_finished());
+        trajectories.insert(id, handle);
+
+        Ok(Response::new(TrajectoryId { id }))
+    }
+
+    async fn control_trajectory(
+        &self,
+        request: Request<TrajectoryCommand>,
+    ) -> Result<Response<PbTrajectoryState>, Status> {
+        let request = request.into_inner();
+        let trajectories = self.trajectories.lock().unwrap();
+        let trajectory = trajectories
+            .get(&request.id)
+            .ok_or_else(|| Status::not_found(format!("Unknown trajectory {}", request.id)))?;
+
+        match TrajectoryCommandType::try_from(request.command) {
+            Ok(TrajectoryCommandType::GetState) => {}
+            Ok(TrajectoryCommandType::Pause) => trajectory.pause(),
+            Ok(TrajectoryCommandType::Resume) => trajectory.resume(),
+            Ok(TrajectoryCommandType::Abort) => trajectory.abort(),
+            Ok(TrajectoryCommandType::SetTimeScale) => trajectory
+                .set_time_scale(request.time_scale)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown trajectory command {}",
+                    request.command
+                )))
+            }
+        }
+
+        Ok(Response::new(to_pb_trajectory_state(
+            request.id,
+            trajectory.state(),
+        )))
+    }
+
+    type GetThermalEventsStream = ReceiverStream<Result<PbThermalEvent, Status>>;
+
+    async fn get_thermal_events(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<Self::GetThermalEventsStream>, Status> {
+        let (tx, rx) = mpsc::channel(16);
+        let mut events = self.thermal_events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => Ok(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("Thermal events client lagging, {} events dropped", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if tx.send(event).await.is_err() {
+                    // client disconnected
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn to_pb_thermal_level(level: ThermalLevel) -> PbThermalLevel {
+    match level {
+        ThermalLevel::Normal => PbThermalLevel::Normal,
+        ThermalLevel::Warning => PbThermalLevel::Warning,
+        ThermalLevel::Derating => PbThermalLevel::Derating,
+        ThermalLevel::Compliant => PbThermalLevel::Compliant,
+    }
+}
+
+fn to_pb_thermal_event(event: ThermalEvent) -> PbThermalEvent {
+    PbThermalEvent {
+        id: event.id as i32,
+        name: event.name,
+        level: to_pb_thermal_level(event.state.level) as i32,
+        previous_level: to_pb_thermal_level(event.previous_level) as i32,
+        motor_temperatures: event.state.motor_temperatures,
+        board_temperatures: event.state.board_temperatures,
+        torque_ratio: event.state.torque_ratio,
+        made_compliant: event.made_compliant,
+        timestamp: Some(Timestamp::from(event.time)),
+    }
+}
+
+fn to_pb_trajectory_state(id: u32, state: TrajectoryState) -> PbTrajectoryState {
+    let (status, error) = match state.status {
+        TrajectoryStatus::Running => (PbTrajectoryStatus::Running, String::new()),
+        TrajectoryStatus::Paused => (PbTrajectoryStatus::Paused, String::new()),
+        TrajectoryStatus::Completed => (PbTrajectoryStatus::Completed, String::new()),
+        TrajectoryStatus::Aborted => (PbTrajectoryStatus::Aborted, String::new()),
+        TrajectoryStatus::Failed(e) => (PbTrajectoryStatus::Failed, e),
+    };
+    PbTrajectoryState {
+        id,
+        status: status as i32,
+        time: state.time,
+        duration: state.duration,
+        time_scale: state.time_scale,
+        error,
+    }
+}
+
+fn to_control_loop(control_loop: i32) -> Option<ControlLoop> {
+    match PbControlLoop::try_from(control_loop).ok()? {
+        PbControlLoop::Position => Some(ControlLoop::Position),
+        PbControlLoop::Velocity => Some(ControlLoop::Velocity),
+        PbControlLoop::Current => Some(ControlLoop::Current),
+    }
 }
 
 #[tokio::main]
@@ -551,6 +845,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let controller = PoulpeController::connect(filename)?;
+    let config = GrpcConfig::from_yaml(filename)?;
+    log::info!("GRPC server safety configuration: {:?}", config.safety);
 
     // for slave_id in controller.get_slave_ids() {
     //     log::info!("Setup Slave {}...", slave_id);
@@ -566,8 +862,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("POULPE controller ready!");
 
     let addr = "[::]:50098".parse()?;
+    let controller = Arc::new(controller);
+    let commands = Arc::new(CommandMetrics::default());
+
+    // serve the metrics locally (optional)
+    if let Some(address) = config.metrics_address {
+        let controller = Arc::clone(&controller);
+        let commands = Arc::clone(&commands);
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(address, controller, commands).await {
+                log::error!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    // thermal protection of the actuators (if configured)
+    let (thermal_events, _) = broadcast::channel(64);
+    let events = controller.start_thermal_protection()?;
+    let sender = thermal_events.clone();
+    std::thread::spawn(move || {
+        for event in events {
+            // no client listening
+            _ = sender.send(to_pb_thermal_event(event));
+        }
+    });
+
     let srv = PoulpeMultiplexerService {
-        controller: Arc::new(controller),
+        controller,
+        safety: config.safety,
+        commands,
+        trajectories: Mutex::new(HashMap::new()),
+        next_trajectory_id: AtomicU32::new(0),
+        thermal_events,
     };
 
     Server::builder()
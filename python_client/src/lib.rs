@@ -11,6 +11,9 @@ use tokio::{
 use tonic::{transport::Uri, Request};
 
 use poulpe_ethercat_grpc::client::PoulpeRemoteClient;
+use poulpe_ethercat_grpc::pb::{
+    ActuatorTrajectory, Interpolation, Trajectory, TrajectoryCommandType, TrajectoryState, Waypoint,
+};
 
 #[pyclass]
 pub struct PyPoulpeRemoteClient {
@@ -159,9 +162,85 @@ impl PyPoulpeRemoteClient {
         self.client.emergency_stop(slave_id);
     }
 
+    // execute a waypoint trajectory on the server (interpolated at the cycle rate)
+    // positions: positions of the axes at each time (s), returns the id of the trajectory
+    pub fn execute_trajectory(
+        &mut self,
+        slave_id: u16,
+        times: Vec<f32>,
+        positions: Vec<Vec<f32>>,
+        quintic: bool,
+    ) -> u32 {
+        let trajectory = Trajectory {
+            actuators: vec![ActuatorTrajectory {
+                id: slave_id as i32,
+                waypoints: times
+                    .into_iter()
+                    .zip(positions)
+                    .map(|(time, positions)| Waypoint {
+                        time,
+                        positions,
+                        velocities: vec![],
+                    })
+                    .collect(),
+            }],
+            interpolation: match quintic {
+                true => Interpolation::Quintic as i32,
+                false => Interpolation::Cubic as i32,
+            },
+        };
+        match self.client.execute_trajectory_sync(trajectory) {
+            Ok(id) => id,
+            Err(e) => panic!("Error in executing the trajectory: {}", e),
+        }
+    }
+
+    pub fn pause_trajectory(&mut self, trajectory_id: u32) {
+        self.control_trajectory(trajectory_id, TrajectoryCommandType::Pause, 0.0);
+    }
+
+    pub fn resume_trajectory(&mut self, trajectory_id: u32) {
+        self.control_trajectory(trajectory_id, TrajectoryCommandType::Resume, 0.0);
+    }
+
+    pub fn abort_trajectory(&mut self, trajectory_id: u32) {
+        self.control_trajectory(trajectory_id, TrajectoryCommandType::Abort, 0.0);
+    }
+
+    pub fn set_trajectory_time_scale(&mut self, trajectory_id: u32, time_scale: f32) {
+        self.control_trajectory(
+            trajectory_id,
+            TrajectoryCommandType::SetTimeScale,
+            time_scale,
+        );
+    }
+
+    // status (0: running, 1: paused, 2: completed, 3: aborted, 4: failed), time and duration
+    pub fn get_trajectory_state(&mut self, trajectory_id: u32) -> (i32, f32, f32) {
+        let state = self.control_trajectory(trajectory_id, TrajectoryCommandType::GetState, 0.0);
+        (state.status, state.time, state.duration)
+    }
+
     // Define other methods similarly...
 }
 
+impl PyPoulpeRemoteClient {
+    fn control_trajectory(
+        &mut self,
+        trajectory_id: u32,
+        command: TrajectoryCommandType,
+        time_scale: f32,
+    ) -> TrajectoryState {
+        match self
+            .client
+            .control_trajectory_sync(trajectory_id, command, time_scale)
+        {
+            Ok(state) => state,
+            Err(e) => panic!("Error in controlling the trajectory: {}", e),
+        }
+    }
+}
+
 #[pymodule]
 fn python_client(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyPoulpeRemoteClient>()?;